@@ -0,0 +1,476 @@
+//! Bringing up the acquisition of a site's points as a whole: each point is
+//! subscribed to with COV where its device supports it and the site
+//! configuration allows it, and polled otherwise.
+//!
+//! A [`SubscriptionManager`] runs one task per device, which receives the
+//! COV notifications of the device and reads its polled points. Applying a
+//! point list either establishes every point or none: a failure cancels
+//! what was established so far.
+//!
+//! Each device task owns a client, made by the manager's
+//! [`ClientFactory`]. The default factory, [`Client::new`], binds a UDP
+//! socket per device, so a site of a thousand devices holds a thousand
+//! sockets and file descriptors while acquiring. Handles of a
+//! [`crate::ClientPool`] share one socket, but don't receive COV
+//! notifications, so only suit sites acquired by polling.
+
+use std::{collections::BTreeMap, future::Future, net::SocketAddr, sync::Arc};
+
+use futures_util::future::BoxFuture;
+use log::{debug, info, warn};
+use tokio::{
+    sync::oneshot,
+    task::JoinHandle,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    client::Client,
+    error::Error,
+    poll::{jitter_seed, PollOptions},
+    services::Service,
+    subscription::SubscriptionRegistry,
+    value::Value,
+    ObjectId, PropertyId,
+};
+
+/// How a point should be acquired.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AcquisitionHint {
+    /// COV when the device lists SubscribeCOV in its services supported and
+    /// accepts the subscription, polling otherwise.
+    #[default]
+    Auto,
+    /// COV only: a device refusing the subscription fails the whole plan.
+    Cov,
+    /// Polling only, e.g. for values changing constantly.
+    Poll,
+}
+
+/// A point to acquire, see [`SubscriptionManager::apply`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointConfig {
+    pub peer: SocketAddr,
+    pub object_id: ObjectId,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hint: AcquisitionHint,
+}
+
+/// The points established by [`SubscriptionManager::apply`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AcquisitionSummary {
+    /// Points subscribed to with COV, with their process identifier.
+    pub subscribed: Vec<(SocketAddr, ObjectId, u32)>,
+    pub polled: Vec<(SocketAddr, ObjectId)>,
+}
+
+/// Callback invoked with each present value acquired, whether notified or
+/// polled.
+pub type ValueCallback = Arc<dyn Fn(SocketAddr, ObjectId, &Value) + Send + Sync>;
+
+/// Makes the client of a device, see [`SubscriptionManager::set_client_factory`].
+pub type ClientFactory =
+    Arc<dyn Fn(SocketAddr) -> BoxFuture<'static, Result<Client, Error>> + Send + Sync>;
+
+/// The acquisition of a device: the task receiving and polling its points,
+/// which hands its client back when stopped.
+struct DeviceAcquisition {
+    peer: SocketAddr,
+    process_ids: Vec<u32>,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Client>,
+}
+
+/// Acquisition of the present values of a site's points, by COV or polling.
+pub struct SubscriptionManager {
+    callback: ValueCallback,
+    factory: ClientFactory,
+    registry: SubscriptionRegistry,
+    devices: Vec<DeviceAcquisition>,
+}
+
+impl std::fmt::Debug for SubscriptionManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubscriptionManager")
+            .field("registry", &self.registry)
+            .field("devices", &self.devices.len())
+            .finish()
+    }
+}
+
+impl SubscriptionManager {
+    /// Create a manager passing the acquired values to `callback`, with
+    /// subscriptions under a default [`SubscriptionRegistry`].
+    pub fn new(callback: impl Fn(SocketAddr, ObjectId, &Value) + Send + Sync + 'static) -> Self {
+        Self::with_registry(SubscriptionRegistry::new(), callback)
+    }
+
+    /// Create a manager making its subscriptions under `registry`, e.g. to
+    /// use the process identifier prefix of the application.
+    pub fn with_registry(
+        registry: SubscriptionRegistry,
+        callback: impl Fn(SocketAddr, ObjectId, &Value) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            callback: Arc::new(callback),
+            factory: Arc::new(|peer| Box::pin(Client::new(peer))),
+            registry,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Make the clients of the devices with `factory` from the next
+    /// [`SubscriptionManager::apply`] on, e.g. to set their timeout or bind
+    /// them to a given local address. Each device's client is made once and
+    /// kept until the plan stops.
+    pub fn set_client_factory<F>(
+        &mut self,
+        factory: impl Fn(SocketAddr) -> F + Send + Sync + 'static,
+    ) where
+        F: Future<Output = Result<Client, Error>> + Send + 'static,
+    {
+        self.factory = Arc::new(move |peer| Box::pin(factory(peer)));
+    }
+
+    /// Replace the current acquisition plan with `points`, polled with
+    /// `poll` where they aren't subscribed to: polled values are passed on
+    /// when they change, as with [`Client::poll_with`].
+    ///
+    /// The current plan is stopped first. Then every point is either
+    /// subscribed to or polled, as its hint and its device's services
+    /// supported allow. If any device fails to come up, the subscriptions
+    /// already made are cancelled, nothing is acquired and the error is
+    /// returned.
+    pub async fn apply(
+        &mut self,
+        points: &[PointConfig],
+        poll: PollOptions,
+    ) -> Result<AcquisitionSummary, Error> {
+        self.stop().await?;
+
+        let mut devices: BTreeMap<SocketAddr, Vec<&PointConfig>> = BTreeMap::new();
+        for point in points {
+            devices.entry(point.peer).or_default().push(point);
+        }
+        let mut summary = AcquisitionSummary::default();
+        for (peer, points) in devices {
+            match self.start_device(peer, &points, poll, &mut summary).await {
+                Ok(device) => self.devices.push(device),
+                Err(err) => {
+                    warn!("Failed to start acquisition from {peer}: {err:?}");
+                    if let Err(err) = self.stop().await {
+                        warn!("Failed to cancel the subscriptions made: {err:?}");
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        info!(
+            "Acquiring {} points by COV and {} by polling",
+            summary.subscribed.len(),
+            summary.polled.len()
+        );
+        Ok(summary)
+    }
+
+    /// Stop acquiring and cancel every subscription of the current plan.
+    /// Returns the first cancellation that failed, after trying them all.
+    pub async fn stop(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for device in self.devices.drain(..) {
+            let _ = device.stop.send(());
+            let mut client = match device.task.await {
+                Ok(client) => client,
+                Err(err) => {
+                    warn!("Acquisition task of {} failed: {err}", device.peer);
+                    continue;
+                }
+            };
+            for process_id in device.process_ids {
+                if let Err(err) = client.unsubscribe_cov(process_id).await {
+                    warn!(
+                        "Failed to unsubscribe {process_id} from {}: {err:?}",
+                        device.peer
+                    );
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    async fn start_device(
+        &self,
+        peer: SocketAddr,
+        points: &[&PointConfig],
+        poll: PollOptions,
+        summary: &mut AcquisitionSummary,
+    ) -> Result<DeviceAcquisition, Error> {
+        let mut client = (self.factory)(peer).await?;
+        client.set_subscriptions(self.registry.clone());
+        let auto = points
+            .iter()
+            .any(|point| point.hint == AcquisitionHint::Auto);
+        let cov_supported = auto
+            && match client.services_supported().await {
+                Ok(services) => services.supports(Service::SubscribeCov),
+                Err(err) => {
+                    debug!("Polling {peer}, its services supported failed to read: {err:?}");
+                    false
+                }
+            };
+
+        let mut subscribed = Vec::new();
+        let mut polled = Vec::new();
+        for point in points {
+            let subscribe = match point.hint {
+                AcquisitionHint::Auto => cov_supported,
+                AcquisitionHint::Cov => true,
+                AcquisitionHint::Poll => false,
+            };
+            if !subscribe {
+                polled.push(point.object_id);
+                continue;
+            }
+            let callback = self.callback.clone();
+            let result = client
+                .subscribe_cov(point.object_id, false, None, move |notification| {
+                    let present_value = notification
+                        .values
+                        .iter()
+                        .find(|value| value.property_id == PropertyId::PropPresentValue as u32);
+                    if let Some(present_value) = present_value {
+                        callback(peer, notification.object_id, &present_value.value);
+                    }
+                })
+                .await;
+            match result {
+                Ok(process_id) => subscribed.push((point.object_id, process_id)),
+                Err(err) if point.hint == AcquisitionHint::Auto && !err.is_transport() => {
+                    debug!(
+                        "Polling {:?}, its subscription failed: {err:?}",
+                        point.object_id
+                    );
+                    polled.push(point.object_id);
+                }
+                Err(err) => {
+                    for (_, process_id) in subscribed {
+                        if let Err(err) = client.unsubscribe_cov(process_id).await {
+                            warn!("Failed to unsubscribe {process_id} from {peer}: {err:?}");
+                        }
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        summary.subscribed.extend(
+            subscribed
+                .iter()
+                .map(|(object_id, process_id)| (peer, *object_id, *process_id)),
+        );
+        summary
+            .polled
+            .extend(polled.iter().map(|object_id| (peer, *object_id)));
+
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(acquire(
+            client,
+            peer,
+            polled,
+            poll,
+            self.callback.clone(),
+            stopped,
+        ));
+        Ok(DeviceAcquisition {
+            peer,
+            process_ids: subscribed
+                .into_iter()
+                .map(|(_, process_id)| process_id)
+                .collect(),
+            stop,
+            task,
+        })
+    }
+}
+
+/// Receive the COV notifications of a device and read its `polled` points
+/// as `poll` sets until stopped, then hand the client back.
+async fn acquire(
+    mut client: Client,
+    peer: SocketAddr,
+    polled: Vec<ObjectId>,
+    poll: PollOptions,
+    callback: ValueCallback,
+    mut stopped: oneshot::Receiver<()>,
+) -> Client {
+    let mut seed = polled
+        .first()
+        .map_or(1, |object_id| jitter_seed(*object_id));
+    let mut last: Vec<Option<Value>> = vec![None; polled.len()];
+    let mut next_poll = Instant::now();
+    loop {
+        if !polled.is_empty() && Instant::now() >= next_poll {
+            let delay = poll.next_delay(&mut seed);
+            next_poll += delay;
+            let requests = polled
+                .iter()
+                .map(|object_id| (*object_id, PropertyId::PropPresentValue))
+                .collect();
+            match client.read_many(requests).await {
+                Ok(results) => {
+                    for ((object_id, last), result) in polled.iter().zip(&mut last).zip(results) {
+                        match result {
+                            Ok(value) if poll.changed(last.as_ref(), &value) => {
+                                callback(peer, *object_id, &value);
+                                *last = Some(value);
+                            }
+                            Ok(_) => {}
+                            Err(err) => debug!("Failed to poll {object_id:?}: {err:?}"),
+                        }
+                    }
+                }
+                Err(err) => warn!("Failed to poll {peer}: {err:?}"),
+            }
+            // Reads overrunning the interval are followed by a full one, not
+            // by back-to-back reads catching up
+            let now = Instant::now();
+            if next_poll < now {
+                next_poll = now + delay;
+            }
+        }
+        let until = match polled.is_empty() {
+            true => Instant::now() + poll.interval,
+            false => next_poll,
+        };
+        let wait = until.saturating_duration_since(Instant::now());
+        let received = tokio::select! {
+            _ = &mut stopped => None,
+            received = client.receive_notifications(wait) => Some(received),
+        };
+        match received {
+            None => return client,
+            Some(Ok(())) => {}
+            Some(Err(err)) => {
+                warn!("Failed to receive notifications from {peer}: {err:?}");
+                tokio::select! {
+                    _ = &mut stopped => return client,
+                    _ = sleep_until(until) => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        apdu,
+        testing::{UdpDevice, VirtualDevice},
+        ObjectType,
+    };
+
+    // unrecognized-service
+    const REJECT_REASON: u8 = 9;
+
+    fn analog_value() -> ObjectId {
+        ObjectId::new(ObjectType::ObjectAnalogValue, 1)
+    }
+
+    /// A device serving an analog value, and listing SubscribeCOV and
+    /// ReadPropertyMultiple in its services supported.
+    async fn device(instance: u32) -> UdpDevice {
+        let mut device = VirtualDevice::new(instance);
+        device.add_object(
+            analog_value(),
+            [(PropertyId::PropPresentValue, Value::Real(21.5))],
+        );
+        let mut services = vec![false; 40];
+        services[Service::SubscribeCov as usize] = true;
+        services[Service::ReadPropertyMultiple as usize] = true;
+        let device_id = device.device_id();
+        device.set_property(
+            device_id,
+            PropertyId::PropProtocolServicesSupported,
+            Value::BitString(services),
+        );
+        UdpDevice::bind(device, "127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn point(device: &UdpDevice, hint: AcquisitionHint) -> PointConfig {
+        PointConfig {
+            peer: device.local_addr(),
+            object_id: analog_value(),
+            hint,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_cov_point_cancels_earlier_devices() {
+        let (a, b) = (device(1).await, device(2).await);
+        // Devices are brought up in address order
+        let (first, second) = match a.local_addr() < b.local_addr() {
+            true => (a, b),
+            false => (b, a),
+        };
+        second
+            .device()
+            .reject_service(apdu::confirmed::SUBSCRIBE_COV, REJECT_REASON);
+
+        let mut manager = SubscriptionManager::new(|_, _, _| {});
+        let points = [
+            point(&first, AcquisitionHint::Cov),
+            point(&second, AcquisitionHint::Cov),
+        ];
+        let result = manager
+            .apply(&points, PollOptions::new(Duration::from_secs(60)))
+            .await;
+        assert!(
+            matches!(result, Err(Error::Reject(REJECT_REASON))),
+            "{result:?}"
+        );
+
+        let first = first.device();
+        // Subscribed, then cancelled
+        assert_eq!(first.requests(), 2);
+        assert!(first.subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refused_auto_point_is_polled() {
+        let device = device(1).await;
+        device
+            .device()
+            .reject_service(apdu::confirmed::SUBSCRIBE_COV, REJECT_REASON);
+
+        let (sender, mut values) = mpsc::unbounded_channel();
+        let mut manager = SubscriptionManager::new(move |peer, object_id, value: &Value| {
+            let _ = sender.send((peer, object_id, value.clone()));
+        });
+        let summary = manager
+            .apply(
+                &[point(&device, AcquisitionHint::Auto)],
+                PollOptions::new(Duration::from_secs(60)),
+            )
+            .await
+            .unwrap();
+        assert!(summary.subscribed.is_empty());
+        assert_eq!(summary.polled, [(device.local_addr(), analog_value())]);
+        assert_eq!(
+            values.recv().await,
+            Some((device.local_addr(), analog_value(), Value::Real(21.5)))
+        );
+        manager.stop().await.unwrap();
+    }
+}
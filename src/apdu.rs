@@ -0,0 +1,1121 @@
+//! Minimal BVLL/NPDU/APDU codec for services and encodings that
+//! embedded-bacnet doesn't model.
+//!
+//! Frames produced here are plain BACnet/IP datagrams, so they go through the
+//! same `NetworkIo` as the requests built by `embedded_bacnet::simple::Bacnet`.
+
+use crate::{
+    error::{DecodeError, ServiceError},
+    ObjectId, ObjectType,
+};
+
+/// Largest APDU accepted by this client (max-APDU code 5).
+pub(crate) const MAX_APDU: usize = 1476;
+
+const BVLL_TYPE: u8 = 0x81;
+const BVLL_FORWARDED_NPDU: u8 = 0x04;
+const BVLL_ORIGINAL_UNICAST: u8 = 0x0a;
+const BVLL_ORIGINAL_BROADCAST: u8 = 0x0b;
+
+const PDU_CONFIRMED_REQUEST: u8 = 0x0;
+const PDU_UNCONFIRMED_REQUEST: u8 = 0x1;
+const PDU_SIMPLE_ACK: u8 = 0x2;
+const PDU_COMPLEX_ACK: u8 = 0x3;
+const PDU_SEGMENT_ACK: u8 = 0x4;
+const PDU_ERROR: u8 = 0x5;
+const PDU_REJECT: u8 = 0x6;
+const PDU_ABORT: u8 = 0x7;
+
+/// BVLL functions for BBMD management.
+pub(crate) mod bvll {
+    pub const RESULT: u8 = 0x00;
+    pub const WRITE_BDT: u8 = 0x01;
+    pub const READ_BDT: u8 = 0x02;
+    pub const READ_BDT_ACK: u8 = 0x03;
+    pub const READ_FDT: u8 = 0x06;
+    pub const READ_FDT_ACK: u8 = 0x07;
+}
+
+/// Confirmed service choices.
+pub(crate) mod confirmed {
+    pub const ACKNOWLEDGE_ALARM: u8 = 0;
+    pub const COV_NOTIFICATION: u8 = 1;
+    pub const SUBSCRIBE_COV: u8 = 5;
+    pub const ATOMIC_READ_FILE: u8 = 6;
+    pub const ATOMIC_WRITE_FILE: u8 = 7;
+    pub const ADD_LIST_ELEMENT: u8 = 8;
+    pub const REMOVE_LIST_ELEMENT: u8 = 9;
+    pub const READ_PROPERTY: u8 = 12;
+    pub const READ_PROPERTY_MULTIPLE: u8 = 14;
+    pub const WRITE_PROPERTY: u8 = 15;
+    pub const WRITE_PROPERTY_MULTIPLE: u8 = 16;
+    pub const PRIVATE_TRANSFER: u8 = 18;
+    pub const REINITIALIZE_DEVICE: u8 = 20;
+    pub const READ_RANGE: u8 = 26;
+    pub const GET_EVENT_INFORMATION: u8 = 29;
+}
+
+/// Unconfirmed service choices.
+pub(crate) mod unconfirmed {
+    pub const I_AM: u8 = 0;
+    pub const COV_NOTIFICATION: u8 = 2;
+    pub const EVENT_NOTIFICATION: u8 = 3;
+    pub const TIME_SYNCHRONIZATION: u8 = 6;
+    pub const WHO_IS: u8 = 8;
+    pub const UTC_TIME_SYNCHRONIZATION: u8 = 9;
+}
+
+/// Application tag numbers.
+pub(crate) mod tag {
+    pub const NULL: u8 = 0;
+    pub const BOOLEAN: u8 = 1;
+    pub const UNSIGNED: u8 = 2;
+    pub const SIGNED: u8 = 3;
+    pub const REAL: u8 = 4;
+    pub const DOUBLE: u8 = 5;
+    pub const OCTET_STRING: u8 = 6;
+    pub const CHARACTER_STRING: u8 = 7;
+    pub const BIT_STRING: u8 = 8;
+    pub const ENUMERATED: u8 = 9;
+    pub const DATE: u8 = 10;
+    pub const TIME: u8 = 11;
+    pub const OBJECT_ID: u8 = 12;
+}
+
+/// Encoder for BACnet tagged data.
+#[derive(Debug, Default)]
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn raw(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn tag(&mut self, number: u8, context: bool, len: usize) {
+        let class = if context { 0x08 } else { 0x00 };
+        let len_bits = if len <= 4 { len as u8 } else { 5 };
+        self.tag_header(number, class | len_bits);
+        if len > 4 {
+            if len <= 253 {
+                self.buf.push(len as u8);
+            } else if len <= u16::MAX as usize {
+                self.buf.push(254);
+                self.buf.extend_from_slice(&(len as u16).to_be_bytes());
+            } else {
+                self.buf.push(255);
+                self.buf.extend_from_slice(&(len as u32).to_be_bytes());
+            }
+        }
+    }
+
+    fn tag_header(&mut self, number: u8, low_bits: u8) {
+        if number <= 14 {
+            self.buf.push((number << 4) | low_bits);
+        } else {
+            self.buf.push(0xf0 | low_bits);
+            self.buf.push(number);
+        }
+    }
+
+    pub fn opening(&mut self, number: u8) {
+        self.tag_header(number, 0x0e);
+    }
+
+    pub fn closing(&mut self, number: u8) {
+        self.tag_header(number, 0x0f);
+    }
+
+    fn data(&mut self, number: Option<u8>, application: u8, bytes: &[u8]) {
+        match number {
+            Some(number) => self.tag(number, true, bytes.len()),
+            None => self.tag(application, false, bytes.len()),
+        }
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn null(&mut self) {
+        self.tag(tag::NULL, false, 0);
+    }
+
+    pub fn boolean(&mut self, value: bool) {
+        self.tag(tag::BOOLEAN, false, value as usize);
+    }
+
+    pub fn context_boolean(&mut self, number: u8, value: bool) {
+        self.data(Some(number), tag::BOOLEAN, &[value as u8]);
+    }
+
+    pub fn unsigned(&mut self, value: u64) {
+        self.data(None, tag::UNSIGNED, &unsigned_bytes(value));
+    }
+
+    pub fn context_unsigned(&mut self, number: u8, value: u64) {
+        self.data(Some(number), tag::UNSIGNED, &unsigned_bytes(value));
+    }
+
+    pub fn signed(&mut self, value: i64) {
+        self.data(None, tag::SIGNED, &signed_bytes(value));
+    }
+
+    pub fn real(&mut self, value: f32) {
+        self.data(None, tag::REAL, &value.to_be_bytes());
+    }
+
+    pub fn double(&mut self, value: f64) {
+        self.data(None, tag::DOUBLE, &value.to_be_bytes());
+    }
+
+    pub fn octet_string(&mut self, value: &[u8]) {
+        self.data(None, tag::OCTET_STRING, value);
+    }
+
+    /// Encode a UTF-8 character string.
+    pub fn character_string(&mut self, value: &str) {
+        self.context_or_app_character_string(None, value);
+    }
+
+    pub fn context_character_string(&mut self, number: u8, value: &str) {
+        self.context_or_app_character_string(Some(number), value);
+    }
+
+    fn context_or_app_character_string(&mut self, number: Option<u8>, value: &str) {
+        let mut bytes = Vec::with_capacity(value.len() + 1);
+        bytes.push(0);
+        bytes.extend_from_slice(value.as_bytes());
+        self.data(number, tag::CHARACTER_STRING, &bytes);
+    }
+
+    pub fn bit_string(&mut self, bits: &[bool]) {
+        self.data(None, tag::BIT_STRING, &bit_string_bytes(bits));
+    }
+
+    pub fn enumerated(&mut self, value: u32) {
+        self.data(None, tag::ENUMERATED, &unsigned_bytes(value as u64));
+    }
+
+    pub fn context_enumerated(&mut self, number: u8, value: u32) {
+        self.data(Some(number), tag::ENUMERATED, &unsigned_bytes(value as u64));
+    }
+
+    /// Encode a date as `[year - 1900, month, day, weekday]`; 255 is "any".
+    pub fn date(&mut self, date: [u8; 4]) {
+        self.data(None, tag::DATE, &date);
+    }
+
+    /// Encode a time as `[hour, minute, second, hundredths]`; 255 is "any".
+    pub fn time(&mut self, time: [u8; 4]) {
+        self.data(None, tag::TIME, &time);
+    }
+
+    pub fn context_time(&mut self, number: u8, time: [u8; 4]) {
+        self.data(Some(number), tag::TIME, &time);
+    }
+
+    pub fn object_id(&mut self, object_id: ObjectId) {
+        self.data(None, tag::OBJECT_ID, &object_id_bytes(object_id));
+    }
+
+    pub fn context_object_id(&mut self, number: u8, object_id: ObjectId) {
+        self.data(Some(number), tag::OBJECT_ID, &object_id_bytes(object_id));
+    }
+}
+
+fn unsigned_bytes(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take(7).take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+fn signed_bytes(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut skip = 0;
+    while skip < 7 {
+        let redundant = (bytes[skip] == 0x00 && bytes[skip + 1] & 0x80 == 0)
+            || (bytes[skip] == 0xff && bytes[skip + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        skip += 1;
+    }
+    bytes[skip..].to_vec()
+}
+
+fn bit_string_bytes(bits: &[bool]) -> Vec<u8> {
+    let unused = (8 - bits.len() % 8) % 8;
+    let mut bytes = vec![unused as u8];
+    for chunk in bits.chunks(8) {
+        let mut byte = 0u8;
+        for (i, bit) in chunk.iter().enumerate() {
+            if *bit {
+                byte |= 0x80 >> i;
+            }
+        }
+        bytes.push(byte);
+    }
+    bytes
+}
+
+/// The 32-bit encoded form of an object identifier.
+pub(crate) fn object_id_value(object_id: ObjectId) -> u32 {
+    ((object_id.object_type as u32) << 22) | (object_id.id & 0x3f_ffff)
+}
+
+fn object_id_bytes(object_id: ObjectId) -> [u8; 4] {
+    object_id_value(object_id).to_be_bytes()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TagClass {
+    Application,
+    Context,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TagKind {
+    /// A primitive value; the length of its content (or the value of an
+    /// application boolean).
+    Value(u32),
+    Opening,
+    Closing,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Tag {
+    pub number: u8,
+    pub class: TagClass,
+    pub kind: TagKind,
+}
+
+impl Tag {
+    pub fn is_application(&self, number: u8) -> bool {
+        self.class == TagClass::Application && self.number == number
+    }
+
+    pub fn is_context(&self, number: u8) -> bool {
+        self.class == TagClass::Context
+            && self.number == number
+            && matches!(self.kind, TagKind::Value(_))
+    }
+
+    pub fn is_opening(&self, number: u8) -> bool {
+        self.kind == TagKind::Opening && self.number == number
+    }
+
+    pub fn is_closing(&self, number: u8) -> bool {
+        self.kind == TagKind::Closing && self.number == number
+    }
+
+    /// Length of the content following this tag.
+    pub fn len(&self) -> usize {
+        match (self.class, self.kind) {
+            // An application boolean carries its value in the tag itself.
+            (TagClass::Application, TagKind::Value(_)) if self.number == tag::BOOLEAN => 0,
+            (_, TagKind::Value(len)) => len as usize,
+            _ => 0,
+        }
+    }
+}
+
+/// Decoder for BACnet tagged data.
+#[derive(Debug, Clone)]
+pub(crate) struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.pos.min(self.buf.len())..]
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self
+            .buf
+            .get(self.pos)
+            .ok_or(DecodeError("unexpected end of data"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or(DecodeError("unexpected end of data"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn peek_tag(&self) -> Result<Tag, DecodeError> {
+        self.clone().read_tag()
+    }
+
+    pub fn read_tag(&mut self) -> Result<Tag, DecodeError> {
+        let first = self.byte()?;
+        let mut number = first >> 4;
+        if number == 0x0f {
+            number = self.byte()?;
+        }
+        let class = if first & 0x08 != 0 {
+            TagClass::Context
+        } else {
+            TagClass::Application
+        };
+        let low = first & 0x07;
+        let kind = match (class, low) {
+            (TagClass::Context, 6) => TagKind::Opening,
+            (TagClass::Context, 7) => TagKind::Closing,
+            (_, 5) => {
+                let len = match self.byte()? {
+                    254 => u16::from_be_bytes([self.byte()?, self.byte()?]) as u32,
+                    255 => {
+                        u32::from_be_bytes([self.byte()?, self.byte()?, self.byte()?, self.byte()?])
+                    }
+                    len => len as u32,
+                };
+                TagKind::Value(len)
+            }
+            (_, len) => TagKind::Value(len as u32),
+        };
+        Ok(Tag {
+            number,
+            class,
+            kind,
+        })
+    }
+
+    /// Read the next tag if it is a context tag with the given number.
+    pub fn context(&mut self, number: u8) -> Result<Option<&'a [u8]>, DecodeError> {
+        if self.is_empty() || !self.peek_tag()?.is_context(number) {
+            return Ok(None);
+        }
+        let tag = self.read_tag()?;
+        self.bytes(tag.len()).map(Some)
+    }
+
+    pub fn expect_context(&mut self, number: u8) -> Result<&'a [u8], DecodeError> {
+        self.context(number)?
+            .ok_or(DecodeError("missing context tag"))
+    }
+
+    /// Consume an opening tag with the given number if it is next.
+    pub fn opening(&mut self, number: u8) -> Result<bool, DecodeError> {
+        if self.is_empty() || !self.peek_tag()?.is_opening(number) {
+            return Ok(false);
+        }
+        self.read_tag()?;
+        Ok(true)
+    }
+
+    pub fn expect_opening(&mut self, number: u8) -> Result<(), DecodeError> {
+        match self.opening(number)? {
+            true => Ok(()),
+            false => Err(DecodeError("missing opening tag")),
+        }
+    }
+
+    pub fn is_closing(&self, number: u8) -> bool {
+        self.peek_tag().is_ok_and(|tag| tag.is_closing(number))
+    }
+
+    pub fn expect_closing(&mut self, number: u8) -> Result<(), DecodeError> {
+        match self.read_tag()? {
+            tag if tag.is_closing(number) => Ok(()),
+            _ => Err(DecodeError("missing closing tag")),
+        }
+    }
+
+    /// Return the encoded bytes enclosed by the opening/closing tag pair
+    /// `number`, consuming both tags.
+    pub fn enclosed(&mut self, number: u8) -> Result<&'a [u8], DecodeError> {
+        self.expect_opening(number)?;
+        let start = self.pos;
+        while !self.is_closing(number) {
+            self.skip()?;
+        }
+        let end = self.pos;
+        self.expect_closing(number)?;
+        Ok(&self.buf[start..end])
+    }
+
+    /// Skip one element, including constructed ones.
+    pub fn skip(&mut self) -> Result<(), DecodeError> {
+        let tag = self.read_tag()?;
+        match tag.kind {
+            TagKind::Opening => {
+                while !self.is_closing(tag.number) {
+                    self.skip()?;
+                }
+                self.read_tag()?;
+            }
+            TagKind::Closing => return Err(DecodeError("unbalanced closing tag")),
+            TagKind::Value(_) => {
+                self.bytes(tag.len())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an application tag with the given number and return its content.
+    pub fn application(&mut self, number: u8) -> Result<(Tag, &'a [u8]), DecodeError> {
+        let tag = self.read_tag()?;
+        if !tag.is_application(number) {
+            return Err(DecodeError("unexpected application tag"));
+        }
+        Ok((tag, self.bytes(tag.len())?))
+    }
+
+    pub fn unsigned(&mut self) -> Result<u64, DecodeError> {
+        let (_, bytes) = self.application(tag::UNSIGNED)?;
+        Ok(decode_unsigned(bytes))
+    }
+
+    pub fn enumerated(&mut self) -> Result<u32, DecodeError> {
+        let (_, bytes) = self.application(tag::ENUMERATED)?;
+        Ok(decode_unsigned(bytes) as u32)
+    }
+
+    pub fn object_id(&mut self) -> Result<ObjectId, DecodeError> {
+        let (_, bytes) = self.application(tag::OBJECT_ID)?;
+        decode_object_id(bytes)
+    }
+
+    pub fn boolean(&mut self) -> Result<bool, DecodeError> {
+        let (tag, _) = self.application(tag::BOOLEAN)?;
+        Ok(tag.kind == TagKind::Value(1))
+    }
+
+    pub fn signed(&mut self) -> Result<i64, DecodeError> {
+        let (_, bytes) = self.application(tag::SIGNED)?;
+        Ok(decode_signed(bytes))
+    }
+
+    pub fn octet_string(&mut self) -> Result<&'a [u8], DecodeError> {
+        let (_, bytes) = self.application(tag::OCTET_STRING)?;
+        Ok(bytes)
+    }
+
+    pub fn context_unsigned(&mut self, number: u8) -> Result<Option<u64>, DecodeError> {
+        Ok(self.context(number)?.map(decode_unsigned))
+    }
+
+    pub fn context_object_id(&mut self, number: u8) -> Result<Option<ObjectId>, DecodeError> {
+        self.context(number)?.map(decode_object_id).transpose()
+    }
+}
+
+pub(crate) fn decode_unsigned(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .take(8)
+        .rev()
+        .fold(0u64, |acc, b| (acc << 8) | *b as u64)
+}
+
+pub(crate) fn decode_signed(bytes: &[u8]) -> i64 {
+    let Some(first) = bytes.first() else {
+        return 0;
+    };
+    let init: i64 = if first & 0x80 != 0 { -1 } else { 0 };
+    bytes
+        .iter()
+        .rev()
+        .take(8)
+        .rev()
+        .fold(init, |acc, b| (acc << 8) | *b as i64)
+}
+
+pub(crate) fn decode_real(bytes: &[u8]) -> Result<f32, DecodeError> {
+    let bytes: [u8; 4] = bytes.try_into().map_err(|_| DecodeError("invalid real"))?;
+    Ok(f32::from_be_bytes(bytes))
+}
+
+pub(crate) fn decode_double(bytes: &[u8]) -> Result<f64, DecodeError> {
+    let bytes: [u8; 8] = bytes
+        .try_into()
+        .map_err(|_| DecodeError("invalid double"))?;
+    Ok(f64::from_be_bytes(bytes))
+}
+
+pub(crate) fn decode_object_id(bytes: &[u8]) -> Result<ObjectId, DecodeError> {
+    let bytes: [u8; 4] = bytes
+        .try_into()
+        .map_err(|_| DecodeError("invalid object identifier"))?;
+    let value = u32::from_be_bytes(bytes);
+    let object_type: ObjectType = (value >> 22)
+        .try_into()
+        .map_err(|_| DecodeError("unknown object type"))?;
+    Ok(ObjectId::new(object_type, value & 0x3f_ffff))
+}
+
+/// Decode the bits of a bit string, dropping the unused trailing bits.
+pub(crate) fn decode_bit_string(bytes: &[u8]) -> Vec<bool> {
+    let Some((unused, data)) = bytes.split_first() else {
+        return Vec::new();
+    };
+    let len = (data.len() * 8).saturating_sub(*unused as usize);
+    (0..len)
+        .map(|i| data[i / 8] & (0x80 >> (i % 8)) != 0)
+        .collect()
+}
+
+/// Build a confirmed request APDU accepting segmented responses.
+pub(crate) fn confirmed_request(invoke_id: u8, service: u8, payload: &[u8]) -> Vec<u8> {
+    let mut apdu = Vec::with_capacity(4 + payload.len());
+    // segmented-response-accepted
+    apdu.push((PDU_CONFIRMED_REQUEST << 4) | 0x02);
+    // more than 64 segments, 1476 octets
+    apdu.push(0x75);
+    apdu.push(invoke_id);
+    apdu.push(service);
+    apdu.extend_from_slice(payload);
+    apdu
+}
+
+pub(crate) fn unconfirmed_request(service: u8, payload: &[u8]) -> Vec<u8> {
+    let mut apdu = Vec::with_capacity(2 + payload.len());
+    apdu.push(PDU_UNCONFIRMED_REQUEST << 4);
+    apdu.push(service);
+    apdu.extend_from_slice(payload);
+    apdu
+}
+
+/// Build a Simple-ACK answering a confirmed request received from a device.
+pub(crate) fn simple_ack(invoke_id: u8, service: u8) -> Vec<u8> {
+    vec![PDU_SIMPLE_ACK << 4, invoke_id, service]
+}
+
+/// Build a Segment-ACK sent by the client for a received segment.
+pub(crate) fn segment_ack(invoke_id: u8, sequence: u8, window: u8) -> Vec<u8> {
+    vec![PDU_SEGMENT_ACK << 4, invoke_id, sequence, window]
+}
+
+/// Wrap an APDU into an original-unicast BVLL frame.
+pub(crate) fn unicast_frame(apdu: &[u8], expecting_reply: bool) -> Vec<u8> {
+    frame(BVLL_ORIGINAL_UNICAST, apdu, expecting_reply, false)
+}
+
+/// Wrap an APDU into an original-broadcast BVLL frame addressed to all networks.
+pub(crate) fn broadcast_frame(apdu: &[u8]) -> Vec<u8> {
+    frame(BVLL_ORIGINAL_BROADCAST, apdu, false, true)
+}
+
+/// Build a BVLL message without NPDU, such as a BBMD management request.
+pub(crate) fn bvll_message(function: u8, payload: &[u8]) -> Vec<u8> {
+    let len = 4 + payload.len();
+    let mut message = Vec::with_capacity(len);
+    message.push(BVLL_TYPE);
+    message.push(function);
+    message.extend_from_slice(&(len as u16).to_be_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Split a received BVLL message into its function and payload.
+pub(crate) fn parse_bvll(message: &[u8]) -> Option<(u8, &[u8])> {
+    if message.len() < 4 || message[0] != BVLL_TYPE {
+        return None;
+    }
+    let len = u16::from_be_bytes([message[2], message[3]]) as usize;
+    Some((message[1], message.get(4..len)?))
+}
+
+fn frame(function: u8, apdu: &[u8], expecting_reply: bool, global: bool) -> Vec<u8> {
+    let mut npdu = vec![0x01];
+    let mut control = 0u8;
+    if expecting_reply {
+        control |= 0x04;
+    }
+    if global {
+        control |= 0x20;
+    }
+    npdu.push(control);
+    if global {
+        // DNET 0xffff, DLEN 0, hop count 255
+        npdu.extend_from_slice(&[0xff, 0xff, 0x00, 0xff]);
+    }
+    npdu.extend_from_slice(apdu);
+    bvll_message(function, &npdu)
+}
+
+/// Segmentation details of a segmented complex ack.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Segment {
+    pub sequence: u8,
+    pub more: bool,
+}
+
+#[derive(Debug)]
+pub(crate) enum Apdu<'a> {
+    ConfirmedRequest {
+        invoke_id: u8,
+        service: u8,
+        data: &'a [u8],
+    },
+    UnconfirmedRequest {
+        service: u8,
+        data: &'a [u8],
+    },
+    SimpleAck {
+        invoke_id: u8,
+    },
+    ComplexAck {
+        invoke_id: u8,
+        segment: Option<Segment>,
+        data: &'a [u8],
+    },
+    SegmentAck {
+        invoke_id: u8,
+    },
+    Error {
+        invoke_id: u8,
+        data: &'a [u8],
+    },
+    Reject {
+        invoke_id: u8,
+        reason: u8,
+    },
+    Abort {
+        invoke_id: u8,
+        reason: u8,
+    },
+}
+
+impl Apdu<'_> {
+    pub fn invoke_id(&self) -> Option<u8> {
+        match self {
+            Apdu::ConfirmedRequest { invoke_id, .. }
+            | Apdu::SimpleAck { invoke_id }
+            | Apdu::ComplexAck { invoke_id, .. }
+            | Apdu::SegmentAck { invoke_id }
+            | Apdu::Error { invoke_id, .. }
+            | Apdu::Reject { invoke_id, .. }
+            | Apdu::Abort { invoke_id, .. } => Some(*invoke_id),
+            Apdu::UnconfirmedRequest { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Received<'a> {
+    pub apdu: Apdu<'a>,
+}
+
+/// Offset of the NPDU in a BACnet/IP frame, or `None` if it carries no NPDU.
+pub(crate) fn npdu_offset(frame: &[u8]) -> Option<usize> {
+    if frame.len() < 4 || frame[0] != BVLL_TYPE {
+        return None;
+    }
+    match frame[1] {
+        BVLL_ORIGINAL_UNICAST | BVLL_ORIGINAL_BROADCAST => Some(4),
+        BVLL_FORWARDED_NPDU => Some(10),
+        _ => None,
+    }
+}
+
+/// Offset of the APDU in a BACnet/IP frame, or `None` for network layer
+/// messages and non-NPDU BVLL functions.
+pub(crate) fn apdu_offset(frame: &[u8]) -> Option<usize> {
+    let mut pos = npdu_offset(frame)?;
+    let control = *frame.get(pos + 1)?;
+    pos += 2;
+    if control & 0x80 != 0 {
+        return None;
+    }
+    let has_destination = control & 0x20 != 0;
+    if has_destination {
+        let len = *frame.get(pos + 2)? as usize;
+        pos += 3 + len;
+    }
+    if control & 0x08 != 0 {
+        let len = *frame.get(pos + 2)? as usize;
+        pos += 3 + len;
+    }
+    if has_destination {
+        pos += 1;
+    }
+    (pos < frame.len()).then_some(pos)
+}
+
+/// Parse a received BACnet/IP frame. Returns `None` for frames that don't
+/// carry an APDU.
+pub(crate) fn parse_frame(frame: &[u8]) -> Option<Received<'_>> {
+    let pos = apdu_offset(frame)?;
+    let apdu = &frame[pos..];
+    let pdu_type = apdu[0] >> 4;
+    let apdu = match pdu_type {
+        PDU_CONFIRMED_REQUEST => {
+            // segmented requests are not supported
+            if apdu[0] & 0x08 != 0 {
+                return None;
+            }
+            Apdu::ConfirmedRequest {
+                invoke_id: *apdu.get(2)?,
+                service: *apdu.get(3)?,
+                data: apdu.get(4..)?,
+            }
+        }
+        PDU_UNCONFIRMED_REQUEST => Apdu::UnconfirmedRequest {
+            service: *apdu.get(1)?,
+            data: apdu.get(2..)?,
+        },
+        PDU_SIMPLE_ACK => Apdu::SimpleAck {
+            invoke_id: *apdu.get(1)?,
+        },
+        PDU_COMPLEX_ACK if apdu[0] & 0x08 != 0 => Apdu::ComplexAck {
+            invoke_id: *apdu.get(1)?,
+            segment: Some(Segment {
+                sequence: *apdu.get(2)?,
+                more: apdu[0] & 0x04 != 0,
+            }),
+            data: apdu.get(5..)?,
+        },
+        PDU_COMPLEX_ACK => Apdu::ComplexAck {
+            invoke_id: *apdu.get(1)?,
+            segment: None,
+            data: apdu.get(3..)?,
+        },
+        PDU_SEGMENT_ACK => Apdu::SegmentAck {
+            invoke_id: *apdu.get(1)?,
+        },
+        PDU_ERROR => Apdu::Error {
+            invoke_id: *apdu.get(1)?,
+            data: apdu.get(3..)?,
+        },
+        PDU_REJECT => Apdu::Reject {
+            invoke_id: *apdu.get(1)?,
+            reason: *apdu.get(2)?,
+        },
+        PDU_ABORT => Apdu::Abort {
+            invoke_id: *apdu.get(1)?,
+            reason: *apdu.get(2)?,
+        },
+        _ => return None,
+    };
+    Some(Received { apdu })
+}
+
+/// Decode the error class and code of an Error PDU.
+///
+/// Most services return a plain `Error` sequence; the few with a constructed
+/// error type wrap it in context tags, so the first two enumerated values are
+/// taken wherever they appear.
+pub(crate) fn parse_error(data: &[u8]) -> ServiceError {
+    let mut decoder = Decoder::new(data);
+    let mut values = Vec::with_capacity(2);
+    while values.len() < 2 {
+        let Ok(header) = decoder.read_tag() else {
+            break;
+        };
+        if header.kind == TagKind::Opening || header.kind == TagKind::Closing {
+            continue;
+        }
+        let Ok(bytes) = decoder.bytes(header.len()) else {
+            break;
+        };
+        if header.is_application(tag::ENUMERATED) {
+            values.push(decode_unsigned(bytes) as u32);
+        }
+    }
+    ServiceError {
+        class: values.first().copied().unwrap_or_default(),
+        code: values.get(1).copied().unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(encode: impl FnOnce(&mut Encoder)) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encode(&mut encoder);
+        encoder.into_bytes()
+    }
+
+    #[test]
+    fn tag_header_round_trip() {
+        for number in [0, 3, 14, 15, 42, 254] {
+            for len in [0, 1, 4, 5, 253, 254, 300, 65535, 65536] {
+                let content = vec![0xa5; len];
+                let data =
+                    tagged(|encoder| encoder.data(Some(number), tag::OCTET_STRING, &content));
+                let mut decoder = Decoder::new(&data);
+                let tag = decoder.read_tag().unwrap();
+                assert_eq!(tag.number, number);
+                assert_eq!(tag.class, TagClass::Context);
+                assert_eq!(tag.len(), len);
+                assert_eq!(decoder.bytes(tag.len()).unwrap(), &content[..]);
+                assert!(decoder.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn extended_lengths() {
+        assert_eq!(
+            tagged(|encoder| encoder.octet_string(&[0; 5]))[..2],
+            [0x65, 5]
+        );
+        assert_eq!(
+            tagged(|encoder| encoder.octet_string(&[0; 253]))[..2],
+            [0x65, 253]
+        );
+        assert_eq!(
+            tagged(|encoder| encoder.octet_string(&[0; 254]))[..4],
+            [0x65, 254, 0, 254]
+        );
+        assert_eq!(
+            tagged(|encoder| encoder.octet_string(&[0; 65536]))[..6],
+            [0x65, 255, 0, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn application_values_round_trip() {
+        let data = tagged(|encoder| {
+            encoder.null();
+            encoder.boolean(true);
+            encoder.boolean(false);
+            encoder.unsigned(0);
+            encoder.unsigned(256);
+            encoder.signed(-129);
+            encoder.enumerated(85);
+            encoder.octet_string(&[1, 2, 3]);
+            encoder.object_id(ObjectId::new(ObjectType::ObjectAnalogInput, 0x3f_ffff));
+        });
+        let mut decoder = Decoder::new(&data);
+        assert_eq!(decoder.application(tag::NULL).unwrap().1, &[] as &[u8]);
+        assert!(decoder.boolean().unwrap());
+        assert!(!decoder.boolean().unwrap());
+        assert_eq!(decoder.unsigned().unwrap(), 0);
+        assert_eq!(decoder.unsigned().unwrap(), 256);
+        assert_eq!(decoder.signed().unwrap(), -129);
+        assert_eq!(decoder.enumerated().unwrap(), 85);
+        assert_eq!(decoder.octet_string().unwrap(), &[1, 2, 3]);
+        assert_eq!(
+            decoder.object_id().unwrap(),
+            ObjectId::new(ObjectType::ObjectAnalogInput, 0x3f_ffff)
+        );
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn minimal_integer_encodings() {
+        assert_eq!(unsigned_bytes(0), [0]);
+        assert_eq!(unsigned_bytes(255), [0xff]);
+        assert_eq!(unsigned_bytes(256), [1, 0]);
+        assert_eq!(signed_bytes(-1), [0xff]);
+        assert_eq!(signed_bytes(127), [0x7f]);
+        assert_eq!(signed_bytes(128), [0, 0x80]);
+        assert_eq!(signed_bytes(-129), [0xff, 0x7f]);
+        for value in [0, 1, -1, 127, 128, -128, -129, i64::MIN, i64::MAX] {
+            assert_eq!(decode_signed(&signed_bytes(value)), value);
+        }
+        for value in [0, 1, 255, 256, u64::MAX] {
+            assert_eq!(decode_unsigned(&unsigned_bytes(value)), value);
+        }
+    }
+
+    #[test]
+    fn bit_string_round_trip() {
+        for len in [0, 1, 7, 8, 9, 17] {
+            let bits: Vec<bool> = (0..len).map(|i| i % 3 == 0).collect();
+            assert_eq!(decode_bit_string(&bit_string_bytes(&bits)), bits);
+        }
+    }
+
+    #[test]
+    fn opening_and_closing_tags() {
+        let data = tagged(|encoder| {
+            encoder.opening(3);
+            encoder.opening(20);
+            encoder.unsigned(7);
+            encoder.closing(20);
+            encoder.context_unsigned(1, 2);
+            encoder.closing(3);
+            encoder.context_enumerated(4, 1);
+        });
+        assert_eq!(data[0], 0x3e);
+        assert_eq!(data[1..3], [0xfe, 20]);
+
+        let mut decoder = Decoder::new(&data);
+        let enclosed = decoder.enclosed(3).unwrap();
+        assert_eq!(decoder.context_unsigned(4).unwrap(), Some(1));
+        assert!(decoder.is_empty());
+
+        let mut inner = Decoder::new(enclosed);
+        assert!(!inner.opening(3).unwrap());
+        assert!(inner.opening(20).unwrap());
+        assert_eq!(inner.unsigned().unwrap(), 7);
+        assert!(inner.is_closing(20));
+        inner.expect_closing(20).unwrap();
+        assert_eq!(inner.context_unsigned(1).unwrap(), Some(2));
+        assert!(inner.is_empty());
+    }
+
+    #[test]
+    fn unbalanced_closing_tag_fails_to_skip() {
+        let data = tagged(|encoder| encoder.closing(1));
+        assert!(Decoder::new(&data).skip().is_err());
+    }
+
+    #[test]
+    fn confirmed_request_round_trip() {
+        let frame = unicast_frame(
+            &confirmed_request(42, confirmed::READ_PROPERTY, &[1, 2]),
+            true,
+        );
+        assert_eq!(apdu_offset(&frame), Some(6));
+        match parse_frame(&frame).unwrap().apdu {
+            Apdu::ConfirmedRequest {
+                invoke_id,
+                service,
+                data,
+            } => {
+                assert_eq!(
+                    (invoke_id, service, data),
+                    (42, confirmed::READ_PROPERTY, &[1, 2][..])
+                );
+            }
+            apdu => panic!("unexpected {apdu:?}"),
+        }
+    }
+
+    #[test]
+    fn complex_ack_offsets() {
+        let frame = unicast_frame(&[0x30, 7, confirmed::READ_PROPERTY, 0xaa, 0xbb], false);
+        match parse_frame(&frame).unwrap().apdu {
+            Apdu::ComplexAck {
+                invoke_id: 7,
+                segment: None,
+                data,
+            } => assert_eq!(data, [0xaa, 0xbb]),
+            apdu => panic!("unexpected {apdu:?}"),
+        }
+
+        // segmented, more follows: sequence number and window size precede
+        // the service choice
+        let frame = unicast_frame(&[0x3c, 7, 2, 4, confirmed::READ_PROPERTY, 0xaa], false);
+        match parse_frame(&frame).unwrap().apdu {
+            Apdu::ComplexAck {
+                invoke_id: 7,
+                segment: Some(segment),
+                data,
+            } => {
+                assert_eq!(segment.sequence, 2);
+                assert!(segment.more);
+                assert_eq!(data, [0xaa]);
+            }
+            apdu => panic!("unexpected {apdu:?}"),
+        }
+
+        let frame = unicast_frame(&[0x38, 7, 3, 4, confirmed::READ_PROPERTY], false);
+        match parse_frame(&frame).unwrap().apdu {
+            Apdu::ComplexAck {
+                segment: Some(segment),
+                ..
+            } => assert!(!segment.more),
+            apdu => panic!("unexpected {apdu:?}"),
+        }
+    }
+
+    #[test]
+    fn forwarded_npdu_offset() {
+        let mut frame = vec![BVLL_TYPE, BVLL_FORWARDED_NPDU, 0, 0];
+        // originating B/IP address
+        frame.extend_from_slice(&[192, 168, 1, 10, 0xba, 0xc0]);
+        frame.extend_from_slice(&[0x01, 0x00]);
+        frame.extend_from_slice(&unconfirmed_request(unconfirmed::I_AM, &[0xc4]));
+        let len = frame.len() as u16;
+        frame[2..4].copy_from_slice(&len.to_be_bytes());
+
+        assert_eq!(npdu_offset(&frame), Some(10));
+        assert_eq!(apdu_offset(&frame), Some(12));
+        assert!(matches!(
+            parse_frame(&frame).unwrap().apdu,
+            Apdu::UnconfirmedRequest {
+                service: unconfirmed::I_AM,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn routed_npdu_offsets() {
+        let apdu = simple_ack(9, confirmed::WRITE_PROPERTY);
+        let frame = |npdu: &[u8]| {
+            let mut payload = npdu.to_vec();
+            payload.extend_from_slice(&apdu);
+            bvll_message(BVLL_ORIGINAL_UNICAST, &payload)
+        };
+
+        // SNET, SLEN 6, SADR
+        let routed = frame(&[0x01, 0x08, 0x00, 0x05, 6, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(apdu_offset(&routed), Some(4 + 2 + 3 + 6));
+        // DNET, DLEN 2, DADR, hop count
+        let routed = frame(&[0x01, 0x20, 0x00, 0x07, 2, 1, 2, 0xff]);
+        assert_eq!(apdu_offset(&routed), Some(4 + 2 + 3 + 2 + 1));
+        // both: the hop count comes after the source
+        let routed = frame(&[0x01, 0x28, 0x00, 0x07, 1, 9, 0x00, 0x05, 1, 3, 0xff]);
+        assert_eq!(apdu_offset(&routed), Some(4 + 2 + 3 + 1 + 3 + 1 + 1));
+        assert!(matches!(
+            parse_frame(&routed).unwrap().apdu,
+            Apdu::SimpleAck { invoke_id: 9 }
+        ));
+
+        // global broadcast, DLEN 0
+        let broadcast = broadcast_frame(&unconfirmed_request(unconfirmed::WHO_IS, &[]));
+        assert_eq!(apdu_offset(&broadcast), Some(10));
+    }
+
+    #[test]
+    fn network_messages_have_no_apdu() {
+        let frame = bvll_message(BVLL_ORIGINAL_UNICAST, &[0x01, 0x80, 0x00]);
+        assert_eq!(apdu_offset(&frame), None);
+        assert!(parse_frame(&frame).is_none());
+        let truncated = bvll_message(BVLL_ORIGINAL_UNICAST, &[0x01, 0x08, 0x00, 0x05, 6, 1]);
+        assert_eq!(apdu_offset(&truncated), None);
+        assert_eq!(apdu_offset(&bvll_message(bvll::READ_BDT, &[])), None);
+    }
+
+    #[test]
+    fn error_class_and_code() {
+        let data = tagged(|encoder| {
+            encoder.enumerated(1);
+            encoder.enumerated(31);
+        });
+        let error = parse_error(&data);
+        assert_eq!((error.class, error.code), (1, 31));
+
+        // constructed errors wrap the pair in context tags
+        let data = tagged(|encoder| {
+            encoder.opening(0);
+            encoder.enumerated(2);
+            encoder.enumerated(32);
+            encoder.closing(0);
+            encoder.context_unsigned(1, 3);
+        });
+        let error = parse_error(&data);
+        assert_eq!((error.class, error.code), (2, 32));
+    }
+}
@@ -0,0 +1,406 @@
+//! Backup and restore of a device's configuration (Clause 19.1), by
+//! transferring the File objects listed in its configuration-files.
+
+use std::{fs, io, path::Path, time::Duration};
+
+use crate::{
+    apdu::{self, Decoder, Encoder},
+    client::Client,
+    device,
+    error::{DecodeError, Error},
+    value::Value,
+    NetworkIo, ObjectId, ObjectType,
+};
+
+const FILE_ACCESS_METHOD: u32 = 41;
+const CONFIGURATION_FILES: u32 = 154;
+const BACKUP_PREPARATION_TIME: u32 = 339;
+const RESTORE_PREPARATION_TIME: u32 = 341;
+
+/// Ack and request octets of AtomicReadFile and AtomicWriteFile beyond the
+/// file data: APDU header, object identifier, tags and position.
+const FILE_OVERHEAD: usize = 20;
+/// Records requested per AtomicReadFile.
+const RECORDS_PER_READ: u64 = 8;
+/// Lists the files of a saved backup, in restore order.
+const INDEX: &str = "files.txt";
+
+/// State a device is asked to enter by ReinitializeDevice.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReinitializeState {
+    ColdStart,
+    WarmStart,
+    StartBackup,
+    EndBackup,
+    StartRestore,
+    EndRestore,
+    AbortRestore,
+    /// Apply pending network port changes.
+    ActivateChanges,
+}
+
+impl From<ReinitializeState> for u32 {
+    fn from(value: ReinitializeState) -> Self {
+        match value {
+            ReinitializeState::ColdStart => 0,
+            ReinitializeState::WarmStart => 1,
+            ReinitializeState::StartBackup => 2,
+            ReinitializeState::EndBackup => 3,
+            ReinitializeState::StartRestore => 4,
+            ReinitializeState::EndRestore => 5,
+            ReinitializeState::AbortRestore => 6,
+            ReinitializeState::ActivateChanges => 7,
+        }
+    }
+}
+
+/// Contents of a File object, by access method.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FileData {
+    Stream(Vec<u8>),
+    Records(Vec<Vec<u8>>),
+}
+
+/// A configuration file of a [`Backup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BackupFile {
+    pub object_id: ObjectId,
+    pub data: FileData,
+}
+
+/// The configuration files of a device, in the order it lists them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Backup {
+    pub files: Vec<BackupFile>,
+}
+
+impl Backup {
+    /// Write the backup to the directory `dir`, created if missing: one
+    /// file per File object, named after its instance, and an index of
+    /// them. Records are each prefixed with their length as a big-endian
+    /// u32.
+    pub fn save(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut index = String::new();
+        for file in &self.files {
+            let (name, contents) = match &file.data {
+                FileData::Stream(data) => (format!("{}.stream", file.object_id.id), data.clone()),
+                FileData::Records(records) => {
+                    let mut contents = Vec::new();
+                    for record in records {
+                        contents.extend_from_slice(&(record.len() as u32).to_be_bytes());
+                        contents.extend_from_slice(record);
+                    }
+                    (format!("{}.records", file.object_id.id), contents)
+                }
+            };
+            fs::write(dir.join(&name), contents)?;
+            index.push_str(&name);
+            index.push('\n');
+        }
+        fs::write(dir.join(INDEX), index)
+    }
+
+    /// Read a backup written by [`Backup::save`].
+    pub fn load(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut files = Vec::new();
+        for name in fs::read_to_string(dir.join(INDEX))?.lines() {
+            let (instance, kind) = name
+                .split_once('.')
+                .ok_or_else(|| invalid(format!("invalid backup file name `{name}`")))?;
+            let instance = instance
+                .parse()
+                .map_err(|_| invalid(format!("invalid backup file name `{name}`")))?;
+            let contents = fs::read(dir.join(name))?;
+            let data = match kind {
+                "stream" => FileData::Stream(contents),
+                "records" => FileData::Records(
+                    split_records(&contents)
+                        .ok_or_else(|| invalid(format!("truncated record in `{name}`")))?,
+                ),
+                _ => return Err(invalid(format!("invalid backup file name `{name}`"))),
+            };
+            files.push(BackupFile {
+                object_id: ObjectId::new(ObjectType::ObjectFile, instance),
+                data,
+            });
+        }
+        Ok(Self { files })
+    }
+}
+
+fn split_records(mut contents: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut records = Vec::new();
+    while !contents.is_empty() {
+        let (len, rest) = contents.split_at_checked(4)?;
+        let len = u32::from_be_bytes(len.try_into().ok()?) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        records.push(rest[..len].to_vec());
+        contents = &rest[len..];
+    }
+    Some(records)
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Ask the device to restart or to enter or leave backup and restore
+    /// modes, with the password configured in it if any.
+    pub async fn reinitialize_device(
+        &mut self,
+        state: ReinitializeState,
+        password: Option<&str>,
+    ) -> Result<(), Error<T>> {
+        let mut request = Encoder::new();
+        request.context_enumerated(0, state.into());
+        if let Some(password) = password {
+            request.context_character_string(1, password);
+        }
+        self.confirmed_request(apdu::confirmed::REINITIALIZE_DEVICE, &request.into_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// Read the whole contents of a File object with AtomicReadFile.
+    pub async fn read_file(&mut self, file: ObjectId) -> Result<FileData, Error<T>> {
+        let method = self
+            .read_property_raw(file, FILE_ACCESS_METHOD, None)
+            .await?;
+        let stream = Value::decode_all(&method)?.as_u32() == Some(1);
+        let chunk = self.max_apdu().await?.saturating_sub(FILE_OVERHEAD).max(1);
+
+        let mut data = Vec::new();
+        let mut records = Vec::new();
+        loop {
+            let mut request = Encoder::new();
+            request.object_id(file);
+            let number = if stream { 0 } else { 1 };
+            request.opening(number);
+            match stream {
+                true => {
+                    request.signed(data.len() as i64);
+                    request.unsigned(chunk as u64);
+                }
+                false => {
+                    request.signed(records.len() as i64);
+                    request.unsigned(RECORDS_PER_READ);
+                }
+            }
+            request.closing(number);
+            let ack = self
+                .confirmed_request(apdu::confirmed::ATOMIC_READ_FILE, &request.into_bytes())
+                .await?;
+
+            let mut decoder = Decoder::new(&ack);
+            let end_of_file = decoder.boolean()?;
+            decoder.expect_opening(number)?;
+            decoder.signed()?;
+            let received = match stream {
+                true => {
+                    let octets = decoder.octet_string()?;
+                    data.extend_from_slice(octets);
+                    octets.len()
+                }
+                false => {
+                    let count = decoder.unsigned()?;
+                    for _ in 0..count {
+                        records.push(decoder.octet_string()?.to_vec());
+                    }
+                    count as usize
+                }
+            };
+            decoder.expect_closing(number)?;
+            // A device sending nothing without reaching the end would be
+            // asked the same thing forever
+            if end_of_file || received == 0 {
+                break;
+            }
+        }
+        Ok(match stream {
+            true => FileData::Stream(data),
+            false => FileData::Records(records),
+        })
+    }
+
+    /// Write the contents of a File object from the start with
+    /// AtomicWriteFile, in as many requests as the device's max-APDU
+    /// requires.
+    pub async fn write_file(&mut self, file: ObjectId, data: &FileData) -> Result<(), Error<T>> {
+        let chunk = self.max_apdu().await?.saturating_sub(FILE_OVERHEAD).max(1);
+        match data {
+            FileData::Stream(data) => {
+                for (i, octets) in data.chunks(chunk).enumerate() {
+                    let mut request = Encoder::new();
+                    request.object_id(file);
+                    request.opening(0);
+                    request.signed((i * chunk) as i64);
+                    request.octet_string(octets);
+                    request.closing(0);
+                    self.confirmed_request(
+                        apdu::confirmed::ATOMIC_WRITE_FILE,
+                        &request.into_bytes(),
+                    )
+                    .await?;
+                }
+            }
+            FileData::Records(records) => {
+                let mut start = 0;
+                while start < records.len() {
+                    let mut end = start + 1;
+                    let mut size = records[start].len();
+                    while end < records.len() && size + records[end].len() + 5 <= chunk {
+                        size += records[end].len() + 5;
+                        end += 1;
+                    }
+                    let mut request = Encoder::new();
+                    request.object_id(file);
+                    request.opening(1);
+                    request.signed(start as i64);
+                    request.unsigned((end - start) as u64);
+                    records[start..end]
+                        .iter()
+                        .for_each(|record| request.octet_string(record));
+                    request.closing(1);
+                    self.confirmed_request(
+                        apdu::confirmed::ATOMIC_WRITE_FILE,
+                        &request.into_bytes(),
+                    )
+                    .await?;
+                    start = end;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Back up the device's configuration: put it in backup mode, read
+    /// each file listed in its configuration-files, then end backup mode.
+    ///
+    /// Backup mode is ended even when reading fails. Save the result with
+    /// [`Backup::save`].
+    pub async fn backup(&mut self, password: Option<&str>) -> Result<Backup, Error<T>> {
+        self.reinitialize_device(ReinitializeState::StartBackup, password)
+            .await?;
+        let backup = self.read_configuration_files().await;
+        let ended = self
+            .reinitialize_device(ReinitializeState::EndBackup, password)
+            .await;
+        let backup = backup?;
+        ended?;
+        Ok(backup)
+    }
+
+    /// Restore a configuration read by [`Client::backup`]: put the device
+    /// in restore mode, write each file, then end restore mode so the
+    /// device applies the configuration, usually by restarting.
+    ///
+    /// The restore is aborted when writing fails, and the device keeps its
+    /// previous configuration.
+    pub async fn restore(
+        &mut self,
+        backup: &Backup,
+        password: Option<&str>,
+    ) -> Result<(), Error<T>> {
+        self.reinitialize_device(ReinitializeState::StartRestore, password)
+            .await?;
+        match self.write_configuration_files(backup).await {
+            Ok(()) => {
+                self.reinitialize_device(ReinitializeState::EndRestore, password)
+                    .await
+            }
+            Err(err) => {
+                let _ = self
+                    .reinitialize_device(ReinitializeState::AbortRestore, password)
+                    .await;
+                Err(err)
+            }
+        }
+    }
+
+    async fn read_configuration_files(&mut self) -> Result<Backup, Error<T>> {
+        self.wait_preparation(BACKUP_PREPARATION_TIME).await?;
+        let files = self
+            .read_property_raw(device::wildcard_device(), CONFIGURATION_FILES, None)
+            .await?;
+        let files = Value::decode_list(&files)?;
+        let mut backup = Backup::default();
+        for file in files {
+            let Value::ObjectId(object_id) = file else {
+                return Err(DecodeError("configuration file is not an object id").into());
+            };
+            let data = self.read_file(object_id).await?;
+            backup.files.push(BackupFile { object_id, data });
+        }
+        Ok(backup)
+    }
+
+    async fn write_configuration_files(&mut self, backup: &Backup) -> Result<(), Error<T>> {
+        self.wait_preparation(RESTORE_PREPARATION_TIME).await?;
+        for file in &backup.files {
+            self.write_file(file.object_id, &file.data).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait the time the device says it needs to prepare for a backup or
+    /// restore, read from the `property` of its device object.
+    async fn wait_preparation(&mut self, property: u32) -> Result<(), Error<T>> {
+        let seconds = match self
+            .read_property_raw(device::wildcard_device(), property, None)
+            .await
+        {
+            Ok(data) => Value::decode_all(&data)?.as_u32().unwrap_or_default(),
+            Err(err) if err.is_transport() => return Err(err),
+            Err(_) => 0,
+        };
+        tokio::time::sleep(Duration::from_secs(seconds as u64)).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(instance: u32, data: FileData) -> BackupFile {
+        BackupFile {
+            object_id: ObjectId::new(ObjectType::ObjectFile, instance),
+            data,
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup = Backup {
+            files: vec![
+                file(2, FileData::Stream(vec![0, 1, 2, 255])),
+                file(1, FileData::Records(vec![vec![1, 2], Vec::new(), vec![3]])),
+                file(3, FileData::Records(Vec::new())),
+            ],
+        };
+        backup.save(dir.path()).unwrap();
+        assert_eq!(Backup::load(dir.path()).unwrap(), backup);
+    }
+
+    #[test]
+    fn truncated_records_are_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup = Backup {
+            files: vec![file(1, FileData::Records(vec![vec![1, 2, 3]]))],
+        };
+        backup.save(dir.path()).unwrap();
+        let path = dir.path().join("1.records");
+        let contents = fs::read(&path).unwrap();
+        fs::write(&path, &contents[..contents.len() - 1]).unwrap();
+
+        let err = Backup::load(dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
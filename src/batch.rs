@@ -0,0 +1,450 @@
+//! Bulk reads batched into ReadPropertyMultiple requests.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    net::SocketAddr,
+    sync::Mutex,
+};
+
+use futures_util::future::try_join_all;
+use log::debug;
+
+use crate::{
+    apdu::{self, Decoder, Encoder},
+    client::Client,
+    device,
+    error::{DecodeError, Error, ServiceError},
+    pool::ClientPool,
+    services::ServicesSupported,
+    value::Value,
+    NetworkIo, ObjectId, PropertyId,
+};
+
+/// Max-APDU assumed for devices that don't report theirs.
+const DEFAULT_MAX_APDU: usize = 480;
+
+/// Estimated ack size of one property: tags plus a typical primitive value.
+const PROPERTY_ESTIMATE: usize = 16;
+/// Estimated ack size of a character string property: tags plus 64
+/// characters, as names and descriptions run much longer than primitives.
+const STRING_PROPERTY_ESTIMATE: usize = 72;
+/// Character string properties: active-text, application-software-version,
+/// description, device-type, firmware-revision, inactive-text, location,
+/// model-name, object-name, vendor-name and profile-name.
+const STRING_PROPERTIES: [u32; 11] = [4, 12, 28, 31, 44, 46, 58, 70, 77, 121, 168];
+/// Estimated ack size of the object identifier and list tags of one object.
+const OBJECT_ESTIMATE: usize = 7;
+/// Complex-ack header.
+const HEADER_ESTIMATE: usize = 3;
+
+/// What the client learned about the device's ReadPropertyMultiple and
+/// WritePropertyMultiple support, and the services it says it supports.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DeviceInfo {
+    pub max_apdu: Option<usize>,
+    pub rpm_unsupported: bool,
+    pub wpm_unsupported: bool,
+    pub services: Option<ServicesSupported>,
+}
+
+/// What a bulk read does when the device answers a property with an error,
+/// such as an unknown property.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ReadPolicy {
+    /// Fail the whole read with the first error, as [`Error::Property`],
+    /// without sending the remaining batches.
+    FailFast,
+    /// Keep each error in place of the value and read on.
+    #[default]
+    Collect,
+}
+
+/// Results of [`Client::read_properties`], keyed by object and property.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PropertyResults {
+    results: Vec<(ObjectId, PropertyId, Result<Value, ServiceError>)>,
+    /// Position in `results` of each object and property.
+    index: HashMap<(u32, u32), usize>,
+}
+
+impl PropertyResults {
+    fn insert(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+        result: Result<Value, ServiceError>,
+    ) {
+        let key = (apdu::object_id_value(object_id), property_id as u32);
+        if let Entry::Vacant(entry) = self.index.entry(key) {
+            entry.insert(self.results.len());
+            self.results.push((object_id, property_id, result));
+        }
+    }
+
+    /// The result read for a property, `None` when it wasn't requested.
+    pub fn get(
+        &self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+    ) -> Option<&Result<Value, ServiceError>> {
+        let key = (apdu::object_id_value(object_id), property_id as u32);
+        let (_, _, result) = &self.results[*self.index.get(&key)?];
+        Some(result)
+    }
+
+    /// The value read for a property, `None` when it wasn't requested or
+    /// the device answered with an error.
+    pub fn value(&self, object_id: ObjectId, property_id: PropertyId) -> Option<&Value> {
+        self.get(object_id, property_id)?.as_ref().ok()
+    }
+
+    /// Every result, in request order, properties requested twice once.
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (ObjectId, PropertyId, &Result<Value, ServiceError>)> + '_ {
+        self.results
+            .iter()
+            .map(|(object_id, property_id, result)| (*object_id, *property_id, result))
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// The properties the device answered with an error.
+    pub fn errors(&self) -> impl Iterator<Item = (ObjectId, PropertyId, ServiceError)> + '_ {
+        self.iter().filter_map(|(object_id, property_id, result)| {
+            result
+                .as_ref()
+                .err()
+                .map(|err| (object_id, property_id, *err))
+        })
+    }
+}
+
+/// Estimated ack size of one property.
+fn property_estimate(property_id: PropertyId) -> usize {
+    match STRING_PROPERTIES.contains(&(property_id as u32)) {
+        true => STRING_PROPERTY_ESTIMATE,
+        false => PROPERTY_ESTIMATE,
+    }
+}
+
+/// Split requests into groups whose acks should fit in `max_apdu` octets.
+/// Groups hold indices into `requests`, in order.
+fn groups(requests: &[(ObjectId, PropertyId)], max_apdu: usize) -> Vec<Vec<usize>> {
+    let mut groups = Vec::new();
+    let mut group: Vec<usize> = Vec::new();
+    let mut size = HEADER_ESTIMATE;
+    for (i, (object_id, property_id)) in requests.iter().enumerate() {
+        let new_object = group
+            .last()
+            .map_or(true, |last| requests[*last].0 != *object_id);
+        let added = property_estimate(*property_id) + if new_object { OBJECT_ESTIMATE } else { 0 };
+        if !group.is_empty() && size + added > max_apdu {
+            groups.push(std::mem::take(&mut group));
+            // the object starts the new group, whether or not it is new
+            size = HEADER_ESTIMATE + OBJECT_ESTIMATE + property_estimate(*property_id);
+        } else {
+            size += added;
+        }
+        group.push(i);
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+    groups
+}
+
+fn decode_ack<T: NetworkIo>(data: &[u8]) -> Result<Vec<Result<Value, Error<T>>>, DecodeError> {
+    let mut decoder = Decoder::new(data);
+    let mut results = Vec::new();
+    while !decoder.is_empty() {
+        decoder.expect_context(0)?;
+        decoder.expect_opening(1)?;
+        while !decoder.is_closing(1) {
+            decoder.expect_context(2)?;
+            decoder.context(3)?;
+            if decoder.peek_tag()?.is_opening(4) {
+                results.push(Value::decode_all(decoder.enclosed(4)?).map_err(Error::from));
+            } else {
+                let error = decoder.enclosed(5)?;
+                results.push(Err(Error::Service(apdu::parse_error(error))));
+            }
+        }
+        decoder.expect_closing(1)?;
+    }
+    Ok(results)
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// The largest APDU the device accepts, read from its device object
+    /// once then cached. Defaults to 480 when the device doesn't say.
+    pub async fn max_apdu(&mut self) -> Result<usize, Error<T>> {
+        if let Some(max_apdu) = self.device_info().max_apdu {
+            return Ok(max_apdu);
+        }
+        let max_apdu = match self
+            .read_value(
+                device::wildcard_device(),
+                PropertyId::PropMaxApduLengthAccepted,
+            )
+            .await
+        {
+            Ok(value) => value
+                .as_u32()
+                .map_or(DEFAULT_MAX_APDU, |max_apdu| max_apdu as usize),
+            Err(err) if err.is_transport() => return Err(err),
+            Err(_) => DEFAULT_MAX_APDU,
+        };
+        let max_apdu = max_apdu.min(apdu::MAX_APDU);
+        self.device_info().max_apdu = Some(max_apdu);
+        Ok(max_apdu)
+    }
+
+    /// Set the max-APDU used to size batches, e.g. from an I-Am.
+    pub fn set_max_apdu(&mut self, max_apdu: usize) {
+        self.device_info().max_apdu = Some(max_apdu.min(apdu::MAX_APDU));
+    }
+
+    /// Read many properties, batched into ReadPropertyMultiple requests
+    /// sized to the device's max-APDU.
+    ///
+    /// Results are in request order. Devices that reject
+    /// ReadPropertyMultiple are read one property at a time instead. Per
+    /// property errors are returned in place; transport errors and an
+    /// expired deadline fail the whole call.
+    ///
+    /// See [`ClientPool::read_many`] to run batches concurrently.
+    pub async fn read_many(
+        &mut self,
+        requests: Vec<(ObjectId, PropertyId)>,
+    ) -> Result<Vec<Result<Value, Error<T>>>, Error<T>> {
+        let mut results = Vec::with_capacity(requests.len());
+        self.read_each(&requests, |_, result| {
+            results.push(result);
+            Ok(())
+        })
+        .await?;
+        Ok(results)
+    }
+
+    /// Read many properties like [`Client::read_many`], with the errors the
+    /// device answered for single properties typed, and handled according
+    /// to `policy`.
+    ///
+    /// Other errors, such as a value that fails to decode, fail the read
+    /// whatever the policy.
+    pub async fn read_properties(
+        &mut self,
+        requests: Vec<(ObjectId, PropertyId)>,
+        policy: ReadPolicy,
+    ) -> Result<PropertyResults, Error<T>> {
+        let mut results = PropertyResults::default();
+        self.read_each(&requests, |i, result| {
+            let (object_id, property_id) = requests[i];
+            let result = match result {
+                Ok(value) => Ok(value),
+                Err(Error::Service(error)) if policy == ReadPolicy::Collect => Err(error),
+                Err(Error::Service(error)) => {
+                    return Err(Error::Property {
+                        object_id,
+                        property_id,
+                        error,
+                    })
+                }
+                Err(err) => return Err(err),
+            };
+            results.insert(object_id, property_id, result);
+            Ok(())
+        })
+        .await?;
+        Ok(results)
+    }
+
+    /// Read many properties in batches sized to the device's max-APDU,
+    /// passing each result with the index of its request to `handle` as its
+    /// batch completes. An error of `handle` ends the read.
+    async fn read_each(
+        &mut self,
+        requests: &[(ObjectId, PropertyId)],
+        mut handle: impl FnMut(usize, Result<Value, Error<T>>) -> Result<(), Error<T>>,
+    ) -> Result<(), Error<T>> {
+        let max_apdu = self.max_apdu().await?;
+        for group in groups(requests, max_apdu) {
+            let values = self.read_group(requests, &group).await?;
+            for (i, value) in group.into_iter().zip(values) {
+                handle(i, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_group(
+        &mut self,
+        requests: &[(ObjectId, PropertyId)],
+        group: &[usize],
+    ) -> Result<Vec<Result<Value, Error<T>>>, Error<T>> {
+        if !self.device_info().rpm_unsupported {
+            match self.read_group_multiple(requests, group).await {
+                Ok(results) => return Ok(results),
+                Err(err) if err.is_transport() => return Err(err),
+                Err(Error::Reject(reason)) => {
+                    debug!(
+                        "ReadPropertyMultiple rejected ({reason}), reading properties one by one"
+                    );
+                    self.device_info().rpm_unsupported = true;
+                }
+                Err(Error::Service(err)) if err.class == ServiceError::CLASS_SERVICES => {
+                    debug!("ReadPropertyMultiple refused ({err:?}), reading properties one by one");
+                    self.device_info().rpm_unsupported = true;
+                }
+                Err(err) => {
+                    debug!("ReadPropertyMultiple failed ({err:?}), reading properties one by one")
+                }
+            }
+        }
+
+        let mut results = Vec::with_capacity(group.len());
+        for i in group {
+            let (object_id, property_id) = requests[*i];
+            match self.read_value(object_id, property_id).await {
+                Err(err) if err.is_transport() => return Err(err),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
+    async fn read_group_multiple(
+        &mut self,
+        requests: &[(ObjectId, PropertyId)],
+        group: &[usize],
+    ) -> Result<Vec<Result<Value, Error<T>>>, Error<T>> {
+        let mut request = Encoder::new();
+        let mut current: Option<ObjectId> = None;
+        for i in group {
+            let (object_id, property_id) = requests[*i];
+            if current != Some(object_id) {
+                if current.is_some() {
+                    request.closing(1);
+                }
+                request.context_object_id(0, object_id);
+                request.opening(1);
+                current = Some(object_id);
+            }
+            request.context_enumerated(0, property_id as u32);
+        }
+        request.closing(1);
+
+        let ack = self
+            .confirmed_request(
+                apdu::confirmed::READ_PROPERTY_MULTIPLE,
+                &request.into_bytes(),
+            )
+            .await?;
+        let results = decode_ack(&ack)?;
+        if results.len() != group.len() {
+            return Err(Error::Decode("unexpected number of results"));
+        }
+        Ok(results)
+    }
+}
+
+impl ClientPool {
+    /// Like [`Client::read_many`], running up to `concurrency` batches at
+    /// once, each through its own client for the device.
+    ///
+    /// The pool's per-device in-flight limit still applies.
+    pub async fn read_many(
+        &self,
+        peer: SocketAddr,
+        requests: Vec<(ObjectId, PropertyId)>,
+        concurrency: usize,
+    ) -> Result<Vec<Result<Value, Error>>, Error> {
+        let mut first = self.client(peer);
+        let max_apdu = first.max_apdu().await?;
+        let queue = Mutex::new(
+            groups(&requests, max_apdu)
+                .into_iter()
+                .collect::<VecDeque<_>>(),
+        );
+        let results = Mutex::new(
+            std::iter::repeat_with(|| None)
+                .take(requests.len())
+                .collect::<Vec<Option<Result<Value, Error>>>>(),
+        );
+
+        let mut clients = vec![first];
+        for _ in 1..concurrency.max(1) {
+            let mut client = self.client(peer);
+            client.set_max_apdu(max_apdu);
+            clients.push(client);
+        }
+        try_join_all(clients.into_iter().map(|mut client| {
+            let (requests, queue, results) = (&requests, &queue, &results);
+            async move {
+                loop {
+                    let Some(group) = queue.lock().unwrap().pop_front() else {
+                        return Ok::<_, Error>(());
+                    };
+                    let values = client.read_group(requests, &group).await?;
+                    let mut results = results.lock().unwrap();
+                    for (i, value) in group.into_iter().zip(values) {
+                        results[i] = Some(value);
+                    }
+                }
+            }
+        }))
+        .await?;
+
+        Ok(results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|result| result.unwrap_or(Err(Error::Decode("property not read"))))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ObjectType;
+
+    fn analog_input(instance: u32) -> ObjectId {
+        ObjectId::new(ObjectType::ObjectAnalogInput, instance)
+    }
+
+    #[test]
+    fn groups_fit_the_max_apdu() {
+        // 3 + 7 + 16 * 3 = 58 for the first group, then the split object
+        let requests = vec![(analog_input(1), PropertyId::PropPresentValue); 5];
+        assert_eq!(groups(&requests, 60), vec![vec![0, 1, 2], vec![3, 4]]);
+
+        // a new object after a split is counted once
+        let requests = vec![
+            (analog_input(1), PropertyId::PropPresentValue),
+            (analog_input(1), PropertyId::PropPresentValue),
+            (analog_input(2), PropertyId::PropPresentValue),
+            (analog_input(2), PropertyId::PropPresentValue),
+        ];
+        assert_eq!(groups(&requests, 42), vec![vec![0, 1], vec![2, 3]]);
+    }
+
+    #[test]
+    fn string_properties_are_sized_larger() {
+        let requests = vec![
+            (analog_input(1), PropertyId::PropPresentValue),
+            (analog_input(1), PropertyId::PropObjectName),
+            (analog_input(1), PropertyId::PropDescription),
+        ];
+        // 3 + 7 + 16 + 72 = 98, without room for the description
+        assert_eq!(groups(&requests, 120), vec![vec![0, 1], vec![2]]);
+        assert_eq!(groups(&requests, 170), vec![vec![0, 1, 2]]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Management of BACnet/IP broadcast management devices (BBMDs): their
+//! broadcast distribution table (BDT), listing the peer BBMDs broadcasts
+//! are forwarded to, and their foreign device table (FDT).
+
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
+
+use crate::{
+    apdu::{self, bvll},
+    client::Client,
+    error::{DecodeError, Error},
+};
+
+const BDT_ENTRY_LEN: usize = 10;
+const FDT_ENTRY_LEN: usize = 10;
+
+/// A peer BBMD in a broadcast distribution table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BdtEntry {
+    pub addr: SocketAddrV4,
+    /// Broadcast distribution mask: all ones to forward broadcasts to the
+    /// BBMD itself, the subnet mask of the peer's network to send directed
+    /// broadcasts to it.
+    pub mask: Ipv4Addr,
+}
+
+impl BdtEntry {
+    /// An entry forwarding broadcasts to the BBMD itself.
+    pub fn new(addr: SocketAddrV4) -> Self {
+        Self {
+            addr,
+            mask: Ipv4Addr::BROADCAST,
+        }
+    }
+
+    fn encode(&self, message: &mut Vec<u8>) {
+        encode_addr(message, self.addr);
+        message.extend_from_slice(&self.mask.octets());
+    }
+
+    fn decode(entry: &[u8]) -> Self {
+        Self {
+            addr: decode_addr(entry),
+            mask: Ipv4Addr::new(entry[6], entry[7], entry[8], entry[9]),
+        }
+    }
+}
+
+/// A foreign device registered with a BBMD.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FdtEntry {
+    pub addr: SocketAddrV4,
+    /// Time-to-live the device registered with.
+    pub ttl: Duration,
+    /// Time left before the entry expires, grace period included.
+    pub remaining: Duration,
+}
+
+impl FdtEntry {
+    fn decode(entry: &[u8]) -> Self {
+        let seconds =
+            |i: usize| Duration::from_secs(u16::from_be_bytes([entry[i], entry[i + 1]]) as u64);
+        Self {
+            addr: decode_addr(entry),
+            ttl: seconds(6),
+            remaining: seconds(8),
+        }
+    }
+}
+
+fn encode_addr(message: &mut Vec<u8>, addr: SocketAddrV4) {
+    message.extend_from_slice(&addr.ip().octets());
+    message.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+fn decode_addr(entry: &[u8]) -> SocketAddrV4 {
+    SocketAddrV4::new(
+        Ipv4Addr::new(entry[0], entry[1], entry[2], entry[3]),
+        u16::from_be_bytes([entry[4], entry[5]]),
+    )
+}
+
+/// The result code of a BVLC-Result, 0 for success.
+fn result_code(payload: &[u8]) -> Result<u16, DecodeError> {
+    match payload {
+        [high, low] => Ok(u16::from_be_bytes([*high, *low])),
+        _ => Err(DecodeError("invalid BVLC result")),
+    }
+}
+
+fn entries<E>(
+    payload: &[u8],
+    len: usize,
+    decode: impl Fn(&[u8]) -> E,
+) -> Result<Vec<E>, DecodeError> {
+    if payload.len() % len != 0 {
+        return Err(DecodeError("truncated table entry"));
+    }
+    Ok(payload.chunks(len).map(decode).collect())
+}
+
+/// BBMD management, for clients connected to a BBMD. Requests are BVLL
+/// messages, so they aren't available through a [`crate::ClientPool`].
+impl Client {
+    /// Read the broadcast distribution table of the BBMD.
+    pub async fn read_bdt(&mut self) -> Result<Vec<BdtEntry>, Error> {
+        let message = apdu::bvll_message(bvll::READ_BDT, &[]);
+        match self.bvll_request(&message, bvll::READ_BDT_ACK).await? {
+            (bvll::READ_BDT_ACK, payload) => {
+                Ok(entries(&payload, BDT_ENTRY_LEN, BdtEntry::decode)?)
+            }
+            (_, payload) => Err(Error::BvllNak(result_code(&payload)?)),
+        }
+    }
+
+    /// Replace the broadcast distribution table of the BBMD.
+    ///
+    /// The table usually includes the BBMD itself. Devices may refuse the
+    /// write, e.g. when their table is configured locally only.
+    pub async fn write_bdt(&mut self, entries: &[BdtEntry]) -> Result<(), Error> {
+        let mut payload = Vec::with_capacity(entries.len() * BDT_ENTRY_LEN);
+        for entry in entries {
+            entry.encode(&mut payload);
+        }
+        let message = apdu::bvll_message(bvll::WRITE_BDT, &payload);
+        let (_, payload) = self.bvll_request(&message, bvll::RESULT).await?;
+        match result_code(&payload)? {
+            0 => Ok(()),
+            code => Err(Error::BvllNak(code)),
+        }
+    }
+
+    /// Read the foreign device table of the BBMD.
+    pub async fn read_foreign_device_table(&mut self) -> Result<Vec<FdtEntry>, Error> {
+        let message = apdu::bvll_message(bvll::READ_FDT, &[]);
+        match self.bvll_request(&message, bvll::READ_FDT_ACK).await? {
+            (bvll::READ_FDT_ACK, payload) => {
+                Ok(entries(&payload, FDT_ENTRY_LEN, FdtEntry::decode)?)
+            }
+            (_, payload) => Err(Error::BvllNak(result_code(&payload)?)),
+        }
+    }
+}
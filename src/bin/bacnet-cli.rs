@@ -1,46 +1,136 @@
 use clap::Parser;
 use eyre::{eyre, Result};
-use std::net::SocketAddr;
+use futures_util::StreamExt;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
 
 use async_bacnet::{
-    ApplicationDataValue, ApplicationDataValueWrite, Binary, Client, Enumerated, ObjectId,
-    ObjectType, PropertyId, ReadProperty, WriteProperty,
+    Client, CovNotification, Date, EncodedString, ObjectId, ObjectType, PacketDirection,
+    PropertyId, Service, Time, Units, Value, UNSPECIFIED,
 };
 
-#[derive(clap::ValueEnum, Clone, Debug)]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ApplicationDataValueArg {
+    /// Null, to relinquish a command at the write priority
+    Null,
     Boolean,
+    Unsigned,
+    Signed,
     Real,
+    Double,
     Enumerated,
+    /// Binary present value: true/false, on/off, active/inactive or 1/0
     EnumeratedBinary,
+    CharacterString,
+    /// A date as YYYY-MM-DD, `*` for any field
+    Date,
+    /// A time as HH:MM[:SS[.hh]]
+    Time,
+    /// A multi-state value, by state number or state text
+    State,
 }
 
 impl ApplicationDataValueArg {
-    fn to_value(&self, value: serde_json::Value) -> ApplicationDataValueWrite<'static> {
-        match &value {
-            serde_json::Value::Bool(v) => match self {
-                ApplicationDataValueArg::Boolean => ApplicationDataValueWrite::Boolean(*v),
-                ApplicationDataValueArg::EnumeratedBinary => {
-                    ApplicationDataValueWrite::Enumerated(Enumerated::Binary(if *v {
-                        Binary::On
-                    } else {
-                        Binary::Off
-                    }))
-                }
-                _ => panic!("invalid value type"),
-            },
-            serde_json::Value::Number(v) => match self {
-                ApplicationDataValueArg::Real => {
-                    ApplicationDataValueWrite::Real(v.as_f64().expect("invalid number") as f32)
-                }
-                _ => panic!("invalid value {value:?} for type {self:?}"),
-            },
-            _ => panic!("invalid value {value:?}"),
+    /// The type to write a property currently holding `value` with.
+    fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(_) => Some(Self::Boolean),
+            Value::Unsigned(_) => Some(Self::Unsigned),
+            Value::Signed(_) => Some(Self::Signed),
+            Value::Real(_) => Some(Self::Real),
+            Value::Double(_) => Some(Self::Double),
+            Value::Enumerated(_) => Some(Self::Enumerated),
+            Value::CharacterString(_) => Some(Self::CharacterString),
+            Value::Date(_) => Some(Self::Date),
+            Value::Time(_) => Some(Self::Time),
+            _ => None,
         }
     }
+
+    /// Parse a command-line value. State texts are resolved by the caller.
+    fn parse(&self, input: &str) -> Result<Value> {
+        let invalid = || eyre!("invalid {self:?} value `{input}`");
+        let value = match self {
+            Self::Null if input.eq_ignore_ascii_case("null") => Value::Null,
+            Self::Null => return Err(invalid()),
+            Self::Boolean => Value::Bool(parse_bool(input, false).ok_or_else(invalid)?),
+            Self::EnumeratedBinary => {
+                Value::Enumerated(parse_bool(input, true).ok_or_else(invalid)? as u32)
+            }
+            Self::Unsigned => Value::Unsigned(input.parse().map_err(|_| invalid())?),
+            Self::Signed => Value::Signed(input.parse().map_err(|_| invalid())?),
+            Self::Real => Value::Real(input.parse().map_err(|_| invalid())?),
+            Self::Double => Value::Double(input.parse().map_err(|_| invalid())?),
+            Self::Enumerated | Self::State => {
+                Value::Unsigned(input.parse().map_err(|_| invalid())?)
+            }
+            Self::CharacterString => Value::CharacterString(
+                // Accept JSON strings too, for escapes
+                serde_json::from_str::<String>(input).unwrap_or_else(|_| input.to_string()),
+            ),
+            Self::Date => Value::Date(parse_date(input).ok_or_else(invalid)?),
+            Self::Time => Value::Time(parse_time(input).ok_or_else(invalid)?),
+        };
+        Ok(match (self, value) {
+            (Self::Enumerated, Value::Unsigned(v)) => Value::Enumerated(v as u32),
+            (_, value) => value,
+        })
+    }
+}
+
+fn parse_bool(input: &str, binary: bool) -> Option<bool> {
+    match input.to_ascii_lowercase().as_str() {
+        "true" | "on" | "1" => Some(true),
+        "false" | "off" | "0" => Some(false),
+        "active" if binary => Some(true),
+        "inactive" if binary => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(field: &str, unspecified: T) -> Option<T> {
+    match field {
+        "*" => Some(unspecified),
+        field => field.parse().ok(),
+    }
+}
+
+fn parse_date(input: &str) -> Option<Date> {
+    let mut fields = input.splitn(3, '-');
+    let year = parse_field(fields.next()?, 1900 + UNSPECIFIED as u16)?;
+    let month = parse_field(fields.next()?, UNSPECIFIED)?;
+    let day = parse_field(fields.next()?, UNSPECIFIED)?;
+    Some(Date {
+        year,
+        month,
+        day,
+        weekday: UNSPECIFIED,
+    })
 }
 
+fn parse_time(input: &str) -> Option<Time> {
+    let (time, hundredths) = match input.split_once('.') {
+        Some((time, hundredths)) => (time, parse_field(hundredths, UNSPECIFIED)?),
+        None => (input, 0),
+    };
+    let mut fields = time.splitn(3, ':');
+    let hour = parse_field(fields.next()?, UNSPECIFIED)?;
+    let minute = parse_field(fields.next()?, UNSPECIFIED)?;
+    let second = match fields.next() {
+        Some(second) => parse_field(second, UNSPECIFIED)?,
+        None => 0,
+    };
+    Some(Time {
+        hour,
+        minute,
+        second,
+        hundredths,
+    })
+}
 
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
 #[repr(u32)]
@@ -107,31 +197,230 @@ pub enum ArgObjectType {
     Invalid = 1024,
 }
 
+/// Lifetime of the COV subscriptions made by `watch`, renewed as they expire.
+const COV_LIFETIME: Duration = Duration::from_secs(300);
+
 #[derive(Debug, Parser, Clone)]
 #[command(version)]
 struct BacnetCliArgs {
-    url: SocketAddr,
+    #[command(subcommand)]
+    command: Command,
+
+    /// Output format; streaming commands print one JSON object per line
+    #[clap(long, global = true, value_enum, default_value = "text")]
+    output: OutputFormat,
+
+    /// Shorthand for `--output json`
+    #[clap(long, global = true)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, clap::Subcommand, Clone)]
+enum Command {
+    /// Read a property, or the priority array, of an object
+    Read(ReadArgs),
+    /// Write a property of an object, or relinquish a priority
+    Write(WriteArgs),
+    /// Broadcast a Who-Is and print devices as they answer
+    Discover(DiscoverArgs),
+    /// List every object of a device with its name, present value and units
+    Scan(ScanArgs),
+    /// Print timestamped changes of an object's present value until interrupted
+    Watch(WatchArgs),
+    /// Measure a device's latency and request size limits, and recommend
+    /// settings for it
+    Profile(ProfileArgs),
+    /// Save a device's configuration files to a directory
+    Backup(BackupArgs),
+    /// Restore a device's configuration files saved by `backup`
+    Restore(BackupArgs),
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct ObjectArgs {
+    /// Device host name or IP address, with an optional port
+    url: String,
     object_type: ArgObjectType,
     instance: u32,
     #[clap(short, long, default_value = "85")]
     property: u32,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct ReadArgs {
+    #[command(flatten)]
+    object: ObjectArgs,
+
+    #[clap(long)]
+    priority_array: bool,
 
-    #[clap(short, long, requires = "write_type")]
-    write_value: Option<String>,
-    #[clap(short = 't', long, requires = "write_value")]
+    /// Print every character string of the property tagged with its
+    /// character set, including those that aren't UTF-8
+    #[clap(long, conflicts_with_all = ["priority_array", "convert_to"])]
+    all_encodings: bool,
+
+    /// Also print the frames received from the device in hex, e.g. to
+    /// attach to a support ticket when decoding looks wrong
+    #[clap(long)]
+    raw: bool,
+
+    /// Convert numeric present values to these units, by name or symbol,
+    /// e.g. degrees-fahrenheit or °F
+    #[clap(long)]
+    convert_to: Option<Units>,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct WriteArgs {
+    #[command(flatten)]
+    object: ObjectArgs,
+
+    /// Value to write
+    #[clap(required_unless_present = "clear_priority")]
+    value: Option<String>,
+    /// Type of the value, inferred from the property's current value when
+    /// omitted
+    #[clap(short = 't', long, requires = "value")]
     write_type: Option<ApplicationDataValueArg>,
 
-    #[clap(short = 'P', long, requires = "write_value", value_parser = clap::value_parser!(u8).range(1..=16))]
+    #[clap(short = 'P', long, value_parser = clap::value_parser!(u8).range(1..=16))]
     priority: Option<u8>,
 
-    #[clap(long, conflicts_with_all = ["write_value", "write_type", "property", "clear_priority"])]
-    priority_array: bool,
-
-    #[clap(long, conflicts_with_all = ["write_value", "write_type", "property"], value_parser = clap::value_parser!(u8).range(1..=16))]
+    #[clap(long, conflicts_with_all = ["value", "write_type", "priority"], value_parser = clap::value_parser!(u8).range(1..=16))]
     clear_priority: Option<u8>,
 }
 
-impl BacnetCliArgs {
+#[derive(Debug, clap::Args, Clone)]
+struct DiscoverArgs {
+    /// Broadcast address, e.g. 192.168.1.255, with an optional port
+    broadcast: String,
+    /// How long to wait for answers, in seconds
+    #[clap(short, long, default_value = "5")]
+    duration: u64,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct ScanArgs {
+    /// Device host name or IP address, with an optional port
+    url: String,
+
+    /// Convert numeric present values to these units, by name or symbol,
+    /// e.g. degrees-fahrenheit or °F
+    #[clap(long)]
+    convert_to: Option<Units>,
+
+    /// Write the description, units, limits, COV increment and notification
+    /// class of the objects, read once, to this JSON file
+    #[clap(long)]
+    metadata: Option<std::path::PathBuf>,
+
+    /// Also read the names of the objects in every character set they're
+    /// given in, tagged with it
+    #[clap(long)]
+    all_encodings: bool,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct WatchArgs {
+    /// Device host name or IP address, with an optional port
+    url: String,
+    object_type: ArgObjectType,
+    instance: u32,
+
+    /// Polling interval, e.g. 500ms, 5s or 1m
+    #[clap(short, long, default_value = "5s", value_parser = parse_duration)]
+    interval: Duration,
+
+    /// Subscribe to COV notifications instead of polling
+    #[clap(long)]
+    cov: bool,
+
+    /// Convert numeric present values to these units, by name or symbol,
+    /// e.g. degrees-fahrenheit or °F
+    #[clap(long)]
+    convert_to: Option<Units>,
+
+    /// Write the description, units, limits, COV increment and notification
+    /// class of the objects, read once, to this JSON file
+    #[clap(long)]
+    metadata: Option<std::path::PathBuf>,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct ProfileArgs {
+    /// Device host name or IP address, with an optional port
+    url: String,
+    /// Number of reads timed to measure latency and losses
+    #[clap(short, long, default_value = "10")]
+    samples: usize,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct BackupArgs {
+    /// Device host name or IP address, with an optional port
+    url: String,
+    /// Directory holding the backup
+    dir: std::path::PathBuf,
+    /// Password configured in the device for reinitialization
+    #[clap(long)]
+    password: Option<String>,
+}
+
+/// Parse a duration made of a number and an optional `ms`, `s`, `m` or `h`
+/// unit, seconds by default.
+fn parse_duration(arg: &str) -> Result<Duration, String> {
+    let split = arg
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{arg}`"))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("invalid duration unit `{unit}`")),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration `{arg}`"))
+}
+
+/// The current UTC time in RFC 3339 format, to the millisecond.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (days, time) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+impl ObjectArgs {
     fn object_id(&self) -> Result<ObjectId> {
         let object_type: ObjectType = (self.object_type as u32)
             .try_into()
@@ -144,16 +433,6 @@ impl BacnetCliArgs {
             .try_into()
             .map_err(|e| eyre!("invalid object property: {e}"))
     }
-
-    fn write_value(&self) -> Option<ApplicationDataValueWrite<'static>> {
-        match (&self.write_value, &self.write_type) {
-            (Some(value), Some(write_type)) => {
-                let json_value = serde_json::from_str(value).expect("invalid json");
-                Some(write_type.to_value(json_value))
-            }
-            _ => None,
-        }
-    }
 }
 
 #[tokio::main]
@@ -169,56 +448,616 @@ async fn main() -> Result<()> {
         .init();
 
     let args = BacnetCliArgs::parse();
-    let object_id = args.object_id()?;
-    let property_id = args.property_id()?;
+    let json = args.json || args.output == OutputFormat::Json;
+    match args.command {
+        Command::Read(args) => read(args, json).await,
+        Command::Write(args) => write(args, json).await,
+        Command::Discover(args) => discover(args, json).await,
+        Command::Scan(args) => scan(args, json).await,
+        Command::Watch(args) => watch(args, json).await,
+        Command::Profile(args) => profile(args, json).await,
+        Command::Backup(args) => backup(args, json).await,
+        Command::Restore(args) => restore(args, json).await,
+    }
+}
 
-    let mut client = Client::new(args.url)
+async fn connect(url: &str) -> Result<Client> {
+    Client::new(url)
         .await
-        .map_err(|e| eyre!("failed to create client: {e:?}"))?;
+        .map_err(|e| eyre!("failed to create client: {e:?}"))
+}
 
-    if let Some(priority) = args.clear_priority {
-        let request = WriteProperty::new(
-            object_id,
-            PropertyId::PropPresentValue,
-            Some(priority),
-            None,
-            ApplicationDataValueWrite::Null,
-        );
-        client
-            .write_property(request)
+/// Print a JSON document on its own line.
+fn print_json(value: &impl serde::Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+async fn read(args: ReadArgs, json: bool) -> Result<()> {
+    let object_id = args.object.object_id()?;
+    let property_id = args.object.property_id()?;
+    let mut client = connect(&args.object.url).await?;
+    let frames = args.raw.then(|| capture_frames(&mut client));
+    let raw = || frames.as_ref().map(|frames| frames.lock().unwrap().clone());
+    // Print the frames of a read that fails to decode, too
+    let failed = |e| {
+        if let Some(raw) = raw() {
+            raw.iter().for_each(|frame| eprintln!("raw {frame}"));
+        }
+        e
+    };
+
+    if args.all_encodings {
+        let strings = client
+            .read_strings(object_id, property_id)
             .await
-            .map_err(|e| eyre!("failed to clear priority {priority}: {e:?}"))?;
-        println!("priority {priority} cleared");
+            .map_err(|e| failed(eyre!("failed to read property: {e:?}")))?;
+        if json {
+            return print_json(&with_raw(
+                serde_json::json!({
+                    "object_type": format!("{:?}", object_id.object_type),
+                    "instance": object_id.id,
+                    "property": args.object.property,
+                    "strings": strings,
+                }),
+                raw(),
+            ));
+        }
+        strings
+            .iter()
+            .for_each(|string| println!("{}", with_charset(string)));
     } else if args.priority_array {
-        let request = ReadProperty::new(object_id, PropertyId::PropPriorityArray);
-        let ack = client
-            .read_property(request)
+        let priority_array = client
+            .read_priority_array(object_id)
             .await
-            .map_err(|e| eyre!("failed to read priority array: {e:?}"))?;
-        for (i, value) in ack.property_value.into_iter().enumerate() {
+            .map_err(|e| failed(eyre!("failed to read priority array: {e:?}")))?;
+        if json {
+            return print_json(&with_raw(serde_json::json!(priority_array), raw()));
+        }
+        for (i, slot) in priority_array.slots.iter().enumerate() {
             let priority = i + 1;
-            let v = value.map_err(|e| eyre!("failed to decode priority {priority}: {e:?}"))?;
-            println!("  priority {priority:>2}: {v}");
+            match slot {
+                Some(v) => println!("  priority {priority:>2}: {v}"),
+                None => println!("  priority {priority:>2}: null"),
+            }
         }
-    } else if let Some(write_value) = args.write_value() {
-        let request = WriteProperty::new(object_id, property_id, args.priority, None, write_value);
+    } else {
+        let value = client
+            .read_value(object_id, property_id)
+            .await
+            .map_err(|e| failed(eyre!("failed to read property: {e:?}")))?;
+        let units = match property_id {
+            PropertyId::PropPresentValue => point_units(&mut client, object_id).await,
+            _ => None,
+        };
+        let (value, units) = convert_units(value, units, args.convert_to)?;
+        match json {
+            true => print_json(&with_raw(
+                serde_json::json!({
+                    "object_type": format!("{:?}", object_id.object_type),
+                    "instance": object_id.id,
+                    "property": args.object.property,
+                    "value": value,
+                    "units": units,
+                }),
+                raw(),
+            ))?,
+            false => println!("{}", with_units(&value, units)),
+        }
+    }
+    if let (false, Some(raw)) = (json, raw()) {
+        raw.iter().for_each(|frame| println!("raw {frame}"));
+    }
+    Ok(())
+}
+
+/// The units of an object, `None` when it has none or they can't be read.
+async fn point_units(client: &mut Client, object_id: ObjectId) -> Option<Units> {
+    match client.point(object_id).units().await {
+        Ok(units) => units,
+        Err(e) => {
+            tracing::debug!("failed to read units: {e:?}");
+            None
+        }
+    }
+}
+
+/// Write the static metadata of `objects` to a JSON sidecar file, for
+/// consumers of the exported values.
+async fn write_metadata(
+    client: &mut Client,
+    objects: &[ObjectId],
+    path: &std::path::Path,
+) -> Result<()> {
+    let infos = client
+        .read_point_info(objects)
+        .await
+        .map_err(|e| eyre!("failed to read metadata: {e:?}"))?;
+    let objects = infos
+        .into_iter()
+        .map(|(object_id, info)| {
+            let mut object = serde_json::to_value(info)?;
+            object["object_type"] = format!("{:?}", object_id.object_type).into();
+            object["instance"] = object_id.id.into();
+            Ok(object)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let document = serde_json::to_string_pretty(&serde_json::json!({ "objects": objects }))?;
+    std::fs::write(path, document)
+        .map_err(|e| eyre!("failed to write metadata to {}: {e}", path.display()))
+}
+
+/// Convert a numeric value in `units` to `convert_to`, if given, returning
+/// the value and the units it is in. Doubles stay doubles, other numbers
+/// become reals.
+fn convert_units(
+    value: Value,
+    units: Option<Units>,
+    convert_to: Option<Units>,
+) -> Result<(Value, Option<Units>)> {
+    let number = match value {
+        Value::Real(v) => Some(v as f64),
+        Value::Double(v) => Some(v),
+        Value::Unsigned(v) => Some(v as f64),
+        Value::Signed(v) => Some(v as f64),
+        // Enumerated values are states, not measures
+        _ => None,
+    };
+    let (Some(number), Some(to)) = (number, convert_to) else {
+        return Ok((value, units));
+    };
+    let units = units.ok_or_else(|| eyre!("the value has no units to convert from"))?;
+    let converted = units
+        .convert(number, to)
+        .ok_or_else(|| eyre!("can't convert {units} to {to}"))?;
+    let converted = match value {
+        Value::Double(_) => Value::Double(converted),
+        _ => Value::Real(converted as f32),
+    };
+    Ok((converted, Some(to)))
+}
+
+/// A value followed by the symbol of its units, e.g. `72.1 °F`.
+fn with_units(value: &Value, units: Option<Units>) -> String {
+    match units {
+        Some(units) if units != Units::NO_UNITS => format!("{value} {units}"),
+        _ => value.to_string(),
+    }
+}
+
+/// Record the frames `client` receives, as hex.
+fn capture_frames(client: &mut Client) -> Arc<Mutex<Vec<String>>> {
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let captured = frames.clone();
+    client.set_packet_observer(move |direction, _, datagram| {
+        if direction == PacketDirection::Received {
+            captured.lock().unwrap().push(hex(datagram));
+        }
+    });
+    frames
+}
+
+/// Add the `raw` frames to a JSON document, when asked for.
+fn with_raw(mut document: serde_json::Value, raw: Option<Vec<String>>) -> serde_json::Value {
+    if let Some(raw) = raw {
+        document["raw"] = raw.into();
+    }
+    document
+}
+
+/// A string prefixed with its character set, e.g. `[Ucs2] Lüftung`.
+fn with_charset(string: &EncodedString) -> String {
+    format!("[{:?}] {string}", string.charset)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+async fn write(args: WriteArgs, json: bool) -> Result<()> {
+    let object_id = args.object.object_id()?;
+    let property_id = args.object.property_id()?;
+    let mut client = connect(&args.object.url).await?;
+
+    if let Some(priority) = args.clear_priority {
+        client
+            .relinquish(object_id, priority)
+            .await
+            .map_err(|e| eyre!("failed to clear priority {priority}: {e:?}"))?;
+        match json {
+            true => print_json(&serde_json::json!({ "cleared_priority": priority }))?,
+            false => println!("priority {priority} cleared"),
+        }
+    } else if let Some(input) = &args.value {
+        let value =
+            write_value(&mut client, object_id, property_id, args.write_type, input).await?;
         client
-            .write_property(request)
+            .write_value(object_id, property_id, &value, args.priority)
             .await
             .map_err(|e| eyre!("failed to write property: {e:?}"))?;
-        println!("write done");
-    } else {
-        let request = ReadProperty::new(object_id, property_id);
-        let ack = client
-            .read_property(request)
+        match json {
+            true => print_json(&serde_json::json!({ "written": value }))?,
+            false => println!("write done"),
+        }
+    }
+    Ok(())
+}
+
+/// Parse the value to write, inferring its type from the object and the
+/// property's current value when not given.
+async fn write_value(
+    client: &mut Client,
+    object_id: ObjectId,
+    property_id: PropertyId,
+    write_type: Option<ApplicationDataValueArg>,
+    input: &str,
+) -> Result<Value> {
+    let present_value = property_id == PropertyId::PropPresentValue;
+    let multi_state = client.point(object_id).is_multi_state();
+    let write_type = match write_type {
+        Some(write_type) => write_type,
+        None if input.eq_ignore_ascii_case("null") => ApplicationDataValueArg::Null,
+        None if present_value && multi_state => ApplicationDataValueArg::State,
+        None if present_value
+            && matches!(
+                object_id.object_type,
+                ObjectType::ObjectBinaryInput
+                    | ObjectType::ObjectBinaryOutput
+                    | ObjectType::ObjectBinaryValue
+            ) =>
+        {
+            ApplicationDataValueArg::EnumeratedBinary
+        }
+        None => {
+            let current = client
+                .read_value(object_id, property_id)
+                .await
+                .map_err(|e| eyre!("failed to read the current value to infer its type: {e:?}"))?;
+            ApplicationDataValueArg::of(&current)
+                .ok_or_else(|| eyre!("can't infer a type from `{current}`, use --write-type"))?
+        }
+    };
+
+    match write_type.parse(input) {
+        Err(_) if write_type == ApplicationDataValueArg::State => {
+            let state = client
+                .point(object_id)
+                .state_number(input)
+                .await
+                .map_err(|e| eyre!("invalid state `{input}`: {e:?}"))?;
+            Ok(Value::Unsigned(state as u64))
+        }
+        result => result,
+    }
+}
+
+async fn discover(args: DiscoverArgs, json: bool) -> Result<()> {
+    let broadcast = async_bacnet::resolve(&args.broadcast)
+        .await
+        .map_err(|e| eyre!("invalid broadcast address {}: {e}", args.broadcast))?;
+    let mut devices =
+        async_bacnet::discover::discover(broadcast, Some(Duration::from_secs(args.duration)))
             .await
-            .map_err(|e| eyre!("failed to read property: {e:?}"))?;
-        let value: ApplicationDataValue = ack
-            .property_value
-            .try_into()
-            .map_err(|e| eyre!("failed to parse property value: {e:?}"))?;
-        println!("{value:?}");
+            .map_err(|e| eyre!("failed to start discovery: {e:?}"))?;
+    while let Some(device) = devices.recv().await {
+        match device {
+            Ok(device) if json => print_json(&serde_json::json!({
+                "id": device.id,
+                "vendor_id": device.vendor_id,
+                "addr": device.addr,
+            }))?,
+            Ok(device) => println!(
+                "device {:>7}  vendor {:>4}  {}",
+                device.id, device.vendor_id, device.addr
+            ),
+            Err(e) => tracing::debug!("discovery error: {e:?}"),
+        }
+    }
+    Ok(())
+}
+
+async fn scan(args: ScanArgs, json: bool) -> Result<()> {
+    let mut client = connect(&args.url).await?;
+    // Devices without ReadPropertyMultiple are then read one property at a
+    // time from the start
+    if let Err(e) = client.services_supported().await {
+        tracing::debug!("failed to read services supported: {e:?}");
+    }
+    let object_list = client
+        .read_object_list()
+        .await
+        .map_err(|e| eyre!("failed to read object list: {e:?}"))?;
+    if let Some(path) = &args.metadata {
+        write_metadata(&mut client, &object_list, path).await?;
+    }
+
+    let requests = object_list
+        .iter()
+        .flat_map(|object_id| {
+            [
+                (*object_id, PropertyId::PropObjectName),
+                (*object_id, PropertyId::PropPresentValue),
+                (*object_id, PropertyId::PropUnits),
+            ]
+        })
+        .collect();
+    let results = client
+        .read_many(requests)
+        .await
+        .map_err(|e| eyre!("failed to read objects: {e:?}"))?;
+
+    for (object_id, properties) in object_list.iter().zip(results.chunks(3)) {
+        let name = match &properties[0] {
+            Ok(Value::CharacterString(name)) => name.clone(),
+            _ => String::new(),
+        };
+        let names = match args.all_encodings {
+            true => client
+                .read_strings(*object_id, PropertyId::PropObjectName)
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::debug!("failed to read names of {object_id:?}: {e:?}");
+                    Vec::new()
+                }),
+            false => Vec::new(),
+        };
+        let units = match &properties[2] {
+            Ok(value) => value.as_u32().map(Units),
+            Err(_) => None,
+        };
+        // Values that can't be converted are shown as read
+        let (present_value, units) = match properties[1].clone() {
+            Ok(value) => {
+                let (value, units) =
+                    convert_units(value.clone(), units, args.convert_to).unwrap_or((value, units));
+                (Some(value), units)
+            }
+            Err(_) => (None, units),
+        };
+        if json {
+            let mut document = serde_json::json!({
+                "object_type": format!("{:?}", object_id.object_type),
+                "instance": object_id.id,
+                "name": name,
+                "present_value": present_value,
+                "units": units,
+            });
+            if args.all_encodings {
+                document["names"] = serde_json::json!(names);
+            }
+            print_json(&document)?;
+            continue;
+        }
+        let present_value =
+            present_value.map_or("-".to_string(), |value| with_units(&value, units));
+        let name = match args.all_encodings {
+            true => names
+                .iter()
+                .map(with_charset)
+                .collect::<Vec<_>>()
+                .join(" / "),
+            false => name,
+        };
+        println!(
+            "{:?} {:<8} {:<32} {}",
+            object_id.object_type, object_id.id, name, present_value
+        );
     }
+    Ok(())
+}
 
+async fn profile(args: ProfileArgs, json: bool) -> Result<()> {
+    let mut client = connect(&args.url).await?;
+    let profile = client
+        .profile(args.samples)
+        .await
+        .map_err(|e| eyre!("failed to profile device: {e:?}"))?;
+    if json {
+        return print_json(&profile);
+    }
+
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+    println!("device          {}", profile.device.id);
+    println!(
+        "max-APDU        {}",
+        or_unknown(profile.max_apdu.map(|max_apdu| max_apdu.to_string()))
+    );
+    println!(
+        "segmentation    {}",
+        or_unknown(
+            profile
+                .segmentation
+                .map(|segmentation| format!("{segmentation:?}"))
+        )
+    );
+    println!(
+        "latency         min {:?}  median {:?}  max {:?}",
+        profile.latency.min, profile.latency.median, profile.latency.max
+    );
+    println!(
+        "lost            {}/{} ({:.0}%)",
+        profile.lost,
+        profile.requests,
+        profile.loss_rate() * 100.0
+    );
+    match (profile.rpm_max_properties, profile.rpm_max_ack) {
+        (Some(properties), Some(ack)) => println!(
+            "RPM             up to {properties} properties, {ack} octets{}",
+            if profile.segments_responses {
+                ", segmented"
+            } else {
+                ""
+            }
+        ),
+        _ => println!("RPM             not supported"),
+    }
+    println!("recommended     timeout {:?}", profile.recommended_timeout);
+    if let Some(max_apdu) = profile.recommended_max_apdu {
+        println!("                max-APDU {max_apdu}");
+    }
     Ok(())
 }
+
+async fn backup(args: BackupArgs, json: bool) -> Result<()> {
+    let mut client = connect(&args.url).await?;
+    let backup = client
+        .backup(args.password.as_deref())
+        .await
+        .map_err(|e| eyre!("failed to back up device: {e:?}"))?;
+    backup
+        .save(&args.dir)
+        .map_err(|e| eyre!("failed to save backup to {}: {e}", args.dir.display()))?;
+    match json {
+        true => print_json(&serde_json::json!({ "files": backup.files.len() })),
+        false => {
+            println!(
+                "{} files saved to {}",
+                backup.files.len(),
+                args.dir.display()
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn restore(args: BackupArgs, json: bool) -> Result<()> {
+    let backup = async_bacnet::Backup::load(&args.dir)
+        .map_err(|e| eyre!("failed to load backup from {}: {e}", args.dir.display()))?;
+    let mut client = connect(&args.url).await?;
+    client
+        .restore(&backup, args.password.as_deref())
+        .await
+        .map_err(|e| eyre!("failed to restore device: {e:?}"))?;
+    match json {
+        true => print_json(&serde_json::json!({ "files": backup.files.len() })),
+        false => {
+            println!("{} files restored", backup.files.len());
+            Ok(())
+        }
+    }
+}
+
+async fn watch(args: WatchArgs, json: bool) -> Result<()> {
+    let object_type: ObjectType = (args.object_type as u32)
+        .try_into()
+        .map_err(|e| eyre!("invalid object type: {e}"))?;
+    let object_id = ObjectId::new(object_type, args.instance);
+    let mut client = connect(&args.url).await?;
+    if let Some(path) = &args.metadata {
+        write_metadata(&mut client, &[object_id], path).await?;
+    }
+    let units = point_units(&mut client, object_id).await;
+
+    let print = |value: &Value| {
+        let (value, units) = convert_units(value.clone(), units, args.convert_to)?;
+        match json {
+            true => print_json(&serde_json::json!({
+                "time": timestamp(),
+                "value": value,
+                "units": units,
+            })),
+            false => {
+                println!("{}  {}", timestamp(), with_units(&value, units));
+                Ok(())
+            }
+        }
+    };
+
+    let cov = args.cov
+        && match client.services_supported().await {
+            Ok(services) if !services.supports(Service::SubscribeCov) => {
+                tracing::warn!("device doesn't support COV subscriptions, polling instead");
+                false
+            }
+            _ => true,
+        };
+    if !cov {
+        let stream = client.poll(object_id, PropertyId::PropPresentValue, args.interval);
+        tokio::pin!(stream);
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+                value = stream.next() => match value {
+                    Some(Ok(value)) => print(&value)?,
+                    Some(Err(e)) => tracing::warn!("read failed: {e:?}"),
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+
+    let (sender, mut receiver) = unbounded_channel();
+    let mut process_id = subscribe_present_value(&mut client, object_id, &sender)
+        .await
+        .map_err(|e| eyre!("failed to subscribe: {e:?}"))?;
+
+    // COV sends the current value only on changes, so show where we start
+    match client
+        .read_value(object_id, PropertyId::PropPresentValue)
+        .await
+    {
+        Ok(value) => print(&value)?,
+        Err(e) => tracing::warn!("read failed: {e:?}"),
+    }
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            result = client.receive_notifications(Duration::from_secs(1)) => {
+                result.map_err(|e| eyre!("failed to receive notifications: {e:?}"))?;
+            }
+        }
+        while let Ok(value) = receiver.try_recv() {
+            print(&value)?;
+        }
+
+        let expiring = client
+            .subscriptions()
+            .active()
+            .iter()
+            .find(|subscription| subscription.process_id == process_id)
+            .and_then(|subscription| subscription.remaining)
+            .map_or(true, |remaining| remaining < COV_LIFETIME / 4);
+        if expiring {
+            let renewed = subscribe_present_value(&mut client, object_id, &sender)
+                .await
+                .map_err(|e| eyre!("failed to renew subscription: {e:?}"))?;
+            let _ = client.unsubscribe_cov(process_id).await;
+            process_id = renewed;
+        }
+    }
+
+    client
+        .unsubscribe_cov(process_id)
+        .await
+        .map_err(|e| eyre!("failed to unsubscribe: {e:?}"))
+}
+
+/// Subscribe to COV notifications of an object, sending present values to
+/// `sender`.
+async fn subscribe_present_value(
+    client: &mut Client,
+    object_id: ObjectId,
+    sender: &UnboundedSender<Value>,
+) -> Result<u32, async_bacnet::Error> {
+    let sender = sender.clone();
+    client
+        .subscribe_cov(
+            object_id,
+            false,
+            Some(COV_LIFETIME),
+            move |notification: &CovNotification| {
+                notification
+                    .values
+                    .iter()
+                    .filter(|v| v.property_id == PropertyId::PropPresentValue as u32)
+                    .for_each(|v| {
+                        let _ = sender.send(v.value.clone());
+                    });
+            },
+        )
+        .await
+}
@@ -0,0 +1,217 @@
+//! BACnet CharacterString decoding in every character set.
+//!
+//! embedded-bacnet only decodes UTF-8 strings. Devices localized for other
+//! markets send names in UCS-2, ISO 8859-1 or DBCS, so [`Client::read_strings`]
+//! returns each string tagged with its character set, decoded when possible
+//! and raw otherwise.
+
+use crate::{
+    apdu::{tag, Decoder, TagKind},
+    client::Client,
+    error::{DecodeError, Error},
+    NetworkIo, ObjectId, PropertyId,
+};
+
+/// Character set of a BACnet CharacterString.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CharacterSet {
+    Utf8,
+    /// IBM/Microsoft DBCS.
+    Dbcs,
+    JisX0208,
+    Ucs4,
+    Ucs2,
+    Iso8859_1,
+    Other(u8),
+}
+
+impl From<u8> for CharacterSet {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Utf8,
+            1 => Self::Dbcs,
+            2 => Self::JisX0208,
+            3 => Self::Ucs4,
+            4 => Self::Ucs2,
+            5 => Self::Iso8859_1,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// A character string tagged with the character set it was encoded in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncodedString {
+    pub charset: CharacterSet,
+    /// Decoded text, or `None` when the character set can't be decoded
+    /// (DBCS, JIS X 0208) or the data is invalid for it.
+    pub text: Option<String>,
+    /// The string content as sent by the device, without the charset octet.
+    pub raw: Vec<u8>,
+}
+
+impl EncodedString {
+    /// Decode the content of a CharacterString (charset octet included).
+    pub fn decode(content: &[u8]) -> Self {
+        let Some((charset, raw)) = content.split_first() else {
+            return Self {
+                charset: CharacterSet::Utf8,
+                text: Some(String::new()),
+                raw: Vec::new(),
+            };
+        };
+        let charset = CharacterSet::from(*charset);
+        let text = match charset {
+            CharacterSet::Utf8 => String::from_utf8(raw.to_vec()).ok(),
+            CharacterSet::Iso8859_1 => Some(raw.iter().map(|b| *b as char).collect()),
+            CharacterSet::Ucs2 if raw.len() % 2 == 0 => {
+                let units = raw.chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]]));
+                char::decode_utf16(units)
+                    .collect::<Result<String, _>>()
+                    .ok()
+            }
+            CharacterSet::Ucs4 if raw.len() % 4 == 0 => raw
+                .chunks(4)
+                .map(|c| char::from_u32(u32::from_be_bytes([c[0], c[1], c[2], c[3]])))
+                .collect(),
+            _ => None,
+        };
+        Self {
+            charset,
+            text,
+            raw: raw.to_vec(),
+        }
+    }
+
+    /// The decoded text, or a lossy UTF-8 rendering of the raw bytes.
+    pub fn text_lossy(&self) -> String {
+        match &self.text {
+            Some(text) => text.clone(),
+            None => String::from_utf8_lossy(&self.raw).into_owned(),
+        }
+    }
+}
+
+impl std::fmt::Display for EncodedString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text_lossy())
+    }
+}
+
+/// Decode every CharacterString found in an encoded property value,
+/// including those nested in arrays, lists and constructed data.
+pub(crate) fn decode_strings(data: &[u8]) -> Result<Vec<EncodedString>, DecodeError> {
+    let mut decoder = Decoder::new(data);
+    let mut strings = Vec::new();
+    while !decoder.is_empty() {
+        let header = decoder.read_tag()?;
+        if !matches!(header.kind, TagKind::Value(_)) {
+            continue;
+        }
+        let content = decoder.bytes(header.len())?;
+        if header.is_application(tag::CHARACTER_STRING) {
+            strings.push(EncodedString::decode(content));
+        }
+    }
+    Ok(strings)
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read a property and decode all character strings it holds, whatever
+    /// their character set.
+    ///
+    /// Unlike [`Client::read_property`], strings that aren't UTF-8 are
+    /// returned tagged with their character set instead of failing to
+    /// decode. Devices exposing names in several locales typically return
+    /// them as an array or list, hence the `Vec`.
+    pub async fn read_strings(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+    ) -> Result<Vec<EncodedString>, Error<T>> {
+        let data = self
+            .read_property_raw(object_id, property_id as u32, None)
+            .await?;
+        Ok(decode_strings(&data)?)
+    }
+
+    /// Read a single character string property, whatever its character set.
+    pub async fn read_string(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+    ) -> Result<EncodedString, Error<T>> {
+        self.read_strings(object_id, property_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(Error::Decode("property holds no character string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(charset: u8, raw: &[u8]) -> EncodedString {
+        let mut content = vec![charset];
+        content.extend_from_slice(raw);
+        EncodedString::decode(&content)
+    }
+
+    #[test]
+    fn utf8() {
+        let string = decode(0, "Lüftung".as_bytes());
+        assert_eq!(string.charset, CharacterSet::Utf8);
+        assert_eq!(string.text.as_deref(), Some("Lüftung"));
+        assert_eq!(decode(0, &[0xff, 0xfe]).text, None);
+    }
+
+    #[test]
+    fn iso_8859_1() {
+        let string = decode(5, &[b'L', 0xfc, b'f', b't', b'u', b'n', b'g']);
+        assert_eq!(string.charset, CharacterSet::Iso8859_1);
+        assert_eq!(string.text.as_deref(), Some("Lüftung"));
+    }
+
+    #[test]
+    fn ucs2() {
+        let string = decode(4, &[0x00, b'L', 0x00, 0xfc, 0x6e, 0x29]);
+        assert_eq!(string.charset, CharacterSet::Ucs2);
+        assert_eq!(string.text.as_deref(), Some("Lü温"));
+        // Odd length
+        let string = decode(4, &[0x00, b'L', 0x00]);
+        assert_eq!(string.text, None);
+        assert_eq!(string.raw, [0x00, b'L', 0x00]);
+    }
+
+    #[test]
+    fn ucs4() {
+        let string = decode(3, &[0x00, 0x00, 0x00, b'L', 0x00, 0x01, 0xf3, 0x21]);
+        assert_eq!(string.charset, CharacterSet::Ucs4);
+        assert_eq!(string.text.as_deref(), Some("L\u{1f321}"));
+        // A surrogate isn't a scalar value
+        assert_eq!(decode(3, &[0x00, 0x00, 0xd8, 0x00]).text, None);
+        assert_eq!(decode(3, &[0x00, 0x00, 0x00]).text, None);
+    }
+
+    #[test]
+    fn dbcs_is_kept_raw() {
+        let string = decode(1, &[0x88, 0xea]);
+        assert_eq!(string.charset, CharacterSet::Dbcs);
+        assert_eq!(string.text, None);
+        assert_eq!(string.raw, [0x88, 0xea]);
+        assert_eq!(string.text_lossy(), "\u{fffd}\u{fffd}");
+    }
+
+    #[test]
+    fn empty() {
+        let string = EncodedString::decode(&[]);
+        assert_eq!(string.charset, CharacterSet::Utf8);
+        assert_eq!(string.text.as_deref(), Some(""));
+        assert!(string.raw.is_empty());
+        assert_eq!(decode(4, &[]).text.as_deref(), Some(""));
+    }
+}
@@ -1,4 +1,11 @@
-use std::net::SocketAddr;
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
 
 use embedded_bacnet::{
     application_protocol::services::{
@@ -7,24 +14,46 @@ use embedded_bacnet::{
         read_property_multiple::{ReadPropertyMultiple, ReadPropertyMultipleAck},
         write_property::WriteProperty,
     },
-    simple::Bacnet,
+    simple::{Bacnet, NetworkIo},
 };
+use tokio::time::{timeout_at, Instant};
 
-use crate::{error::Error, io::TokioUdpIo};
+use crate::{
+    apdu::{self, Apdu, Decoder, Encoder},
+    batch::DeviceInfo,
+    error::Error,
+    io::{resolve, with_request_timeout, PacketDirection, ToPeerAddr, TokioUdpIo},
+    point::PointMetadata,
+    subscription::SubscriptionRegistry,
+    ObjectId,
+};
 
 const BUF_SIZE: usize = 1500;
 
-/// Async BACnet client wrapping `embedded_bacnet::simple::Bacnet<TokioUdpIo>`.
+/// Async BACnet client wrapping `embedded_bacnet::simple::Bacnet<T>`.
 ///
 /// Manages an internal buffer so callers don't need to provide one.
 /// With the `alloc` feature enabled in embedded-bacnet, returned types are
 /// fully owned and don't borrow from the buffer.
-pub struct Client {
-    inner: Bacnet<TokioUdpIo>,
+///
+/// The transport defaults to BACnet/IP over UDP; any [`NetworkIo`]
+/// implementation can be plugged in with [`Client::from_io`]. Services that
+/// embedded-bacnet doesn't model are sent through a clone of the transport,
+/// using their own invoke IDs.
+pub struct Client<T: NetworkIo = TokioUdpIo> {
+    inner: Bacnet<T>,
+    io: T,
+    peer: Option<SocketAddr>,
     buf: Vec<u8>,
+    invoke_id: u8,
+    metadata: HashMap<u32, PointMetadata>,
+    device: DeviceInfo,
+    deadline: Option<Instant>,
+    timeout: Option<Duration>,
+    subscriptions: SubscriptionRegistry,
 }
 
-impl std::fmt::Debug for Client {
+impl<T: NetworkIo> std::fmt::Debug for Client<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Client").finish_non_exhaustive()
     }
@@ -32,16 +61,178 @@ impl std::fmt::Debug for Client {
 
 impl Client {
     /// Create a new client connected to the given BACnet device address.
-    pub async fn new(peer: SocketAddr) -> Result<Self, Error> {
-        let io = TokioUdpIo::new(peer).await?;
-        Ok(Self {
-            inner: Bacnet::new(io),
+    ///
+    /// Host names are resolved, and addresses without a port use the
+    /// standard BACnet/IP port 47808, see [`crate::ToPeerAddr`].
+    pub async fn new(peer: impl ToPeerAddr) -> Result<Self, Error> {
+        let peer = resolve(peer).await?;
+        Ok(Self::from_udp(TokioUdpIo::new(peer).await?))
+    }
+
+    pub(crate) fn from_udp(io: TokioUdpIo) -> Self {
+        let peer = io.peer();
+        let mut client = Self::from_io(io);
+        client.peer = Some(peer);
+        client
+    }
+
+    /// Pass every datagram the client sends or receives to `observer`, with
+    /// the device's address, e.g. to write a capture file while debugging
+    /// interoperability. Clones of the client share the observer.
+    pub fn set_packet_observer(
+        &mut self,
+        observer: impl Fn(PacketDirection, SocketAddr, &[u8]) + Send + Sync + 'static,
+    ) {
+        self.io.set_packet_observer(Some(Arc::new(observer)));
+    }
+
+    /// Send a BVLL message without NPDU and return the function and payload
+    /// of the first answer that is either a `reply` or a BVLC-Result.
+    pub(crate) async fn bvll_request(
+        &mut self,
+        message: &[u8],
+        reply: u8,
+    ) -> Result<(u8, Vec<u8>), Error> {
+        let (deadline, timeout) = (self.deadline, self.timeout);
+        bounded(deadline, timeout, self.bvll_exchange(message, reply)).await
+    }
+
+    async fn bvll_exchange(&mut self, message: &[u8], reply: u8) -> Result<(u8, Vec<u8>), Error> {
+        self.io.write(message).await.map_err(Error::Transport)?;
+        loop {
+            let n = self
+                .io
+                .read(&mut self.buf)
+                .await
+                .map_err(Error::Transport)?;
+            match apdu::parse_bvll(&self.buf[..n]) {
+                Some((function, payload))
+                    if function == reply || function == apdu::bvll::RESULT =>
+                {
+                    return Ok((function, payload.to_vec()))
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// Receive and dispatch COV notifications for `duration`, or until the
+    /// client's deadline.
+    ///
+    /// Only BACnet/IP clients can wait this way, as socket read timeouts
+    /// have to be told apart from transport failures.
+    pub async fn receive_notifications(&mut self, duration: Duration) -> Result<(), Error> {
+        let end = Instant::now() + duration;
+        let end = self.deadline.map_or(end, |deadline| deadline.min(end));
+        loop {
+            let n = match timeout_at(end, self.io.read(&mut self.buf)).await {
+                Err(_elapsed) => return Ok(()),
+                Ok(Ok(n)) => n,
+                Ok(Err(err)) if err.kind() == std::io::ErrorKind::TimedOut => continue,
+                Ok(Err(err)) => return Err(Error::Transport(err)),
+            };
+            if let Some(ack) = self.subscriptions.handle_frame(&self.buf[..n]) {
+                self.io.write(&ack).await.map_err(Error::Transport)?;
+            }
+        }
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Create a client sending requests through the given transport, e.g.
+    /// MS/TP, BACnet/SC or a test double.
+    pub fn from_io(io: T) -> Self {
+        Self {
+            inner: Bacnet::new(io.clone()),
+            io,
+            peer: None,
             buf: vec![0u8; BUF_SIZE],
-        })
+            invoke_id: 128,
+            metadata: HashMap::new(),
+            device: DeviceInfo::default(),
+            deadline: None,
+            timeout: None,
+            subscriptions: SubscriptionRegistry::new(),
+        }
+    }
+
+    /// How long each answer of the device is waited for, `None` when the
+    /// transport's own timeout applies, 5 seconds for BACnet/IP.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Wait up to `duration` for each answer of the device, e.g. longer for
+    /// devices slow to answer large reads.
+    ///
+    /// The timeout is handed to each read of the transport, see
+    /// [`crate::request_timeout`], so other clients sharing the transport
+    /// keep theirs. It applies to every datagram, so a segmented response
+    /// can take longer in total; bound whole operations with a deadline.
+    pub fn set_timeout(&mut self, duration: Duration) {
+        self.timeout = Some(duration);
+    }
+
+    /// Use `duration` as the timeout of the operations made through the
+    /// returned guard, e.g. a short one for health checks. The previous
+    /// timeout is restored when the guard is dropped.
+    pub fn with_timeout(&mut self, duration: Duration) -> TimeoutGuard<'_, T> {
+        let previous = self.timeout.replace(duration);
+        TimeoutGuard {
+            client: self,
+            previous,
+        }
+    }
+
+    /// Bound every following operation by an absolute deadline, or remove
+    /// the bound with `None`.
+    ///
+    /// Unlike the per-datagram socket timeout, the deadline covers whole
+    /// operations, including segmented responses and multi-request helpers,
+    /// so a caller with a fixed time budget can hand it down as is. Expired
+    /// operations fail with [`Error::DeadlineExceeded`].
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Bound the operations made through the returned guard by `deadline`.
+    /// The previous deadline is restored when the guard is dropped.
+    ///
+    /// An earlier deadline already set on the client is kept.
+    pub fn with_deadline(&mut self, deadline: Instant) -> DeadlineGuard<'_, T> {
+        let previous = self.deadline;
+        self.deadline = Some(previous.map_or(deadline, |previous| previous.min(deadline)));
+        DeadlineGuard {
+            client: self,
+            previous,
+        }
+    }
+
+    /// Use `registry` for the following COV subscriptions, to share process
+    /// identifiers and callbacks with other clients.
+    pub fn set_subscriptions(&mut self, registry: SubscriptionRegistry) {
+        self.subscriptions = registry;
+    }
+
+    pub fn subscriptions(&self) -> &SubscriptionRegistry {
+        &self.subscriptions
+    }
+
+    pub(crate) fn device_info(&mut self) -> &mut DeviceInfo {
+        &mut self.device
+    }
+
+    /// Address of the device, for clients communicating over UDP.
+    pub(crate) fn peer(&self) -> Option<SocketAddr> {
+        self.peer
     }
 
-    /// Access the inner `Bacnet<TokioUdpIo>` for advanced operations.
-    pub fn inner(&mut self) -> &mut Bacnet<TokioUdpIo> {
+    /// Access the inner `Bacnet<T>` for advanced operations.
+    pub fn inner(&mut self) -> &mut Bacnet<T> {
         &mut self.inner
     }
 
@@ -50,33 +241,376 @@ impl Client {
         &mut self.buf
     }
 
+    /// Drop the point metadata cached by [`crate::Point`] handles, e.g. after
+    /// the device was reconfigured.
+    pub fn clear_point_cache(&mut self) {
+        self.metadata.clear();
+    }
+
+    pub(crate) fn point_metadata(&mut self, object_id: ObjectId) -> &mut PointMetadata {
+        self.metadata
+            .entry(apdu::object_id_value(object_id))
+            .or_default()
+    }
+
     /// Read a single property from a BACnet object.
-    pub async fn read_property(&mut self, request: ReadProperty) -> Result<ReadPropertyAck<'_>, Error> {
-        let ack = self.inner.read_property(&mut self.buf, request).await?;
-        Ok(ack)
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn read_property(
+        &mut self,
+        request: ReadProperty,
+    ) -> Result<ReadPropertyAck<'_>, Error<T>> {
+        bounded(
+            self.deadline,
+            self.timeout,
+            self.inner.read_property(&mut self.buf, request),
+        )
+        .await
     }
 
     /// Read multiple properties from multiple BACnet objects.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     pub async fn read_property_multiple(
         &mut self,
         request: ReadPropertyMultiple<'_>,
-    ) -> Result<ReadPropertyMultipleAck<'_>, Error> {
+    ) -> Result<ReadPropertyMultipleAck<'_>, Error<T>> {
+        bounded(
+            self.deadline,
+            self.timeout,
+            self.inner.read_property_multiple(&mut self.buf, request),
+        )
+        .await
+    }
+
+    /// Write a property value to a BACnet object.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn write_property(&mut self, request: WriteProperty<'_>) -> Result<(), Error<T>> {
+        bounded(
+            self.deadline,
+            self.timeout,
+            self.inner.write_property(&mut self.buf, request),
+        )
+        .await
+    }
+
+    /// Send a WHO-IS request and return the first I-Am response, if any.
+    pub async fn who_is(&mut self) -> Result<Option<IAm>, Error<T>> {
+        bounded(
+            self.deadline,
+            self.timeout,
+            self.inner.who_is(&mut self.buf),
+        )
+        .await
+    }
+
+    /// Send a confirmed request encoded by this crate and return the service
+    /// ack data, which is empty for a simple ack. Segmented acks are
+    /// reassembled.
+    pub(crate) async fn confirmed_request(
+        &mut self,
+        service: u8,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Error<T>> {
+        let (deadline, timeout) = (self.deadline, self.timeout);
+        bounded(deadline, timeout, self.transact(service, payload)).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            name = "request",
+            skip(self, payload),
+            fields(invoke_id = self.invoke_id, peer = ?self.peer, rtt_ms = tracing::field::Empty)
+        )
+    )]
+    async fn transact(&mut self, service: u8, payload: &[u8]) -> Result<Vec<u8>, Error<T>> {
+        let invoke_id = self.invoke_id;
+        self.invoke_id = self.invoke_id.wrapping_add(1);
+        #[cfg(feature = "tracing")]
+        let started = Instant::now();
+        let result = self.exchange(invoke_id, service, payload).await;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("rtt_ms", started.elapsed().as_secs_f64() * 1000.0);
+        result
+    }
+
+    async fn exchange(
+        &mut self,
+        invoke_id: u8,
+        service: u8,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Error<T>> {
+        let request = apdu::confirmed_request(invoke_id, service, payload);
+        self.io
+            .write(&apdu::unicast_frame(&request, true))
+            .await
+            .map_err(Error::Transport)?;
+
+        let mut data = Vec::new();
+        let mut next_sequence = 0u8;
+        loop {
+            let n = self
+                .io
+                .read(&mut self.buf)
+                .await
+                .map_err(Error::Transport)?;
+            let Some(received) = apdu::parse_frame(&self.buf[..n]) else {
+                continue;
+            };
+            // Requests of the device, e.g. COV notifications, carry invoke
+            // IDs of its own, which can match the one of this request
+            if matches!(
+                received.apdu,
+                Apdu::ConfirmedRequest { .. } | Apdu::UnconfirmedRequest { .. }
+            ) {
+                if let Some(ack) = self.subscriptions.handle_frame(&self.buf[..n]) {
+                    self.io.write(&ack).await.map_err(Error::Transport)?;
+                }
+                continue;
+            }
+            if received.apdu.invoke_id() != Some(invoke_id) {
+                continue;
+            }
+            match received.apdu {
+                Apdu::SimpleAck { .. } => return Ok(data),
+                Apdu::ComplexAck {
+                    segment: None,
+                    data: chunk,
+                    ..
+                } => {
+                    data.extend_from_slice(chunk);
+                    return Ok(data);
+                }
+                Apdu::ComplexAck {
+                    segment: Some(segment),
+                    data: chunk,
+                    ..
+                } => {
+                    if segment.sequence == next_sequence {
+                        data.extend_from_slice(chunk);
+                        next_sequence = next_sequence.wrapping_add(1);
+                    }
+                    let ack = apdu::segment_ack(invoke_id, next_sequence.wrapping_sub(1), 1);
+                    self.io
+                        .write(&apdu::unicast_frame(&ack, false))
+                        .await
+                        .map_err(Error::Transport)?;
+                    if !segment.more && segment.sequence.wrapping_add(1) == next_sequence {
+                        return Ok(data);
+                    }
+                }
+                Apdu::Error { data: error, .. } => {
+                    return Err(Error::Service(apdu::parse_error(error)))
+                }
+                Apdu::Reject { reason, .. } => return Err(Error::Reject(reason)),
+                Apdu::Abort { reason, .. } => return Err(Error::Abort(reason)),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Read a property and return its value as encoded on the wire, without
+    /// the enclosing context tags.
+    ///
+    /// Takes any property identifier, so proprietary properties (512 and
+    /// above) can be read, and leaves decoding to the caller, so encodings
+    /// this crate doesn't know don't fail the read.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn read_property_raw(
+        &mut self,
+        object_id: ObjectId,
+        property_id: u32,
+        array_index: Option<u32>,
+    ) -> Result<Vec<u8>, Error<T>> {
+        let mut request = Encoder::new();
+        request.context_object_id(0, object_id);
+        request.context_enumerated(1, property_id);
+        if let Some(index) = array_index {
+            request.context_unsigned(2, index as u64);
+        }
         let ack = self
-            .inner
-            .read_property_multiple(&mut self.buf, request)
+            .confirmed_request(apdu::confirmed::READ_PROPERTY, &request.into_bytes())
             .await?;
-        Ok(ack)
+
+        let mut decoder = Decoder::new(&ack);
+        decoder.expect_context(0)?;
+        decoder.expect_context(1)?;
+        decoder.context(2)?;
+        Ok(decoder.enclosed(3)?.to_vec())
     }
 
-    /// Write a property value to a BACnet object.
-    pub async fn write_property(&mut self, request: WriteProperty<'_>) -> Result<(), Error> {
-        self.inner.write_property(&mut self.buf, request).await?;
+    /// Write a property from a value already encoded on the wire, without
+    /// the enclosing context tags, optionally at a command priority.
+    ///
+    /// The counterpart of [`Client::read_property_raw`]: `data` is sent as
+    /// is, tags included.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, data))
+    )]
+    pub async fn write_property_raw(
+        &mut self,
+        object_id: ObjectId,
+        property_id: u32,
+        array_index: Option<u32>,
+        data: &[u8],
+        priority: Option<u8>,
+    ) -> Result<(), Error<T>> {
+        let mut request = Encoder::new();
+        request.context_object_id(0, object_id);
+        request.context_enumerated(1, property_id);
+        if let Some(index) = array_index {
+            request.context_unsigned(2, index as u64);
+        }
+        request.opening(3);
+        request.raw(data);
+        request.closing(3);
+        if let Some(priority) = priority {
+            request.context_unsigned(4, priority as u64);
+        }
+        self.confirmed_request(apdu::confirmed::WRITE_PROPERTY, &request.into_bytes())
+            .await?;
         Ok(())
     }
+}
 
-    /// Send a WHO-IS request and return the first I-Am response, if any.
-    pub async fn who_is(&mut self) -> Result<Option<IAm>, Error> {
-        let iam = self.inner.who_is(&mut self.buf).await?;
-        Ok(iam)
+/// A client whose operations are bounded by a deadline, see
+/// [`Client::with_deadline`].
+#[derive(Debug)]
+pub struct DeadlineGuard<'a, T: NetworkIo = TokioUdpIo> {
+    client: &'a mut Client<T>,
+    previous: Option<Instant>,
+}
+
+impl<T: NetworkIo> Deref for DeadlineGuard<'_, T> {
+    type Target = Client<T>;
+
+    fn deref(&self) -> &Client<T> {
+        &*self.client
+    }
+}
+
+impl<T: NetworkIo> DerefMut for DeadlineGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Client<T> {
+        &mut *self.client
+    }
+}
+
+impl<T: NetworkIo> Drop for DeadlineGuard<'_, T> {
+    fn drop(&mut self) {
+        self.client.deadline = self.previous;
+    }
+}
+
+/// A client using a different timeout, see [`Client::with_timeout`].
+#[derive(Debug)]
+pub struct TimeoutGuard<'a, T: NetworkIo = TokioUdpIo> {
+    client: &'a mut Client<T>,
+    previous: Option<Duration>,
+}
+
+impl<T: NetworkIo> Deref for TimeoutGuard<'_, T> {
+    type Target = Client<T>;
+
+    fn deref(&self) -> &Client<T> {
+        &*self.client
+    }
+}
+
+impl<T: NetworkIo> DerefMut for TimeoutGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Client<T> {
+        &mut *self.client
+    }
+}
+
+impl<T: NetworkIo> Drop for TimeoutGuard<'_, T> {
+    fn drop(&mut self) {
+        self.client.timeout = self.previous;
+    }
+}
+
+/// Run `future` to completion with `timeout` for each read, or fail once
+/// `deadline` has passed.
+async fn bounded<T: NetworkIo, R, E>(
+    deadline: Option<Instant>,
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<R, E>>,
+) -> Result<R, Error<T>>
+where
+    Error<T>: From<E>,
+{
+    let future = with_request_timeout(timeout, future);
+    match deadline {
+        None => Ok(future.await?),
+        Some(deadline) => match timeout_at(deadline, future).await {
+            Ok(result) => Ok(result?),
+            Err(_elapsed) => Err(Error::DeadlineExceeded),
+        },
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        subscription::CovNotification,
+        testing::{MockIo, VirtualDevice},
+        ObjectType, PropertyId, Value,
+    };
+
+    #[tokio::test]
+    async fn notification_sharing_the_invoke_id_is_dispatched() {
+        let object_id = ObjectId::new(ObjectType::ObjectAnalogValue, 1);
+        let mut device = VirtualDevice::new(1234);
+        device.add_object(
+            object_id,
+            [(PropertyId::PropPresentValue, Value::Real(21.5))],
+        );
+        let device_id = device.device_id();
+        let io = MockIo::new(device);
+        let mut client = Client::from_io(io.clone());
+
+        let notifications = Arc::new(Mutex::new(Vec::<CovNotification>::new()));
+        let received = notifications.clone();
+        let process_id = client
+            .subscribe_cov(object_id, true, None, move |notification| {
+                received.lock().unwrap().push(notification.clone());
+            })
+            .await
+            .unwrap();
+
+        // Sent by the device with the invoke ID of the client's next request
+        let mut notification = Encoder::new();
+        notification.context_unsigned(0, process_id as u64);
+        notification.context_object_id(1, device_id);
+        notification.context_object_id(2, object_id);
+        notification.context_unsigned(3, 0);
+        notification.opening(4);
+        notification.context_enumerated(0, PropertyId::PropPresentValue as u32);
+        notification.opening(2);
+        notification.real(22.0);
+        notification.closing(2);
+        notification.closing(4);
+        let request = apdu::confirmed_request(
+            client.invoke_id,
+            apdu::confirmed::COV_NOTIFICATION,
+            &notification.into_bytes(),
+        );
+        io.inject(apdu::unicast_frame(&request, true));
+
+        let requests = io.device().requests();
+        let value = client
+            .read_value(object_id, PropertyId::PropPresentValue)
+            .await
+            .unwrap();
+        assert_eq!(value, Value::Real(21.5));
+
+        let notifications = notifications.lock().unwrap();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].values[0].value, Value::Real(22.0));
+        // The read request and the Simple-ACK of the notification
+        assert_eq!(io.device().requests(), requests + 2);
     }
 }
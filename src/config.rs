@@ -0,0 +1,296 @@
+//! Managing device configuration: copying the configuration of a device's
+//! objects to another device, e.g. when replacing a failed controller with a
+//! spare of the same model, and bringing properties to desired values.
+
+use log::debug;
+
+use crate::{
+    client::Client, error::Error, io::TokioUdpIo, value::Value, NetworkIo, ObjectId, ObjectType,
+    PropertyId,
+};
+
+/// property-list
+const PROPERTY_LIST: u32 = 371;
+
+/// Properties holding identity, status or runtime data rather than
+/// configuration, never copied.
+const NOT_CONFIGURATION: &[u32] = &[
+    0,   // acked-transitions
+    12,  // application-software-version
+    30,  // device-address-binding
+    36,  // event-state
+    44,  // firmware-revision
+    56,  // local-date
+    57,  // local-time
+    62,  // max-apdu-length-accepted
+    70,  // model-name
+    75,  // object-identifier
+    76,  // object-list
+    79,  // object-type
+    81,  // out-of-service
+    85,  // present-value
+    87,  // priority-array
+    96,  // protocol-object-types-supported
+    97,  // protocol-services-supported
+    98,  // protocol-version
+    103, // reliability
+    107, // segmentation-supported
+    111, // status-flags
+    112, // system-status
+    120, // vendor-identifier
+    121, // vendor-name
+    130, // event-time-stamps
+    131, // log-buffer
+    139, // protocol-revision
+    141, // record-count
+    145, // total-record-count
+    152, // active-cov-subscriptions
+    155, // database-revision
+    209, // structured-object-list
+    PROPERTY_LIST,
+];
+
+/// Configuration properties tried on objects without a `property-list`.
+const COMMON_CONFIGURATION: &[u32] = &[
+    4,   // active-text
+    17,  // notification-class
+    22,  // cov-increment
+    25,  // deadband
+    28,  // description
+    35,  // event-enable
+    45,  // high-limit
+    46,  // inactive-text
+    52,  // limit-enable
+    59,  // low-limit
+    65,  // max-pres-value
+    69,  // min-pres-value
+    72,  // notify-type
+    77,  // object-name
+    104, // relinquish-default
+    110, // state-text
+    113, // time-delay
+    117, // units
+];
+
+/// Outcome of [`clone_config`].
+#[derive(Debug)]
+pub struct CloneReport<T: NetworkIo = TokioUdpIo> {
+    /// Properties written to the target, by object and property identifier.
+    pub copied: Vec<(ObjectId, u32)>,
+    /// Properties the target refused to write as read-only.
+    pub read_only: Vec<(ObjectId, u32)>,
+    /// Source objects the target doesn't have.
+    pub missing_objects: Vec<ObjectId>,
+    /// Properties that couldn't be read from the source or written to the
+    /// target for another reason.
+    pub failed: Vec<(ObjectId, u32, Error<T>)>,
+}
+
+impl<T: NetworkIo> Default for CloneReport<T> {
+    fn default() -> Self {
+        Self {
+            copied: Vec::new(),
+            read_only: Vec::new(),
+            missing_objects: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// The configuration properties of an object: its `property-list`
+    /// without identity, status and runtime properties, or common
+    /// configuration properties when the object has no property list.
+    async fn configuration_properties(
+        &mut self,
+        object_id: ObjectId,
+    ) -> Result<Vec<u32>, Error<T>> {
+        let properties = match self.read_property_raw(object_id, PROPERTY_LIST, None).await {
+            Ok(data) => Value::decode_list(&data)?
+                .iter()
+                .filter_map(Value::as_u32)
+                .collect(),
+            Err(Error::Service(err)) if err.is_unknown_property() => COMMON_CONFIGURATION.to_vec(),
+            Err(err) => return Err(err),
+        };
+        Ok(properties
+            .into_iter()
+            .filter(|property_id| !NOT_CONFIGURATION.contains(property_id))
+            .collect())
+    }
+}
+
+/// Copy the configuration properties of the objects of `source` selected
+/// by `object_filter` to the objects with the same identifiers in `target`.
+///
+/// Values are copied as encoded, so constructed values such as schedules
+/// are copied too. Identity, status and runtime properties, including
+/// present values, aren't copied, nor are the device objects. Properties
+/// the target refuses to write are reported rather than failing the copy;
+/// only transport failures stop it.
+pub async fn clone_config<T: NetworkIo + Clone>(
+    source: &mut Client<T>,
+    target: &mut Client<T>,
+    object_filter: impl Fn(&ObjectId) -> bool,
+) -> Result<CloneReport<T>, Error<T>> {
+    let mut report = CloneReport::default();
+    for object_id in source.read_object_list().await? {
+        if object_id.object_type == ObjectType::ObjectDevice || !object_filter(&object_id) {
+            continue;
+        }
+        let properties = match source.configuration_properties(object_id).await {
+            Ok(properties) => properties,
+            Err(err) if err.is_transport() => return Err(err),
+            Err(err) => {
+                report.failed.push((object_id, PROPERTY_LIST, err));
+                continue;
+            }
+        };
+        for property_id in properties {
+            let data = match source.read_property_raw(object_id, property_id, None).await {
+                Ok(data) => data,
+                // Objects without a property list lack most common properties
+                Err(Error::Service(err)) if err.is_unknown_property() => continue,
+                Err(err) if err.is_transport() => return Err(err),
+                Err(err) => {
+                    report.failed.push((object_id, property_id, err));
+                    continue;
+                }
+            };
+            match target
+                .write_property_raw(object_id, property_id, None, &data, None)
+                .await
+            {
+                Ok(()) => report.copied.push((object_id, property_id)),
+                Err(Error::Service(err)) if err.is_unknown_object() => {
+                    report.missing_objects.push(object_id);
+                    break;
+                }
+                Err(Error::Service(err)) if err.is_write_access_denied() => {
+                    report.read_only.push((object_id, property_id));
+                }
+                Err(err) if err.is_transport() => return Err(err),
+                Err(err) => {
+                    debug!("Writing {object_id:?} property {property_id} failed: {err:?}");
+                    report.failed.push((object_id, property_id, err));
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// A value a property should hold, see [`Client::ensure`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DesiredPropertyState {
+    pub object_id: ObjectId,
+    pub property_id: PropertyId,
+    pub value: Value,
+    /// Command priority to write at, `None` to write without one.
+    pub priority: Option<u8>,
+}
+
+impl DesiredPropertyState {
+    pub fn new(object_id: ObjectId, property_id: PropertyId, value: Value) -> Self {
+        Self {
+            object_id,
+            property_id,
+            value,
+            priority: None,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Whether the device holds the desired value. Numbers are compared
+    /// by value, so an enumerated 1 matches a desired unsigned 1.
+    fn is_held(&self, actual: &Value) -> bool {
+        *actual == self.value
+            || actual
+                .as_f64()
+                .zip(self.value.as_f64())
+                .is_some_and(|(actual, desired)| actual == desired)
+    }
+}
+
+/// A property that held another value than the desired one.
+#[derive(Debug, Clone)]
+pub struct Drift {
+    pub desired: DesiredPropertyState,
+    /// The value read before writing.
+    pub actual: Value,
+}
+
+/// Outcome of [`Client::ensure`].
+#[derive(Debug)]
+pub struct EnsureReport<T: NetworkIo = TokioUdpIo> {
+    /// Properties already holding their desired value, left untouched.
+    pub in_sync: Vec<DesiredPropertyState>,
+    /// Properties that had drifted and were written.
+    pub applied: Vec<Drift>,
+    /// Properties that couldn't be read or written.
+    pub failed: Vec<(DesiredPropertyState, Error<T>)>,
+}
+
+impl<T: NetworkIo> Default for EnsureReport<T> {
+    fn default() -> Self {
+        Self {
+            in_sync: Vec::new(),
+            applied: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Bring properties to their desired values: read them all in batches,
+    /// then write only those holding another value.
+    ///
+    /// Running it again with the same states writes nothing unless the
+    /// device changed in between. Commandable properties are compared by
+    /// their effective value, which may come from another priority than
+    /// the one written at. Per property failures are reported; transport
+    /// failures stop the run.
+    pub async fn ensure(
+        &mut self,
+        points: Vec<DesiredPropertyState>,
+    ) -> Result<EnsureReport<T>, Error<T>> {
+        let requests = points
+            .iter()
+            .map(|point| (point.object_id, point.property_id))
+            .collect();
+        let current = self.read_many(requests).await?;
+        let mut report = EnsureReport::default();
+        for (desired, current) in points.into_iter().zip(current) {
+            let actual = match current {
+                Ok(actual) => actual,
+                Err(err) => {
+                    report.failed.push((desired, err));
+                    continue;
+                }
+            };
+            if desired.is_held(&actual) {
+                report.in_sync.push(desired);
+                continue;
+            }
+            match self
+                .write_value(
+                    desired.object_id,
+                    desired.property_id,
+                    &desired.value,
+                    desired.priority,
+                )
+                .await
+            {
+                Ok(()) => report.applied.push(Drift { desired, actual }),
+                Err(err) if err.is_transport() => return Err(err),
+                Err(err) => report.failed.push((desired, err)),
+            }
+        }
+        Ok(report)
+    }
+}
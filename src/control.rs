@@ -0,0 +1,228 @@
+//! Typed reads of Loop objects and the properties they reference.
+
+use crate::{
+    apdu::{self, Decoder},
+    client::Client,
+    error::{DecodeError, Error},
+    value::Value,
+    NetworkIo, ObjectId, PropertyId,
+};
+
+/// A property of an object in the same device
+/// (BACnetObjectPropertyReference).
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ObjectPropertyReference {
+    pub object_id: ObjectId,
+    pub property_id: u32,
+    pub array_index: Option<u32>,
+}
+
+impl ObjectPropertyReference {
+    pub fn new(object_id: ObjectId, property_id: PropertyId) -> Self {
+        Self {
+            object_id,
+            property_id: property_id as u32,
+            array_index: None,
+        }
+    }
+
+    pub(crate) fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let object_id = apdu::decode_object_id(decoder.expect_context(0)?)?;
+        let property_id = apdu::decode_unsigned(decoder.expect_context(1)?) as u32;
+        let array_index = decoder.context_unsigned(2)?.map(|i| i as u32);
+        Ok(Self {
+            object_id,
+            property_id,
+            array_index,
+        })
+    }
+}
+
+/// Control action of a loop (BACnetAction).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Action {
+    /// The output increases when the controlled variable rises above the
+    /// setpoint.
+    Direct,
+    /// The output increases when the controlled variable falls below the
+    /// setpoint.
+    Reverse,
+    Other(u32),
+}
+
+impl From<u32> for Action {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::Direct,
+            1 => Self::Reverse,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Proportional, integral and derivative constants of a loop. Each is
+/// `None` when the loop doesn't have the property.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PidGains {
+    pub proportional: Option<f32>,
+    pub integral: Option<f32>,
+    pub derivative: Option<f32>,
+}
+
+/// Configuration of a Loop object, see [`Client::read_loop`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoopConfig {
+    pub object_id: ObjectId,
+    pub controlled_variable: ObjectPropertyReference,
+    pub manipulated_variable: ObjectPropertyReference,
+    /// `None` when the loop controls to its own `setpoint` property.
+    pub setpoint_reference: Option<ObjectPropertyReference>,
+    pub setpoint: f32,
+    pub gains: PidGains,
+    pub action: Action,
+}
+
+impl LoopConfig {
+    /// The properties the loop reads or writes, with the name of the loop
+    /// property holding each reference.
+    pub fn references(&self) -> Vec<(&'static str, ObjectPropertyReference)> {
+        let mut references = vec![
+            ("controlled-variable-reference", self.controlled_variable),
+            ("manipulated-variable-reference", self.manipulated_variable),
+        ];
+        if let Some(setpoint) = self.setpoint_reference {
+            references.push(("setpoint-reference", setpoint));
+        }
+        references
+    }
+}
+
+fn real(value: &Value) -> Option<f32> {
+    match value {
+        Value::Real(v) => Some(*v),
+        Value::Double(v) => Some(*v as f32),
+        _ => None,
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read a property holding a BACnetObjectPropertyReference, such as the
+    /// controlled- or manipulated-variable-reference of a loop.
+    pub async fn read_reference(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+    ) -> Result<ObjectPropertyReference, Error<T>> {
+        let data = self
+            .read_property_raw(object_id, property_id as u32, None)
+            .await?;
+        Ok(ObjectPropertyReference::decode(&mut Decoder::new(&data))?)
+    }
+
+    /// Read the `setpoint-reference` of a loop (BACnetSetpointReference),
+    /// `None` when the loop uses its own `setpoint` property.
+    pub async fn read_setpoint_reference(
+        &mut self,
+        loop_id: ObjectId,
+    ) -> Result<Option<ObjectPropertyReference>, Error<T>> {
+        let data = self
+            .read_property_raw(loop_id, PropertyId::PropSetpointReference as u32, None)
+            .await?;
+        let mut decoder = Decoder::new(&data);
+        if !decoder.opening(0)? {
+            return Ok(None);
+        }
+        let reference = ObjectPropertyReference::decode(&mut decoder)?;
+        decoder.expect_closing(0)?;
+        Ok(Some(reference))
+    }
+
+    /// Read the PID constants of a loop in one batch.
+    pub async fn read_pid_gains(&mut self, loop_id: ObjectId) -> Result<PidGains, Error<T>> {
+        let results = self
+            .read_many(vec![
+                (loop_id, PropertyId::PropProportionalConstant),
+                (loop_id, PropertyId::PropIntegralConstant),
+                (loop_id, PropertyId::PropDerivativeConstant),
+            ])
+            .await?;
+        let mut gains = results.into_iter().map(|result| match result {
+            Ok(value) => real(&value)
+                .map(Some)
+                .ok_or(Error::Decode("loop constant is not real")),
+            Err(Error::Service(err)) if err.is_unknown_property() => Ok(None),
+            Err(err) => Err(err),
+        });
+        let mut next = || gains.next().unwrap_or(Ok(None));
+        Ok(PidGains {
+            proportional: next()?,
+            integral: next()?,
+            derivative: next()?,
+        })
+    }
+
+    /// Read the `action` of a loop.
+    pub async fn read_loop_action(&mut self, loop_id: ObjectId) -> Result<Action, Error<T>> {
+        let action = self
+            .read_value(loop_id, PropertyId::PropAction)
+            .await?
+            .as_u32()
+            .ok_or(DecodeError("action is not enumerated"))?;
+        Ok(Action::from(action))
+    }
+
+    /// Read the references, setpoint, constants and action of a loop.
+    pub async fn read_loop(&mut self, loop_id: ObjectId) -> Result<LoopConfig, Error<T>> {
+        let controlled_variable = self
+            .read_reference(loop_id, PropertyId::PropControlledVariableReference)
+            .await?;
+        let manipulated_variable = self
+            .read_reference(loop_id, PropertyId::PropManipulatedVariableReference)
+            .await?;
+        let setpoint_reference = self.read_setpoint_reference(loop_id).await?;
+        let setpoint = real(&self.read_value(loop_id, PropertyId::PropSetpoint).await?)
+            .ok_or(DecodeError("setpoint is not real"))?;
+        let gains = self.read_pid_gains(loop_id).await?;
+        let action = self.read_loop_action(loop_id).await?;
+        Ok(LoopConfig {
+            object_id: loop_id,
+            controlled_variable,
+            manipulated_variable,
+            setpoint_reference,
+            setpoint,
+            gains,
+            action,
+        })
+    }
+
+    /// Read the property a reference points to.
+    ///
+    /// A dangling reference fails with the device's unknown-object or
+    /// unknown-property error, see [`crate::ServiceError`].
+    pub async fn resolve_reference(
+        &mut self,
+        reference: &ObjectPropertyReference,
+    ) -> Result<Value, Error<T>> {
+        let data = self
+            .read_property_raw(
+                reference.object_id,
+                reference.property_id,
+                reference.array_index,
+            )
+            .await?;
+        Ok(Value::decode_all(&data)?)
+    }
+
+    /// The setpoint a loop controls to: the referenced property when the
+    /// loop has a setpoint reference, its own `setpoint` otherwise.
+    pub async fn read_effective_setpoint(&mut self, loop_id: ObjectId) -> Result<Value, Error<T>> {
+        match self.read_setpoint_reference(loop_id).await? {
+            Some(reference) => self.resolve_reference(&reference).await,
+            None => self.read_value(loop_id, PropertyId::PropSetpoint).await,
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Reads of the device object.
+
+use log::debug;
+
+use crate::{
+    client::Client, error::Error, value::Value, NetworkIo, ObjectId, ObjectType, PropertyId,
+};
+
+/// Instance number addressing the device object of whichever device
+/// receives the request. Also used by references that aren't configured.
+pub(crate) const WILDCARD_INSTANCE: u32 = 4_194_303;
+
+/// The device object of whichever device receives the request.
+pub(crate) fn wildcard_device() -> ObjectId {
+    ObjectId::new(ObjectType::ObjectDevice, WILDCARD_INSTANCE)
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read the `object-list` of the device.
+    ///
+    /// Devices whose object list doesn't fit in one response are read one
+    /// element at a time.
+    pub async fn read_object_list(&mut self) -> Result<Vec<ObjectId>, Error<T>> {
+        let device = wildcard_device();
+        let values = match self.read_list(device, PropertyId::PropObjectList).await {
+            Ok(values) => values,
+            Err(err) if err.is_transport() => return Err(err),
+            Err(err) => {
+                debug!("Reading object list at once failed ({err:?}), reading elements");
+                let len = self
+                    .read_value_at(device, PropertyId::PropObjectList, 0)
+                    .await?
+                    .as_u32()
+                    .ok_or(Error::Decode("object list length is not unsigned"))?;
+                let mut values = Vec::with_capacity(len as usize);
+                for i in 1..=len {
+                    values.push(
+                        self.read_value_at(device, PropertyId::PropObjectList, i)
+                            .await?,
+                    );
+                }
+                values
+            }
+        };
+        Ok(values
+            .into_iter()
+            .filter_map(|value| match value {
+                Value::ObjectId(object_id) => Some(object_id),
+                _ => None,
+            })
+            .collect())
+    }
+}
@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, ops::RangeInclusive, time::Duration};
 
 use embedded_bacnet::{
     application_protocol::{
@@ -14,14 +14,20 @@ use embedded_bacnet::{
 };
 use log::{debug, info};
 use tokio::{
-    sync::mpsc::{self, Receiver},
-    time::timeout,
+    sync::mpsc::{self, Receiver, Sender},
+    time::{timeout, Instant, MissedTickBehavior},
 };
 
-use crate::{error::Error, io::TokioUdpIo};
+use crate::{
+    apdu::{self, Encoder},
+    error::Error,
+    io::TokioUdpIo,
+    listener::Notification,
+    shard::Sharding,
+};
 
 /// A BACnet device found during discovery.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Device {
     /// The device identifier (instance number of the BACnet device object).
     pub id: u32,
@@ -35,6 +41,7 @@ pub struct Device {
 ///
 /// The `addr` should be a broadcast address (e.g. `192.168.1.255:47808`).
 /// Discovery runs for `duration` (default: 2 minutes) or until the channel is dropped.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug"))]
 pub async fn discover(
     addr: SocketAddr,
     duration: Option<Duration>,
@@ -123,3 +130,261 @@ pub async fn discover(
 
     Ok(receiver)
 }
+
+/// Settings for [`discover_continuously`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryOptions {
+    /// Time between two Who-Is broadcasts.
+    pub interval: Duration,
+    /// Device instance ranges asked in turn, one per Who-Is, so that the
+    /// devices of a large network don't all answer at once. Empty asks
+    /// every device each time.
+    pub ranges: Vec<RangeInclusive<u32>>,
+    /// Rotations through the ranges a known device may leave unanswered
+    /// before it is reported gone.
+    pub missed_rounds: u32,
+    /// The sharding of the collectors sharing the network and the index of
+    /// this one, to only report the devices it owns. `None` reports every
+    /// device.
+    pub shard: Option<(Sharding, u32)>,
+}
+
+impl DiscoveryOptions {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            ranges: Vec::new(),
+            missed_rounds: 3,
+            shard: None,
+        }
+    }
+
+    /// Whether this collector reports the device `instance`.
+    fn owns(&self, instance: u32) -> bool {
+        match &self.shard {
+            Some((sharding, collector)) => sharding.owns(*collector, instance),
+            None => true,
+        }
+    }
+
+    /// The Who-Is broadcast for the given round.
+    fn who_is(&self, round: usize) -> Vec<u8> {
+        let mut request = Encoder::new();
+        if !self.ranges.is_empty() {
+            let range = &self.ranges[round % self.ranges.len()];
+            request.context_unsigned(0, *range.start() as u64);
+            request.context_unsigned(1, *range.end() as u64);
+        }
+        let who_is = apdu::unconfirmed_request(apdu::unconfirmed::WHO_IS, &request.into_bytes());
+        apdu::broadcast_frame(&who_is)
+    }
+
+    /// How long a device may stay silent before it is reported gone.
+    fn expiry(&self) -> Duration {
+        self.interval * self.ranges.len().max(1) as u32 * self.missed_rounds.max(1)
+    }
+}
+
+/// A change of the devices found by [`discover_continuously`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiscoveryEvent {
+    /// A device answered for the first time, again after it was reported
+    /// gone, or from a new address.
+    Appeared(Device),
+    /// A device left [`DiscoveryOptions::missed_rounds`] rotations
+    /// unanswered.
+    Disappeared(Device),
+}
+
+/// Broadcast Who-Is to `addr` every [`DiscoveryOptions::interval`] until
+/// the returned channel is dropped, and yield the devices that appear and
+/// disappear, so that long-running services pick up devices plugged in
+/// later.
+///
+/// I-Am broadcasts devices send on startup are picked up between two
+/// Who-Is too.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug"))]
+pub async fn discover_continuously(
+    addr: SocketAddr,
+    options: DiscoveryOptions,
+) -> Result<Receiver<Result<DiscoveryEvent, Error>>, Error> {
+    let io = TokioUdpIo::new_broadcast(addr).await?;
+    let (sender, receiver) = mpsc::channel(1000);
+    tokio::spawn(run_discovery(io, addr, options, sender));
+    Ok(receiver)
+}
+
+/// Discover the devices answering Who-Is sent to `addr` until `sender` is
+/// dropped.
+async fn run_discovery(
+    io: TokioUdpIo,
+    addr: SocketAddr,
+    options: DiscoveryOptions,
+    sender: Sender<Result<DiscoveryEvent, Error>>,
+) {
+    let socket = io.socket();
+    let mut buf = vec![0u8; 1500];
+    let mut ticks = tokio::time::interval(options.interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut round = 0;
+    let mut known: HashMap<u32, (Device, Instant)> = HashMap::new();
+    loop {
+        let received = tokio::select! {
+            _ = ticks.tick() => {
+                let sent = socket.send_to(&options.who_is(round), addr).await;
+                round += 1;
+                if let Err(err) = sent {
+                    if sender.send(Err(err.into())).await.is_err() {
+                        break;
+                    }
+                }
+                if !expire(&mut known, options.expiry(), &sender).await {
+                    break;
+                }
+                continue;
+            }
+            received = socket.recv_from(&mut buf) => received,
+        };
+        let (n, source) = match received {
+            Ok(received) => received,
+            Err(err) => {
+                if sender.send(Err(err.into())).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+        let Some(Ok(Notification::IAm(device))) = Notification::decode(&buf[..n], source) else {
+            continue;
+        };
+        if !options.owns(device.id) {
+            continue;
+        }
+        let previous = known.insert(device.id, (device, Instant::now()));
+        if previous.is_some_and(|(previous, _)| previous == device) {
+            continue;
+        }
+        debug!("Device {} appeared at {}", device.id, device.addr);
+        if sender
+            .send(Ok(DiscoveryEvent::Appeared(device)))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    info!("Continuous discovery stopped");
+}
+
+/// Report and forget the devices silent for longer than `expiry`, returning
+/// whether the receiver is still listening.
+async fn expire(
+    known: &mut HashMap<u32, (Device, Instant)>,
+    expiry: Duration,
+    sender: &Sender<Result<DiscoveryEvent, Error>>,
+) -> bool {
+    let gone: Vec<u32> = known
+        .iter()
+        .filter(|(_, (_, seen))| seen.elapsed() > expiry)
+        .map(|(id, _)| *id)
+        .collect();
+    for id in gone {
+        let Some((device, _)) = known.remove(&id) else {
+            continue;
+        };
+        debug!("Device {id} disappeared from {}", device.addr);
+        if sender
+            .send(Ok(DiscoveryEvent::Disappeared(device)))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+    }
+    !sender.is_closed()
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::{UdpDevice, VirtualDevice};
+
+    async fn discovery(
+        device: &UdpDevice,
+        options: DiscoveryOptions,
+    ) -> Receiver<Result<DiscoveryEvent, Error>> {
+        let io = TokioUdpIo::new(device.local_addr()).await.unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+        tokio::spawn(run_discovery(io, device.local_addr(), options, sender));
+        receiver
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn devices_of_other_collectors_are_ignored() {
+        let device = UdpDevice::bind(VirtualDevice::new(1234), "127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let sharding = Sharding::Ranges(vec![vec![0..=999], vec![1000..=1999]]);
+
+        let options = DiscoveryOptions {
+            shard: Some((sharding.clone(), 1)),
+            ..DiscoveryOptions::new(Duration::from_secs(1))
+        };
+        let mut events = discovery(&device, options).await;
+        let expected = Device {
+            id: 1234,
+            vendor_id: 0,
+            addr: device.local_addr(),
+        };
+        assert_eq!(
+            events.recv().await.unwrap().unwrap(),
+            DiscoveryEvent::Appeared(expected)
+        );
+
+        let options = DiscoveryOptions {
+            shard: Some((sharding, 0)),
+            ..DiscoveryOptions::new(Duration::from_secs(1))
+        };
+        let mut events = discovery(&device, options).await;
+        let event = timeout(Duration::from_secs(10), events.recv()).await;
+        assert!(event.is_err(), "unexpected event: {event:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_devices_disappear_after_missed_rounds() {
+        let device = UdpDevice::bind(VirtualDevice::new(1234), "127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let options = DiscoveryOptions {
+            missed_rounds: 2,
+            ..DiscoveryOptions::new(Duration::from_secs(1))
+        };
+        let mut events = discovery(&device, options).await;
+        let expected = Device {
+            id: 1234,
+            vendor_id: 0,
+            addr: device.local_addr(),
+        };
+        assert_eq!(
+            events.recv().await.unwrap().unwrap(),
+            DiscoveryEvent::Appeared(expected)
+        );
+        // Answers to the following rounds aren't news
+        let event = timeout(Duration::from_secs(5), events.recv()).await;
+        assert!(event.is_err(), "unexpected event: {event:?}");
+
+        device.device().set_loss(1.0);
+        let silent = Instant::now();
+        assert_eq!(
+            events.recv().await.unwrap().unwrap(),
+            DiscoveryEvent::Disappeared(expected)
+        );
+        assert!(silent.elapsed() >= Duration::from_secs(2));
+
+        device.device().set_loss(0.0);
+        assert_eq!(
+            events.recv().await.unwrap().unwrap(),
+            DiscoveryEvent::Appeared(expected)
+        );
+    }
+}
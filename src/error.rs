@@ -1,24 +1,117 @@
-use embedded_bacnet::simple::BacnetError;
+use embedded_bacnet::simple::{BacnetError, NetworkIo};
 
-use crate::io::TokioUdpIo;
+use crate::{io::TokioUdpIo, ObjectId, PropertyId};
 
-/// Error type for async-bacnet operations.
+/// Error class and code carried by a BACnet-Error PDU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ServiceError {
+    pub class: u32,
+    pub code: u32,
+}
+
+impl ServiceError {
+    pub const CLASS_OBJECT: u32 = 1;
+    pub const CLASS_PROPERTY: u32 = 2;
+    pub const CLASS_SERVICES: u32 = 5;
+    pub const CODE_UNKNOWN_OBJECT: u32 = 31;
+    pub const CODE_UNKNOWN_PROPERTY: u32 = 32;
+    pub const CODE_WRITE_ACCESS_DENIED: u32 = 40;
+
+    /// Whether the device reported the object as unknown.
+    pub fn is_unknown_object(&self) -> bool {
+        self.class == Self::CLASS_OBJECT && self.code == Self::CODE_UNKNOWN_OBJECT
+    }
+
+    /// Whether the device reported the property as unknown.
+    pub fn is_unknown_property(&self) -> bool {
+        self.class == Self::CLASS_PROPERTY && self.code == Self::CODE_UNKNOWN_PROPERTY
+    }
+
+    /// Whether the device refused to write a read-only property.
+    pub fn is_write_access_denied(&self) -> bool {
+        self.class == Self::CLASS_PROPERTY && self.code == Self::CODE_WRITE_ACCESS_DENIED
+    }
+}
+
+/// Error type for async-bacnet operations over the transport `T`.
 #[derive(Debug)]
-pub enum Error {
-    /// I/O error from socket operations.
+pub enum Error<T: NetworkIo = TokioUdpIo> {
+    /// I/O error from socket setup, such as binding or name resolution.
     Io(std::io::Error),
+    /// The transport failed to send or receive a request.
+    Transport(T::Error),
     /// BACnet protocol error from embedded-bacnet.
-    Bacnet(BacnetError<TokioUdpIo>),
+    Bacnet(BacnetError<T>),
+    /// The device answered with a BACnet-Error PDU.
+    Service(ServiceError),
+    /// The device answered a property of a bulk read with an error, which
+    /// failed the read, see [`crate::ReadPolicy::FailFast`].
+    Property {
+        object_id: ObjectId,
+        property_id: PropertyId,
+        error: ServiceError,
+    },
+    /// The device rejected the request with the given reject reason.
+    Reject(u8),
+    /// The device aborted the transaction with the given abort reason.
+    Abort(u8),
+    /// The BBMD refused a BVLL request with the given BVLC result code.
+    BvllNak(u16),
+    /// The deadline set on the client passed before the operation completed.
+    DeadlineExceeded,
+    /// No state text of the multi-state object matches the given label.
+    UnknownState(String),
+    /// Every process identifier under the subscription registry's prefix is
+    /// in use, so no further COV subscription can be made.
+    ProcessIdsExhausted,
+    /// A response was received but did not hold the expected data.
+    Decode(&'static str),
 }
 
-impl From<std::io::Error> for Error {
+impl<T: NetworkIo> Error<T> {
+    /// Whether the error concerns the transport rather than the request, so
+    /// retrying the request differently is pointless.
+    pub(crate) fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            Error::Io(_) | Error::Transport(_) | Error::Bacnet(_) | Error::DeadlineExceeded
+        )
+    }
+}
+
+impl<T: NetworkIo<Error = std::io::Error>> Error<T> {
+    /// Whether the device didn't answer in time, for transports reporting
+    /// their failures as I/O errors, such as UDP, BACnet/SC and their layers.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::Io(err) => err.kind() == std::io::ErrorKind::TimedOut,
+            Error::Transport(err) => err.kind() == std::io::ErrorKind::TimedOut,
+            _ => false,
+        }
+    }
+}
+
+impl<T: NetworkIo> From<std::io::Error> for Error<T> {
     fn from(value: std::io::Error) -> Self {
         Error::Io(value)
     }
 }
 
-impl From<BacnetError<TokioUdpIo>> for Error {
-    fn from(value: BacnetError<TokioUdpIo>) -> Self {
+impl<T: NetworkIo> From<BacnetError<T>> for Error<T> {
+    fn from(value: BacnetError<T>) -> Self {
         Error::Bacnet(value)
     }
 }
+
+/// Malformed data received from a device, surfaced as [`Error::Decode`].
+///
+/// Decoding doesn't depend on the transport, so the codec returns this
+/// rather than [`Error`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct DecodeError(pub &'static str);
+
+impl<T: NetworkIo> From<DecodeError> for Error<T> {
+    fn from(value: DecodeError) -> Self {
+        Error::Decode(value.0)
+    }
+}
@@ -0,0 +1,465 @@
+//! Event states, time stamps and event notifications.
+
+use crate::{
+    apdu::{self, Decoder, Encoder},
+    charset::EncodedString,
+    client::Client,
+    error::{DecodeError, Error},
+    value::{DateTime, Time},
+    NetworkIo, ObjectId,
+};
+
+/// State of an object's event or fault detection (BACnetEventState).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventState {
+    Normal,
+    Fault,
+    Offnormal,
+    HighLimit,
+    LowLimit,
+    LifeSafetyAlarm,
+    /// A vendor-specific (64 and above) value.
+    Other(u32),
+}
+
+impl From<u32> for EventState {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::Normal,
+            1 => Self::Fault,
+            2 => Self::Offnormal,
+            3 => Self::HighLimit,
+            4 => Self::LowLimit,
+            5 => Self::LifeSafetyAlarm,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<EventState> for u32 {
+    fn from(value: EventState) -> Self {
+        match value {
+            EventState::Normal => 0,
+            EventState::Fault => 1,
+            EventState::Offnormal => 2,
+            EventState::HighLimit => 3,
+            EventState::LowLimit => 4,
+            EventState::LifeSafetyAlarm => 5,
+            EventState::Other(other) => other,
+        }
+    }
+}
+
+impl EventState {
+    pub fn is_normal(&self) -> bool {
+        *self == Self::Normal
+    }
+}
+
+/// Kind of an event notification (BACnetNotifyType).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NotifyType {
+    Alarm,
+    Event,
+    /// Confirms that a transition was acknowledged.
+    AckNotification,
+    Other(u32),
+}
+
+impl From<u32> for NotifyType {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::Alarm,
+            1 => Self::Event,
+            2 => Self::AckNotification,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// When an event occurred, as recorded by the device (BACnetTimeStamp).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeStamp {
+    Time(Time),
+    Sequence(u32),
+    DateTime(DateTime),
+}
+
+impl TimeStamp {
+    /// Decode the choice, without the tags enclosing it.
+    pub(crate) fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        if let Some(time) = decoder.context(0)? {
+            return Ok(Self::Time(Time::from_bytes(time)?));
+        }
+        if let Some(sequence) = decoder.context_unsigned(1)? {
+            return Ok(Self::Sequence(sequence as u32));
+        }
+        let mut datetime = Decoder::new(decoder.enclosed(2)?);
+        Ok(Self::DateTime(DateTime::decode(&mut datetime)?))
+    }
+
+    pub(crate) fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Self::Time(time) => encoder.context_time(0, time.to_bytes()),
+            Self::Sequence(sequence) => encoder.context_unsigned(1, *sequence as u64),
+            Self::DateTime(datetime) => {
+                encoder.opening(2);
+                datetime.encode(encoder);
+                encoder.closing(2);
+            }
+        }
+    }
+
+    /// Decode a time stamp enclosed in the tags `number`.
+    pub(crate) fn decode_enclosed(
+        decoder: &mut Decoder<'_>,
+        number: u8,
+    ) -> Result<Self, DecodeError> {
+        Self::decode(&mut Decoder::new(decoder.enclosed(number)?))
+    }
+}
+
+/// A decoded event notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventNotification {
+    pub process_id: u32,
+    pub initiating_device: ObjectId,
+    pub object_id: ObjectId,
+    pub timestamp: TimeStamp,
+    pub notification_class: u32,
+    pub priority: u8,
+    /// The event algorithm (BACnetEventType), e.g. 5 for out-of-range.
+    pub event_type: u32,
+    pub message: Option<String>,
+    pub notify_type: NotifyType,
+    pub ack_required: Option<bool>,
+    pub from_state: Option<EventState>,
+    /// The state entered, `None` for ack notifications.
+    pub to_state: Option<EventState>,
+    /// The encoded notification parameters, specific to the event type.
+    pub event_values: Option<Vec<u8>>,
+}
+
+impl EventNotification {
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut decoder = Decoder::new(data);
+        let process_id = apdu::decode_unsigned(decoder.expect_context(0)?) as u32;
+        let initiating_device = apdu::decode_object_id(decoder.expect_context(1)?)?;
+        let object_id = apdu::decode_object_id(decoder.expect_context(2)?)?;
+        let timestamp = TimeStamp::decode_enclosed(&mut decoder, 3)?;
+        let notification_class = apdu::decode_unsigned(decoder.expect_context(4)?) as u32;
+        let priority = apdu::decode_unsigned(decoder.expect_context(5)?) as u8;
+        let event_type = apdu::decode_unsigned(decoder.expect_context(6)?) as u32;
+        let message = decoder
+            .context(7)?
+            .map(|text| EncodedString::decode(text).text_lossy());
+        let notify_type =
+            NotifyType::from(apdu::decode_unsigned(decoder.expect_context(8)?) as u32);
+        let ack_required = decoder.context_unsigned(9)?.map(|ack| ack != 0);
+        let from_state = decoder
+            .context_unsigned(10)?
+            .map(|state| EventState::from(state as u32));
+        let to_state = decoder
+            .context_unsigned(11)?
+            .map(|state| EventState::from(state as u32));
+        let event_values = match decoder.is_empty() {
+            true => None,
+            false => Some(decoder.enclosed(12)?.to_vec()),
+        };
+        Ok(Self {
+            process_id,
+            initiating_device,
+            object_id,
+            timestamp,
+            notification_class,
+            priority,
+            event_type,
+            message,
+            notify_type,
+            ack_required,
+            from_state,
+            to_state,
+            event_values,
+        })
+    }
+}
+
+/// One flag per kind of transition: to-offnormal, to-fault and to-normal
+/// (BACnetEventTransitionBits).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventTransitions {
+    pub to_offnormal: bool,
+    pub to_fault: bool,
+    pub to_normal: bool,
+}
+
+impl EventTransitions {
+    fn from_bits(bits: &[bool]) -> Self {
+        let bit = |i: usize| bits.get(i).copied().unwrap_or_default();
+        Self {
+            to_offnormal: bit(0),
+            to_fault: bit(1),
+            to_normal: bit(2),
+        }
+    }
+}
+
+/// An object in alarm or fault, or with unacknowledged transitions, as
+/// listed by [`Client::get_event_information`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventSummary {
+    pub object_id: ObjectId,
+    pub event_state: EventState,
+    pub acked_transitions: EventTransitions,
+    /// Time of the last to-offnormal, to-fault and to-normal transitions.
+    pub timestamps: [TimeStamp; 3],
+    pub notify_type: NotifyType,
+    pub event_enable: EventTransitions,
+    /// Priorities of to-offnormal, to-fault and to-normal notifications.
+    pub priorities: [u32; 3],
+}
+
+impl EventSummary {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let object_id = apdu::decode_object_id(decoder.expect_context(0)?)?;
+        let event_state =
+            EventState::from(apdu::decode_unsigned(decoder.expect_context(1)?) as u32);
+        let acked_transitions =
+            EventTransitions::from_bits(&apdu::decode_bit_string(decoder.expect_context(2)?));
+        decoder.expect_opening(3)?;
+        let timestamps = [
+            TimeStamp::decode(decoder)?,
+            TimeStamp::decode(decoder)?,
+            TimeStamp::decode(decoder)?,
+        ];
+        decoder.expect_closing(3)?;
+        let notify_type =
+            NotifyType::from(apdu::decode_unsigned(decoder.expect_context(4)?) as u32);
+        let event_enable =
+            EventTransitions::from_bits(&apdu::decode_bit_string(decoder.expect_context(5)?));
+        decoder.expect_opening(6)?;
+        let priorities = [
+            decoder.unsigned()? as u32,
+            decoder.unsigned()? as u32,
+            decoder.unsigned()? as u32,
+        ];
+        decoder.expect_closing(6)?;
+        Ok(Self {
+            object_id,
+            event_state,
+            acked_transitions,
+            timestamps,
+            notify_type,
+            event_enable,
+            priorities,
+        })
+    }
+
+    /// Whether a transition the device expects to be acknowledged isn't.
+    pub fn needs_ack(&self) -> bool {
+        !(self.acked_transitions.to_offnormal
+            && self.acked_transitions.to_fault
+            && self.acked_transitions.to_normal)
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// List the objects of the device that are in alarm or fault, or have
+    /// unacknowledged transitions, with GetEventInformation.
+    ///
+    /// Devices answer in chunks; they are requested until the device
+    /// reports no more events.
+    pub async fn get_event_information(&mut self) -> Result<Vec<EventSummary>, Error<T>> {
+        let mut summaries: Vec<EventSummary> = Vec::new();
+        loop {
+            let mut request = Encoder::new();
+            if let Some(last) = summaries.last() {
+                request.context_object_id(0, last.object_id);
+            }
+            let ack = self
+                .confirmed_request(
+                    apdu::confirmed::GET_EVENT_INFORMATION,
+                    &request.into_bytes(),
+                )
+                .await?;
+            let mut decoder = Decoder::new(&ack);
+            let received = summaries.len();
+            decoder.expect_opening(0)?;
+            while !decoder.is_closing(0) {
+                summaries.push(EventSummary::decode(&mut decoder)?);
+            }
+            decoder.expect_closing(0)?;
+            let more = decoder.context_unsigned(1)?.is_some_and(|more| more != 0);
+            // A device claiming more events without sending any would
+            // be asked the same question forever
+            if !more || summaries.len() == received {
+                return Ok(summaries);
+            }
+        }
+    }
+
+    /// Acknowledge the transition of an object to `event_state`, identified
+    /// by the time stamp of its notification or event summary.
+    ///
+    /// `source` identifies who acknowledged, e.g. an operator name, and
+    /// `time` is when they did. The acknowledging process identifier is 0.
+    pub async fn acknowledge_alarm(
+        &mut self,
+        object_id: ObjectId,
+        event_state: EventState,
+        timestamp: TimeStamp,
+        source: &str,
+        time: TimeStamp,
+    ) -> Result<(), Error<T>> {
+        let mut request = Encoder::new();
+        request.context_unsigned(0, 0);
+        request.context_object_id(1, object_id);
+        request.context_enumerated(2, event_state.into());
+        request.opening(3);
+        timestamp.encode(&mut request);
+        request.closing(3);
+        request.context_character_string(4, source);
+        request.opening(5);
+        time.encode(&mut request);
+        request.closing(5);
+        self.confirmed_request(apdu::confirmed::ACKNOWLEDGE_ALARM, &request.into_bytes())
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{MockIo, VirtualDevice},
+        value::Date,
+        ObjectType,
+    };
+
+    fn time() -> Time {
+        Time {
+            hour: 13,
+            minute: 30,
+            second: 5,
+            hundredths: 50,
+        }
+    }
+
+    fn analog_input(instance: u32) -> ObjectId {
+        ObjectId::new(ObjectType::ObjectAnalogInput, instance)
+    }
+
+    #[test]
+    fn time_stamps_round_trip() {
+        let date = Date {
+            year: 2024,
+            month: 2,
+            day: 29,
+            weekday: 4,
+        };
+        for timestamp in [
+            TimeStamp::Time(time()),
+            TimeStamp::Sequence(70_000),
+            TimeStamp::DateTime(DateTime::new(date, time())),
+        ] {
+            let mut encoder = Encoder::new();
+            timestamp.encode(&mut encoder);
+            let data = encoder.into_bytes();
+            let mut decoder = Decoder::new(&data);
+            assert_eq!(TimeStamp::decode(&mut decoder), Ok(timestamp));
+            assert!(decoder.is_empty());
+        }
+    }
+
+    /// A GetEventInformation ack listing analog inputs in high-limit, with
+    /// the to-offnormal transition unacknowledged.
+    fn event_information(instances: &[u32], more: bool) -> Vec<u8> {
+        let mut ack = Encoder::new();
+        ack.opening(0);
+        for instance in instances {
+            ack.context_object_id(0, analog_input(*instance));
+            ack.context_enumerated(1, 3);
+            // Bit strings of 3 bits: to-fault and to-normal acked
+            ack.raw(&[0x2a, 0x05, 0b0110_0000]);
+            ack.opening(3);
+            TimeStamp::Time(time()).encode(&mut ack);
+            TimeStamp::Sequence(0).encode(&mut ack);
+            TimeStamp::Sequence(0).encode(&mut ack);
+            ack.closing(3);
+            ack.context_enumerated(4, 0);
+            // All transitions enabled
+            ack.raw(&[0x5a, 0x05, 0b1110_0000]);
+            ack.opening(6);
+            ack.unsigned(100);
+            ack.unsigned(100);
+            ack.unsigned(200);
+            ack.closing(6);
+        }
+        ack.closing(0);
+        ack.context_boolean(1, more);
+        ack.into_bytes()
+    }
+
+    #[tokio::test]
+    async fn event_information_in_chunks() {
+        let mut device = VirtualDevice::new(1234);
+        device
+            .queue_ack(
+                apdu::confirmed::GET_EVENT_INFORMATION,
+                event_information(&[1, 2], true),
+            )
+            .queue_ack(
+                apdu::confirmed::GET_EVENT_INFORMATION,
+                event_information(&[3], false),
+            );
+        let io = MockIo::new(device);
+        let mut client = Client::from_io(io.clone());
+
+        let summaries = client.get_event_information().await.unwrap();
+        let object_ids: Vec<_> = summaries.iter().map(|summary| summary.object_id).collect();
+        assert_eq!(
+            object_ids,
+            [analog_input(1), analog_input(2), analog_input(3)]
+        );
+        assert_eq!(io.device().requests(), 2);
+
+        let summary = &summaries[0];
+        assert_eq!(summary.event_state, EventState::HighLimit);
+        assert_eq!(
+            summary.acked_transitions,
+            EventTransitions {
+                to_offnormal: false,
+                to_fault: true,
+                to_normal: true,
+            }
+        );
+        assert!(summary.needs_ack());
+        assert_eq!(summary.timestamps[0], TimeStamp::Time(time()));
+        assert_eq!(summary.notify_type, NotifyType::Alarm);
+        assert_eq!(summary.priorities, [100, 100, 200]);
+    }
+
+    #[tokio::test]
+    async fn event_information_stops_without_progress() {
+        let mut device = VirtualDevice::new(1234);
+        // More events claimed, none sent, twice
+        for _ in 0..2 {
+            device.queue_ack(
+                apdu::confirmed::GET_EVENT_INFORMATION,
+                event_information(&[], true),
+            );
+        }
+        let io = MockIo::new(device);
+        let mut client = Client::from_io(io.clone());
+
+        let summaries = client.get_event_information().await.unwrap();
+        assert!(summaries.is_empty());
+        assert_eq!(io.device().requests(), 1);
+    }
+}
@@ -0,0 +1,238 @@
+use embedded_bacnet::application_protocol::primitives::data_value::BitString;
+
+use crate::{
+    client::Client, error::Error, value::enumerated_to_u32, ApplicationDataValue, NetworkIo,
+    ObjectId, PropertyId, ReadProperty,
+};
+
+/// The four flags of the `status-flags` property.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StatusFlags {
+    pub in_alarm: bool,
+    pub fault: bool,
+    pub overridden: bool,
+    pub out_of_service: bool,
+}
+
+impl StatusFlags {
+    /// Flags from a decoded bit string, missing bits read as clear.
+    pub(crate) fn from_bits(bits: &[bool]) -> Self {
+        let bit = |i: usize| bits.get(i).copied().unwrap_or_default();
+        Self {
+            in_alarm: bit(0),
+            fault: bit(1),
+            overridden: bit(2),
+            out_of_service: bit(3),
+        }
+    }
+}
+
+/// Value of the `reliability` property (BACnetReliability).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reliability {
+    NoFaultDetected,
+    NoSensor,
+    OverRange,
+    UnderRange,
+    OpenLoop,
+    ShortedLoop,
+    NoOutput,
+    UnreliableOther,
+    ProcessError,
+    MultiStateFault,
+    ConfigurationError,
+    CommunicationFailure,
+    MemberFault,
+    MonitoredObjectFault,
+    Tripped,
+    LampFailure,
+    ActivationFailure,
+    RenewDhcpFailure,
+    RenewFdRegistrationFailure,
+    RestartAutoNegotiationFailure,
+    RestartFailure,
+    ProprietaryCommandFailure,
+    FaultsListed,
+    ReferencedObjectFault,
+    /// A reserved or vendor-specific (64 and above) value.
+    Other(u32),
+}
+
+impl From<u32> for Reliability {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::NoFaultDetected,
+            1 => Self::NoSensor,
+            2 => Self::OverRange,
+            3 => Self::UnderRange,
+            4 => Self::OpenLoop,
+            5 => Self::ShortedLoop,
+            6 => Self::NoOutput,
+            7 => Self::UnreliableOther,
+            8 => Self::ProcessError,
+            9 => Self::MultiStateFault,
+            10 => Self::ConfigurationError,
+            12 => Self::CommunicationFailure,
+            13 => Self::MemberFault,
+            14 => Self::MonitoredObjectFault,
+            15 => Self::Tripped,
+            16 => Self::LampFailure,
+            17 => Self::ActivationFailure,
+            18 => Self::RenewDhcpFailure,
+            19 => Self::RenewFdRegistrationFailure,
+            20 => Self::RestartAutoNegotiationFailure,
+            21 => Self::RestartFailure,
+            22 => Self::ProprietaryCommandFailure,
+            23 => Self::FaultsListed,
+            24 => Self::ReferencedObjectFault,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl Reliability {
+    /// Whether this value reports a fault condition.
+    pub fn is_fault(&self) -> bool {
+        *self != Self::NoFaultDetected
+    }
+}
+
+/// Fault algorithm configured in the `fault-type` property (BACnetFaultType).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultType {
+    None,
+    CharacterString,
+    Extended,
+    LifeSafety,
+    State,
+    StatusFlags,
+    OutOfRange,
+    Listed,
+    Other(u32),
+}
+
+impl From<u32> for FaultType {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::None,
+            1 => Self::CharacterString,
+            2 => Self::Extended,
+            3 => Self::LifeSafety,
+            4 => Self::State,
+            5 => Self::StatusFlags,
+            6 => Self::OutOfRange,
+            7 => Self::Listed,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Combined health view of an object, see [`Client::read_health`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub status_flags: StatusFlags,
+    /// `None` when the object has no `reliability` property.
+    pub reliability: Option<Reliability>,
+    pub out_of_service: bool,
+}
+
+impl Health {
+    /// True when the object is in service, not faulted and not in alarm.
+    pub fn is_healthy(&self) -> bool {
+        !self.out_of_service
+            && !self.status_flags.fault
+            && !self.status_flags.in_alarm
+            && !self.reliability.is_some_and(|r| r.is_fault())
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read the `status-flags` property of an object.
+    pub async fn read_status_flags(
+        &mut self,
+        object_id: ObjectId,
+    ) -> Result<StatusFlags, Error<T>> {
+        match self
+            .read_application_value(object_id, PropertyId::PropStatusFlags)
+            .await?
+        {
+            ApplicationDataValue::BitString(BitString::StatusFlags(flags)) => Ok(StatusFlags {
+                in_alarm: flags.in_alarm,
+                fault: flags.fault,
+                overridden: flags.overridden,
+                out_of_service: flags.out_of_service,
+            }),
+            _ => Err(Error::Decode("status-flags is not a bit string")),
+        }
+    }
+
+    /// Read the `reliability` property of an object.
+    pub async fn read_reliability(&mut self, object_id: ObjectId) -> Result<Reliability, Error<T>> {
+        let value = self
+            .read_value(object_id, PropertyId::PropReliability)
+            .await?;
+        value
+            .as_u32()
+            .map(Reliability::from)
+            .ok_or(Error::Decode("reliability is not enumerated"))
+    }
+
+    /// Read the `fault-type` property of an object.
+    pub async fn read_fault_type(&mut self, object_id: ObjectId) -> Result<FaultType, Error<T>> {
+        let value = self
+            .read_application_value(object_id, PropertyId::PropFaultType)
+            .await?;
+        enumerated_value(&value)
+            .map(FaultType::from)
+            .ok_or(Error::Decode("fault-type is not enumerated"))
+    }
+
+    /// Read status-flags, reliability and out-of-service in one call.
+    ///
+    /// Reliability is optional for most object types, so a device reporting
+    /// it as an unknown property yields `None` rather than failing the call.
+    pub async fn read_health(&mut self, object_id: ObjectId) -> Result<Health, Error<T>> {
+        let status_flags = self.read_status_flags(object_id).await?;
+        let out_of_service = match self
+            .read_application_value(object_id, PropertyId::PropOutOfService)
+            .await?
+        {
+            ApplicationDataValue::Boolean(value) => value,
+            _ => return Err(Error::Decode("out-of-service is not boolean")),
+        };
+        let reliability = match self.read_reliability(object_id).await {
+            Ok(reliability) => Some(reliability),
+            Err(err) if err.is_transport() => return Err(err),
+            Err(Error::Service(err)) if err.is_unknown_property() => None,
+            Err(err) => return Err(err),
+        };
+        Ok(Health {
+            status_flags,
+            reliability,
+            out_of_service,
+        })
+    }
+
+    async fn read_application_value(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+    ) -> Result<ApplicationDataValue<'_>, Error<T>> {
+        let ack = self
+            .read_property(ReadProperty::new(object_id, property_id))
+            .await?;
+        ack.property_value
+            .try_into()
+            .map_err(|_| Error::Decode("unexpected property value"))
+    }
+}
+
+/// Raw numeric value of an enumerated application value.
+fn enumerated_value(value: &ApplicationDataValue<'_>) -> Option<u32> {
+    match value {
+        ApplicationDataValue::Enumerated(value) => enumerated_to_u32(value).ok(),
+        ApplicationDataValue::UnsignedInt(value) => Some(*value),
+        _ => None,
+    }
+}
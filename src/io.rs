@@ -1,13 +1,174 @@
-use std::{fmt::Debug, net::SocketAddr, time::Duration};
+use std::{
+    fmt::Debug,
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use embedded_bacnet::simple::NetworkIo;
 use tokio::{net::UdpSocket, time::timeout};
 
+use crate::pool::Mailbox;
+
+/// The BACnet/IP UDP port (0xBAC0).
+pub const DEFAULT_PORT: u16 = 47808;
+
+/// How long a datagram is waited for unless set otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+tokio::task_local! {
+    /// Timeout of the request being made, see [`request_timeout`].
+    static REQUEST_TIMEOUT: Duration;
+}
+
+/// How long a transport read should wait: the timeout of the request a
+/// [`crate::Client`] is making, see [`crate::Client::set_timeout`], or
+/// `default` outside of one.
+///
+/// Transports call it on each read instead of keeping the timeout, so
+/// clients sharing a transport keep their own.
+pub fn request_timeout(default: Duration) -> Duration {
+    REQUEST_TIMEOUT
+        .try_with(|timeout| *timeout)
+        .unwrap_or(default)
+}
+
+/// Run `future` with `timeout` as its [`request_timeout`], if any.
+pub(crate) async fn with_request_timeout<F: Future>(
+    timeout: Option<Duration>,
+    future: F,
+) -> F::Output {
+    match timeout {
+        Some(timeout) => REQUEST_TIMEOUT.scope(timeout, future).await,
+        None => future.await,
+    }
+}
+
+/// Something that names a BACnet/IP device: a socket address, an IP address,
+/// or a string holding a host name or IP address with an optional port,
+/// such as `controller-3.plant.local`, `10.0.0.5:47809` or `[fe80::1]`.
+///
+/// Addresses without a port use [`DEFAULT_PORT`].
+pub trait ToPeerAddr {
+    /// The host to resolve, and the port if one was given.
+    fn to_host_port(&self) -> (String, Option<u16>);
+}
+
+impl ToPeerAddr for SocketAddr {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        (self.ip().to_string(), Some(self.port()))
+    }
+}
+
+impl ToPeerAddr for IpAddr {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        (self.to_string(), None)
+    }
+}
+
+impl ToPeerAddr for Ipv4Addr {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        (self.to_string(), None)
+    }
+}
+
+impl ToPeerAddr for Ipv6Addr {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        (self.to_string(), None)
+    }
+}
+
+impl ToPeerAddr for str {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        if let Ok(addr) = self.parse::<SocketAddr>() {
+            return addr.to_host_port();
+        }
+        if self.parse::<IpAddr>().is_ok() {
+            return (self.to_string(), None);
+        }
+        if let Some(host) = self.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            return (host.to_string(), None);
+        }
+        match self.rsplit_once(':') {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => (host.to_string(), Some(port)),
+                Err(_) => (self.to_string(), None),
+            },
+            None => (self.to_string(), None),
+        }
+    }
+}
+
+impl ToPeerAddr for String {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        self.as_str().to_host_port()
+    }
+}
+
+impl ToPeerAddr for (&str, u16) {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        (self.0.to_string(), Some(self.1))
+    }
+}
+
+impl ToPeerAddr for (IpAddr, u16) {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        (self.0.to_string(), Some(self.1))
+    }
+}
+
+impl<T: ToPeerAddr + ?Sized> ToPeerAddr for &T {
+    fn to_host_port(&self) -> (String, Option<u16>) {
+        (**self).to_host_port()
+    }
+}
+
+/// Resolve a device address, looking host names up in DNS.
+///
+/// IPv4 addresses are preferred, since sockets are bound to an IPv4
+/// wildcard address.
+pub async fn resolve(peer: impl ToPeerAddr) -> Result<SocketAddr, std::io::Error> {
+    let (host, port) = peer.to_host_port();
+    let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), port.unwrap_or(DEFAULT_PORT)))
+        .await?
+        .collect();
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or(addrs.first())
+        .copied()
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no address found for {host}"),
+            )
+        })
+}
+
+/// Whether a datagram seen by a [`PacketObserver`] was sent or received.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PacketDirection {
+    Sent,
+    Received,
+}
+
+/// Callback seeing every datagram a transport sends or receives, with the
+/// address of the other end, e.g. to write a capture file or a hex log.
+pub type PacketObserver = Arc<dyn Fn(PacketDirection, SocketAddr, &[u8]) + Send + Sync>;
+
 /// A tokio-based UDP I/O implementation for `embedded_bacnet::simple::Bacnet<T>`.
+///
+/// Clones share the underlying socket and the packet observer.
+#[derive(Clone)]
 pub struct TokioUdpIo {
-    socket: UdpSocket,
+    socket: Arc<UdpSocket>,
     peer: SocketAddr,
     timeout: Duration,
+    /// Set for handles of a [`crate::ClientPool`], which owns the socket's
+    /// receive side.
+    mailbox: Option<Arc<Mailbox>>,
+    observer: Arc<Mutex<Option<PacketObserver>>>,
 }
 
 impl Debug for TokioUdpIo {
@@ -16,46 +177,81 @@ impl Debug for TokioUdpIo {
             .field("local_addr", &self.socket.local_addr().ok())
             .field("peer", &self.peer)
             .field("timeout", &self.timeout)
+            .field("pooled", &self.mailbox.is_some())
+            .field("observed", &self.observer.lock().unwrap().is_some())
             .finish()
     }
 }
 
+/// Bind a broadcast-enabled socket that other BACnet software on the host
+/// can bind too, as they usually all want port 47808.
+pub(crate) fn bind_shared(local: SocketAddr) -> Result<UdpSocket, std::io::Error> {
+    use socket2::{Domain, Socket, Type};
+
+    let domain = match local {
+        SocketAddr::V4(_) => Domain::IPV4,
+        SocketAddr::V6(_) => Domain::IPV6,
+    };
+    let socket = Socket::new(domain, Type::DGRAM, None)?;
+    socket.set_nonblocking(true)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(not(target_os = "windows"))]
+    socket.set_reuse_port(true)?;
+    socket.bind(&local.into())?;
+
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.set_broadcast(true)?;
+    Ok(socket)
+}
+
 impl TokioUdpIo {
     pub async fn new(peer: SocketAddr) -> Result<Self, std::io::Error> {
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
         Ok(Self {
-            socket,
+            socket: Arc::new(socket),
             peer,
-            timeout: Duration::from_secs(5),
+            timeout: DEFAULT_TIMEOUT,
+            mailbox: None,
+            observer: Arc::default(),
         })
     }
 
     pub async fn new_broadcast(peer: SocketAddr) -> Result<Self, std::io::Error> {
-        use socket2::{Domain, Socket, Type};
-
-        let domain = match peer {
-            SocketAddr::V4(_) => Domain::IPV4,
-            SocketAddr::V6(_) => Domain::IPV6,
-        };
-        let socket = Socket::new(domain, Type::DGRAM, None)?;
-        socket.set_nonblocking(true)?;
-        socket.set_reuse_address(true)?;
-        #[cfg(not(target_os = "windows"))]
-        socket.set_reuse_port(true)?;
-
         let local_addr: SocketAddr = format!("0.0.0.0:{}", peer.port()).parse().unwrap();
-        socket.bind(&local_addr.into())?;
-
-        let socket = UdpSocket::from_std(socket.into())?;
-        socket.set_broadcast(true)?;
+        let socket = bind_shared(local_addr)?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            peer,
+            timeout: DEFAULT_TIMEOUT,
+            mailbox: None,
+            observer: Arc::default(),
+        })
+    }
 
+    /// Communicate with `peer` from the given local address, which no other
+    /// socket on the host may share: binding fails if one already has.
+    pub(crate) fn bound(local: SocketAddr, peer: SocketAddr) -> Result<Self, std::io::Error> {
+        let socket = std::net::UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
         Ok(Self {
-            socket,
+            socket: Arc::new(UdpSocket::from_std(socket)?),
             peer,
-            timeout: Duration::from_secs(5),
+            timeout: DEFAULT_TIMEOUT,
+            mailbox: None,
+            observer: Arc::default(),
         })
     }
 
+    pub(crate) fn pooled(socket: Arc<UdpSocket>, peer: SocketAddr, mailbox: Arc<Mailbox>) -> Self {
+        Self {
+            socket,
+            peer,
+            timeout: DEFAULT_TIMEOUT,
+            mailbox: Some(mailbox),
+            observer: Arc::default(),
+        }
+    }
+
     pub fn socket(&self) -> &UdpSocket {
         &self.socket
     }
@@ -64,28 +260,67 @@ impl TokioUdpIo {
         self.peer
     }
 
+    /// How long reads wait for a datagram and writes for the socket,
+    /// unless the request being made sets its own, see [`request_timeout`].
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
     pub fn set_timeout(&mut self, duration: Duration) {
         self.timeout = duration;
     }
+
+    /// Pass every datagram sent or received from now on to `observer`,
+    /// replacing any previous observer. `None` removes it.
+    pub fn set_packet_observer(&self, observer: Option<PacketObserver>) {
+        *self.observer.lock().unwrap() = observer;
+    }
+
+    fn observe(&self, direction: PacketDirection, addr: SocketAddr, datagram: &[u8]) {
+        let observer = self.observer.lock().unwrap().clone();
+        if let Some(observer) = observer {
+            observer(direction, addr, datagram);
+        }
+    }
 }
 
 impl NetworkIo for TokioUdpIo {
     type Error = std::io::Error;
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(peer = %self.peer))
+    )]
     async fn read(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        let result = timeout(self.timeout, self.socket.recv_from(buf)).await;
-        match result {
-            Ok(Ok((n, _peer))) => Ok(n),
-            Ok(Err(e)) => Err(e),
-            Err(_elapsed) => Err(std::io::Error::new(
-                std::io::ErrorKind::TimedOut,
-                "read timed out",
-            )),
-        }
+        let duration = request_timeout(self.timeout);
+        let (n, from) = match &self.mailbox {
+            Some(mailbox) => (mailbox.recv(buf, duration).await?, self.peer),
+            None => match timeout(duration, self.socket.recv_from(buf)).await {
+                Ok(Ok(received)) => received,
+                Ok(Err(e)) => return Err(e),
+                Err(_elapsed) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "read timed out",
+                    ))
+                }
+            },
+        };
+        self.observe(PacketDirection::Received, from, &buf[..n]);
+        Ok(n)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(peer = %self.peer, len = buf.len()))
+    )]
     async fn write(&self, buf: &[u8]) -> Result<usize, Self::Error> {
-        let result = timeout(self.timeout, self.socket.send_to(buf, self.peer)).await;
+        self.observe(PacketDirection::Sent, self.peer, buf);
+        let duration = request_timeout(self.timeout);
+        if let Some(mailbox) = &self.mailbox {
+            return mailbox.send(buf, duration).await;
+        }
+        let result = timeout(duration, self.socket.send_to(buf, self.peer)).await;
         match result {
             Ok(Ok(n)) => Ok(n),
             Ok(Err(e)) => Err(e),
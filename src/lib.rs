@@ -1,11 +1,84 @@
 mod io;
+mod acquisition;
+mod apdu;
+mod backup;
+mod batch;
+mod bbmd;
+mod charset;
 mod client;
+mod config;
+mod control;
+mod device;
 mod error;
+mod event;
+mod health;
+mod listener;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod point;
+mod poll;
+mod pool;
+mod priority;
+mod private;
+mod profile;
+mod references;
+mod schedule;
+mod services;
+mod shard;
+mod snapshot;
+mod subscription;
+mod trend;
+mod units;
+mod value;
 pub mod discover;
+pub mod middleware;
+#[cfg(feature = "bacnet-sc")]
+pub mod sc;
+#[cfg(feature = "testing")]
+pub mod testing;
 
-pub use client::Client;
-pub use error::Error;
-pub use io::TokioUdpIo;
+pub use acquisition::{
+    AcquisitionHint, AcquisitionSummary, ClientFactory, PointConfig, SubscriptionManager,
+    ValueCallback,
+};
+pub use backup::{Backup, BackupFile, FileData, ReinitializeState};
+pub use batch::{PropertyResults, ReadPolicy};
+pub use bbmd::{BdtEntry, FdtEntry};
+pub use client::{Client, DeadlineGuard, TimeoutGuard};
+pub use config::{clone_config, CloneReport, DesiredPropertyState, Drift, EnsureReport};
+pub use control::{Action, LoopConfig, ObjectPropertyReference, PidGains};
+pub use charset::{CharacterSet, EncodedString};
+pub use error::{Error, ServiceError};
+pub use event::{
+    EventNotification, EventState, EventSummary, EventTransitions, NotifyType, TimeStamp,
+};
+pub use health::{FaultType, Health, Reliability, StatusFlags};
+pub use io::{
+    request_timeout, resolve, PacketDirection, PacketObserver, ToPeerAddr, TokioUdpIo, DEFAULT_PORT,
+};
+pub use listener::{Listener, Notification, NotificationHandler, Notifications};
+pub use point::{Point, PointInfo};
+pub use poll::PollOptions;
+pub use pool::ClientPool;
+pub use priority::PriorityArray;
+pub use profile::{DeviceProfile, Latency, Segmentation};
+pub use references::{
+    check_references, DanglingReference, DeviceObjectPropertyReference, Reference,
+    ReferenceProblem,
+};
+pub use schedule::{ScheduleEdit, ScheduleEditReport};
+pub use services::{ObjectTypesSupported, Service, ServicesSupported};
+pub use shard::Sharding;
+pub use snapshot::{ObjectState, Snapshot, SnapshotChange};
+pub use subscription::{
+    ActiveSubscription, CleanupReport, CovCallback, CovNotification, CovValue,
+    DeviceSubscription, Recipient, RecipientProcess, SubscriptionRegistry,
+};
+pub use trend::{LogDatum, LogRecord, TrendCollector};
+pub use units::{Units, UnknownUnits};
+pub use value::{Date, DateTime, Time, Value, UNSPECIFIED};
+#[cfg(feature = "bacnet-sc")]
+pub use sc::{ScConfig, ScIo, Vmac};
 
 // Re-export commonly used embedded-bacnet types
 pub use embedded_bacnet::application_protocol::primitives::data_value::{
@@ -21,3 +94,4 @@ pub use embedded_bacnet::application_protocol::services::write_property::WritePr
 pub use embedded_bacnet::common::object_id::{ObjectId, ObjectType};
 pub use embedded_bacnet::common::property_id::PropertyId;
 pub use embedded_bacnet::common::spec::Binary;
+pub use embedded_bacnet::simple::NetworkIo;
@@ -0,0 +1,393 @@
+//! Receiving what devices send without being asked.
+//!
+//! Controllers announce themselves with I-Am on startup, push COV and event
+//! notifications to the recipients configured in them, and synchronize
+//! clocks. A [`Listener`] binds the BACnet port, decodes these unconfirmed
+//! requests and hands them to registered handlers and to
+//! [`Notifications`] streams.
+
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+};
+
+use futures_util::Stream;
+use log::{debug, warn};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+
+use crate::{
+    apdu::{self, Apdu, Decoder},
+    discover::Device,
+    error::{DecodeError, Error},
+    event::EventNotification,
+    io::{self, DEFAULT_PORT},
+    subscription::CovNotification,
+    value::DateTime,
+};
+
+/// Notifications buffered per stream before new ones are dropped.
+const STREAM_CAPACITY: usize = 1000;
+
+/// An unconfirmed request received by a [`Listener`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    IAm(Device),
+    Cov(CovNotification),
+    Event(EventNotification),
+    /// A TimeSynchronization, or a UTCTimeSynchronization when `utc` is set.
+    TimeSync {
+        time: DateTime,
+        utc: bool,
+    },
+}
+
+impl Notification {
+    /// Decode the notification carried by a frame, `None` for other
+    /// frames.
+    pub(crate) fn decode(frame: &[u8], source: SocketAddr) -> Option<Result<Self, DecodeError>> {
+        let Apdu::UnconfirmedRequest { service, data } = apdu::parse_frame(frame)?.apdu else {
+            return None;
+        };
+        let notification = match service {
+            apdu::unconfirmed::I_AM => decode_i_am(data, source).map(Self::IAm),
+            apdu::unconfirmed::COV_NOTIFICATION => CovNotification::decode(data).map(Self::Cov),
+            apdu::unconfirmed::EVENT_NOTIFICATION => {
+                EventNotification::decode(data).map(Self::Event)
+            }
+            apdu::unconfirmed::TIME_SYNCHRONIZATION
+            | apdu::unconfirmed::UTC_TIME_SYNCHRONIZATION => {
+                DateTime::decode(&mut Decoder::new(data)).map(|time| Self::TimeSync {
+                    time,
+                    utc: service == apdu::unconfirmed::UTC_TIME_SYNCHRONIZATION,
+                })
+            }
+            _ => return None,
+        };
+        Some(notification)
+    }
+}
+
+fn decode_i_am(data: &[u8], addr: SocketAddr) -> Result<Device, DecodeError> {
+    let mut decoder = Decoder::new(data);
+    let device = decoder.object_id()?;
+    // max-APDU and segmentation
+    decoder.unsigned()?;
+    decoder.enumerated()?;
+    let vendor_id = decoder.unsigned()? as u16;
+    Ok(Device {
+        id: device.id,
+        vendor_id,
+        addr,
+    })
+}
+
+/// Handler invoked for each notification, with the address it came from.
+pub type NotificationHandler = Arc<dyn Fn(SocketAddr, &Notification) + Send + Sync>;
+
+/// Receives notifications on a UDP port until dropped.
+///
+/// The socket is bound with address reuse, so discovery and other BACnet
+/// software on the host can use the port too. Broadcasts reach every
+/// socket, but on Linux unicast datagrams go to only one of them.
+///
+/// Clones share the socket and the registered handlers.
+#[derive(Clone)]
+pub struct Listener {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listener")
+            .field("socket", &self.shared.socket)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Shared {
+    socket: Arc<UdpSocket>,
+    handlers: Mutex<Vec<NotificationHandler>>,
+    streams: Mutex<Vec<mpsc::Sender<(SocketAddr, Notification)>>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+impl Shared {
+    fn dispatch(&self, source: SocketAddr, notification: Notification) {
+        let handlers = self.handlers.lock().unwrap().clone();
+        for handler in handlers {
+            handler(source, &notification);
+        }
+        self.streams.lock().unwrap().retain(|stream| {
+            match stream.try_send((source, notification.clone())) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    debug!("Notification stream full, dropping notification");
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
+
+impl Listener {
+    /// Listen on the BACnet port (47808) of every interface.
+    pub async fn new() -> Result<Self, Error> {
+        Self::bind(SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT))).await
+    }
+
+    /// Listen on the given local address.
+    pub async fn bind(local: SocketAddr) -> Result<Self, Error> {
+        let socket = Arc::new(io::bind_shared(local)?);
+        let shared = Arc::new(Shared {
+            socket: socket.clone(),
+            handlers: Mutex::new(Vec::new()),
+            streams: Mutex::new(Vec::new()),
+            task: Mutex::new(None),
+        });
+        let task = tokio::spawn(listen(Arc::downgrade(&shared), socket));
+        *shared.task.lock().unwrap() = Some(task);
+        Ok(Self { shared })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.shared.socket.local_addr()?)
+    }
+
+    /// Call `handler` for every notification received from now on.
+    ///
+    /// Handlers run on the listener's task, so they should return quickly.
+    pub fn on_notification(
+        &self,
+        handler: impl Fn(SocketAddr, &Notification) + Send + Sync + 'static,
+    ) {
+        self.shared.handlers.lock().unwrap().push(Arc::new(handler));
+    }
+
+    /// A stream of the notifications received from now on.
+    ///
+    /// Up to 1000 notifications are buffered; further ones are dropped
+    /// until the stream catches up.
+    pub fn notifications(&self) -> Notifications {
+        let (sender, receiver) = mpsc::channel(STREAM_CAPACITY);
+        self.shared.streams.lock().unwrap().push(sender);
+        Notifications { receiver }
+    }
+}
+
+async fn listen(shared: Weak<Shared>, socket: Arc<UdpSocket>) {
+    let mut buf = vec![0u8; 1500];
+    loop {
+        let (n, source) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) => {
+                warn!("Listener socket receive failed: {err}");
+                continue;
+            }
+        };
+        let notification = match Notification::decode(&buf[..n], source) {
+            None => continue,
+            Some(Ok(notification)) => notification,
+            Some(Err(err)) => {
+                debug!("Ignoring malformed notification from {source}: {err:?}");
+                continue;
+            }
+        };
+        let Some(shared) = shared.upgrade() else {
+            break;
+        };
+        shared.dispatch(source, notification);
+    }
+}
+
+/// Notifications received by a [`Listener`], with the address each came
+/// from. Ends when the listener is dropped.
+#[derive(Debug)]
+pub struct Notifications {
+    receiver: mpsc::Receiver<(SocketAddr, Notification)>,
+}
+
+impl Notifications {
+    pub async fn recv(&mut self) -> Option<(SocketAddr, Notification)> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for Notifications {
+    type Item = (SocketAddr, Notification);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        apdu::Encoder,
+        subscription::CovValue,
+        value::{Date, Time, Value},
+        ObjectId, ObjectType, PropertyId,
+    };
+
+    fn source() -> SocketAddr {
+        SocketAddr::from(([192, 168, 1, 20], DEFAULT_PORT))
+    }
+
+    fn unconfirmed(service: u8, request: Encoder) -> Vec<u8> {
+        let apdu = apdu::unconfirmed_request(service, &request.into_bytes());
+        apdu::unicast_frame(&apdu, false)
+    }
+
+    fn i_am(instance: u32) -> Vec<u8> {
+        let mut request = Encoder::new();
+        request.object_id(ObjectId::new(ObjectType::ObjectDevice, instance));
+        request.unsigned(1476);
+        // no-segmentation
+        request.enumerated(3);
+        request.unsigned(42);
+        unconfirmed(apdu::unconfirmed::I_AM, request)
+    }
+
+    #[test]
+    fn i_am_is_decoded() {
+        let notification = Notification::decode(&i_am(1234), source()).unwrap();
+        let device = Device {
+            id: 1234,
+            vendor_id: 42,
+            addr: source(),
+        };
+        assert_eq!(notification, Ok(Notification::IAm(device)));
+    }
+
+    #[test]
+    fn cov_notification_is_decoded() {
+        let device = ObjectId::new(ObjectType::ObjectDevice, 1234);
+        let object_id = ObjectId::new(ObjectType::ObjectAnalogValue, 1);
+        let mut request = Encoder::new();
+        request.context_unsigned(0, 7);
+        request.context_object_id(1, device);
+        request.context_object_id(2, object_id);
+        request.context_unsigned(3, 300);
+        request.opening(4);
+        request.context_enumerated(0, PropertyId::PropPresentValue as u32);
+        request.opening(2);
+        request.real(21.5);
+        request.closing(2);
+        request.closing(4);
+        let frame = unconfirmed(apdu::unconfirmed::COV_NOTIFICATION, request);
+
+        let expected = CovNotification {
+            process_id: 7,
+            device,
+            object_id,
+            time_remaining: Duration::from_secs(300),
+            values: vec![CovValue {
+                property_id: PropertyId::PropPresentValue as u32,
+                array_index: None,
+                value: Value::Real(21.5),
+                priority: None,
+            }],
+        };
+        assert_eq!(
+            Notification::decode(&frame, source()).unwrap(),
+            Ok(Notification::Cov(expected))
+        );
+    }
+
+    #[test]
+    fn time_synchronizations_are_decoded() {
+        let date = Date {
+            year: 2024,
+            month: 3,
+            day: 31,
+            weekday: 7,
+        };
+        let time = DateTime::new(
+            date,
+            Time {
+                hour: 2,
+                minute: 0,
+                second: 0,
+                hundredths: 0,
+            },
+        );
+        for (service, utc) in [
+            (apdu::unconfirmed::TIME_SYNCHRONIZATION, false),
+            (apdu::unconfirmed::UTC_TIME_SYNCHRONIZATION, true),
+        ] {
+            let mut request = Encoder::new();
+            time.encode(&mut request);
+            let frame = unconfirmed(service, request);
+            assert_eq!(
+                Notification::decode(&frame, source()).unwrap(),
+                Ok(Notification::TimeSync { time, utc })
+            );
+        }
+    }
+
+    #[test]
+    fn other_frames_are_not_notifications() {
+        let who_is = unconfirmed(apdu::unconfirmed::WHO_IS, Encoder::new());
+        assert_eq!(Notification::decode(&who_is, source()), None);
+
+        let mut truncated = i_am(1234);
+        truncated.pop();
+        assert!(matches!(
+            Notification::decode(&truncated, source()),
+            Some(Err(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn listener_streams_notifications() {
+        let listener = Listener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let mut notifications = listener.notifications();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        listener.on_notification({
+            let received = received.clone();
+            move |source, notification| {
+                received
+                    .lock()
+                    .unwrap()
+                    .push((source, notification.clone()))
+            }
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let source = socket.local_addr().unwrap();
+        socket
+            .send_to(&i_am(1234), listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (from, notification) = notifications.recv().await.unwrap();
+        assert_eq!(from, source);
+        let device = Device {
+            id: 1234,
+            vendor_id: 42,
+            addr: source,
+        };
+        assert_eq!(notification, Notification::IAm(device));
+        assert_eq!(*received.lock().unwrap(), [(source, notification)]);
+    }
+}
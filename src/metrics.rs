@@ -0,0 +1,191 @@
+//! Request metrics recorded through the [`metrics`](https://docs.rs/metrics)
+//! facade, with the `metrics` feature. Install any recorder, e.g. a
+//! Prometheus exporter, to collect them:
+//!
+//! - `bacnet_requests_total{peer, service}`: confirmed requests sent.
+//! - `bacnet_request_errors_total{peer, service, kind}`: requests that
+//!   failed, by kind of error, `timeout` included.
+//! - `bacnet_request_duration_seconds{peer, service}`: histogram of the
+//!   time requests took, segmented acks included, whatever their outcome.
+//! - `bacnet_timeouts_total{peer}`: socket reads or writes that timed out.
+//! - `bacnet_retransmissions_total{peer, service}`: requests retransmitted
+//!   by a [`crate::middleware::Retry`] layer.
+//! - `bacnet_pool_dropped_responses_total{peer}`: responses a
+//!   [`crate::ClientPool`] received for no pending request, usually late
+//!   answers to requests that timed out.
+//!
+//! Requests, their errors and timeouts are recorded from the frames a
+//! transport sends and receives, so they cover every client method: by the
+//! [`crate::middleware::Metrics`] layer, under the `peer` label given to it,
+//! and by the handles of a [`crate::ClientPool`], under the device's
+//! address.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::Mutex,
+};
+
+use ::metrics::{counter, histogram};
+use tokio::time::Instant;
+
+use crate::apdu::{self, confirmed, Apdu};
+
+/// Follows the confirmed requests a transport sends to a peer until their
+/// response, to record them.
+#[derive(Debug)]
+pub(crate) struct RequestTracker {
+    peer: String,
+    /// Service and time sent of the requests awaiting a response, by
+    /// invoke ID.
+    pending: Mutex<HashMap<u8, (u8, Instant)>>,
+}
+
+impl RequestTracker {
+    pub(crate) fn new(peer: impl Into<String>) -> Self {
+        Self {
+            peer: peer.into(),
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Note a frame being sent. Returns the invoke ID of a confirmed
+    /// request, to pass to [`RequestTracker::write_failed`].
+    pub(crate) fn sending(&self, frame: &[u8]) -> Option<u8> {
+        let (invoke_id, service) = crate::middleware::confirmed_request(frame)?;
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(invoke_id, (service, Instant::now()));
+        Some(invoke_id)
+    }
+
+    /// Record the request `invoke_id`, if any, as failed to send.
+    pub(crate) fn write_failed(&self, invoke_id: Option<u8>, err: &io::Error) {
+        if err.kind() == ErrorKind::TimedOut {
+            record_timeout(&self.peer);
+        }
+        if let Some(invoke_id) = invoke_id {
+            self.complete(invoke_id, Some(error_kind(err)));
+        }
+    }
+
+    /// Record the request a received frame completes, if any.
+    pub(crate) fn received(&self, frame: &[u8]) {
+        let Some(received) = apdu::parse_frame(frame) else {
+            return;
+        };
+        match received.apdu {
+            Apdu::SimpleAck { invoke_id }
+            | Apdu::ComplexAck {
+                invoke_id,
+                segment: None,
+                ..
+            } => self.complete(invoke_id, None),
+            Apdu::ComplexAck {
+                invoke_id,
+                segment: Some(segment),
+                ..
+            } if !segment.more => self.complete(invoke_id, None),
+            Apdu::Error { invoke_id, .. } => self.complete(invoke_id, Some("service")),
+            Apdu::Reject { invoke_id, .. } => self.complete(invoke_id, Some("reject")),
+            Apdu::Abort { invoke_id, .. } => self.complete(invoke_id, Some("abort")),
+            _ => {}
+        }
+    }
+
+    /// Record every pending request as failed, as a read failed.
+    pub(crate) fn read_failed(&self, err: &io::Error) {
+        if err.kind() == ErrorKind::TimedOut {
+            record_timeout(&self.peer);
+        }
+        let pending: Vec<_> = self.pending.lock().unwrap().drain().collect();
+        for (_, (service, started)) in pending {
+            record_request(&self.peer, service, started, Some(error_kind(err)));
+        }
+    }
+
+    fn complete(&self, invoke_id: u8, error: Option<&'static str>) {
+        let request = self.pending.lock().unwrap().remove(&invoke_id);
+        if let Some((service, started)) = request {
+            record_request(&self.peer, service, started, error);
+        }
+    }
+}
+
+fn error_kind(err: &io::Error) -> &'static str {
+    match err.kind() {
+        ErrorKind::TimedOut => "timeout",
+        _ => "transport",
+    }
+}
+
+/// Record a confirmed request of `service` sent to `peer` at `started`,
+/// which failed with the given kind of error, if any.
+pub(crate) fn record_request(
+    peer: &str,
+    service: u8,
+    started: Instant,
+    error: Option<&'static str>,
+) {
+    let service = service_name(service);
+    counter!("bacnet_requests_total", "peer" => peer.to_string(), "service" => service)
+        .increment(1);
+    histogram!(
+        "bacnet_request_duration_seconds",
+        "peer" => peer.to_string(),
+        "service" => service,
+    )
+    .record(started.elapsed().as_secs_f64());
+    if let Some(kind) = error {
+        counter!(
+            "bacnet_request_errors_total",
+            "peer" => peer.to_string(),
+            "service" => service,
+            "kind" => kind,
+        )
+        .increment(1);
+    }
+}
+
+/// Record a socket read or write to `peer` that timed out.
+pub(crate) fn record_timeout(peer: &str) {
+    counter!("bacnet_timeouts_total", "peer" => peer.to_string()).increment(1);
+}
+
+/// Record a confirmed request of `service` retransmitted to `peer`.
+pub(crate) fn record_retransmission(peer: &str, service: u8) {
+    counter!(
+        "bacnet_retransmissions_total",
+        "peer" => peer.to_string(),
+        "service" => service_name(service),
+    )
+    .increment(1);
+}
+
+/// Record a response from `peer` that a pool had no request for.
+pub(crate) fn record_dropped_response(peer: SocketAddr) {
+    counter!("bacnet_pool_dropped_responses_total", "peer" => peer.to_string()).increment(1);
+}
+
+fn service_name(service: u8) -> &'static str {
+    match service {
+        confirmed::ACKNOWLEDGE_ALARM => "acknowledge-alarm",
+        confirmed::COV_NOTIFICATION => "confirmed-cov-notification",
+        confirmed::SUBSCRIBE_COV => "subscribe-cov",
+        confirmed::ATOMIC_READ_FILE => "atomic-read-file",
+        confirmed::ATOMIC_WRITE_FILE => "atomic-write-file",
+        confirmed::ADD_LIST_ELEMENT => "add-list-element",
+        confirmed::REMOVE_LIST_ELEMENT => "remove-list-element",
+        confirmed::READ_PROPERTY => "read-property",
+        confirmed::READ_PROPERTY_MULTIPLE => "read-property-multiple",
+        confirmed::WRITE_PROPERTY => "write-property",
+        confirmed::WRITE_PROPERTY_MULTIPLE => "write-property-multiple",
+        confirmed::PRIVATE_TRANSFER => "confirmed-private-transfer",
+        confirmed::REINITIALIZE_DEVICE => "reinitialize-device",
+        confirmed::READ_RANGE => "read-range",
+        confirmed::GET_EVENT_INFORMATION => "get-event-information",
+        _ => "other",
+    }
+}
@@ -0,0 +1,482 @@
+//! Layers wrapping the transport of a [`Client`](crate::Client), to add
+//! behavior to every request without forking the client: logging,
+//! authorization checks, request rewriting, simulation shims, and the
+//! retries, rate limiting and metrics shipped here.
+//!
+//! A [`Layer`] turns a `NetworkIo` into another, so layers stack like tower
+//! layers, the last applied seeing frames first on the way out:
+//!
+//! ```ignore
+//! use async_bacnet::{middleware::{Intercept, NetworkIoExt, RateLimit, Retry}, Client, TokioUdpIo};
+//!
+//! let io = TokioUdpIo::new(peer)
+//!     .await?
+//!     .layer(Retry::new(3))
+//!     .layer(RateLimit::per_second(20))
+//!     .layer(Intercept::new(|direction, frame: &mut Vec<u8>| {
+//!         log::trace!("{direction:?} {frame:02x?}");
+//!         Ok(())
+//!     }));
+//! let mut client = Client::from_io(io);
+//! ```
+//!
+//! Layers see whole BACnet/IP frames. A client over a layered transport is
+//! a `Client<T>`, so the methods of UDP clients only, such as
+//! `receive_notifications`, aren't available on it.
+
+use std::{
+    io::{self, ErrorKind},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use embedded_bacnet::simple::NetworkIo;
+use log::debug;
+use tokio::time::{sleep_until, Instant};
+
+use crate::{
+    apdu::{self, Apdu},
+    io::PacketDirection,
+};
+
+/// Wraps a transport into another, see the [module](self) documentation.
+pub trait Layer<T: NetworkIo> {
+    type Io: NetworkIo;
+
+    fn layer(&self, inner: T) -> Self::Io;
+}
+
+/// Apply layers to a transport with method calls.
+pub trait NetworkIoExt: NetworkIo + Sized {
+    fn layer<L: Layer<Self>>(self, layer: L) -> L::Io {
+        layer.layer(self)
+    }
+}
+
+impl<T: NetworkIo> NetworkIoExt for T {}
+
+/// The invoke ID and service choice of a confirmed request frame.
+pub(crate) fn confirmed_request(frame: &[u8]) -> Option<(u8, u8)> {
+    match apdu::parse_frame(frame)?.apdu {
+        Apdu::ConfirmedRequest {
+            invoke_id, service, ..
+        } => Some((invoke_id, service)),
+        _ => None,
+    }
+}
+
+/// Callback of an [`Intercept`] layer.
+pub type InterceptFn = Arc<dyn Fn(PacketDirection, &mut Vec<u8>) -> io::Result<()> + Send + Sync>;
+
+/// Pass every frame sent or received to a callback, which can inspect it,
+/// rewrite it in place, or refuse it with an error returned in its stead.
+///
+/// A received frame rewritten beyond the read buffer fails the read with
+/// `InvalidData`.
+#[derive(Clone)]
+pub struct Intercept {
+    callback: InterceptFn,
+}
+
+impl Intercept {
+    pub fn new(
+        callback: impl Fn(PacketDirection, &mut Vec<u8>) -> io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            callback: Arc::new(callback),
+        }
+    }
+}
+
+impl<T: NetworkIo<Error = io::Error>> Layer<T> for Intercept {
+    type Io = InterceptIo<T>;
+
+    fn layer(&self, inner: T) -> InterceptIo<T> {
+        InterceptIo {
+            inner,
+            callback: self.callback.clone(),
+        }
+    }
+}
+
+/// A transport wrapped by an [`Intercept`] layer.
+#[derive(Clone)]
+pub struct InterceptIo<T> {
+    inner: T,
+    callback: InterceptFn,
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for InterceptIo<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InterceptIo")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: NetworkIo<Error = io::Error>> NetworkIo for InterceptIo<T> {
+    type Error = io::Error;
+
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = self.inner.read(buf).await?;
+        let mut frame = buf[..n].to_vec();
+        (self.callback)(PacketDirection::Received, &mut frame)?;
+        if frame.len() > buf.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "intercepted frame of {} bytes exceeds the {}-byte buffer",
+                    frame.len(),
+                    buf.len()
+                ),
+            ));
+        }
+        buf[..frame.len()].copy_from_slice(&frame);
+        Ok(frame.len())
+    }
+
+    async fn write(&self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mut frame = buf.to_vec();
+        (self.callback)(PacketDirection::Sent, &mut frame)?;
+        self.inner.write(&frame).await?;
+        Ok(buf.len())
+    }
+}
+
+/// Retransmit a confirmed request when its response times out, up to
+/// `retries` times, as the APDU retries of the standard.
+///
+/// Requests whose response started arriving, e.g. segmented acks, aren't
+/// retransmitted. Retransmissions are counted in
+/// `bacnet_retransmissions_total` with the `metrics` feature.
+#[derive(Debug, Clone)]
+pub struct Retry {
+    retries: u32,
+    peer: String,
+}
+
+impl Retry {
+    pub fn new(retries: u32) -> Self {
+        Self {
+            retries,
+            peer: String::new(),
+        }
+    }
+
+    /// Label the retransmissions logged and recorded with `peer`, e.g. the
+    /// device's address.
+    pub fn with_peer(mut self, peer: impl Into<String>) -> Self {
+        self.peer = peer.into();
+        self
+    }
+}
+
+impl<T: NetworkIo<Error = io::Error>> Layer<T> for Retry {
+    type Io = RetryIo<T>;
+
+    fn layer(&self, inner: T) -> RetryIo<T> {
+        RetryIo {
+            inner,
+            retries: self.retries,
+            peer: self.peer.clone(),
+            pending: Arc::default(),
+        }
+    }
+}
+
+/// A transport wrapped by a [`Retry`] layer.
+///
+/// Clones share the request awaiting a response, as the client holds
+/// several clones of its transport.
+#[derive(Debug, Clone)]
+pub struct RetryIo<T> {
+    inner: T,
+    retries: u32,
+    peer: String,
+    pending: Arc<Mutex<Option<(u8, Vec<u8>)>>>,
+}
+
+impl<T: NetworkIo<Error = io::Error>> NetworkIo for RetryIo<T> {
+    type Error = io::Error;
+
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let mut attempts = 0;
+        loop {
+            match self.inner.read(buf).await {
+                Ok(n) => {
+                    let received = apdu::parse_frame(&buf[..n]).and_then(|r| r.apdu.invoke_id());
+                    let mut pending = self.pending.lock().unwrap();
+                    if received.is_some() && pending.as_ref().map(|(id, _)| *id) == received {
+                        *pending = None;
+                    }
+                    return Ok(n);
+                }
+                Err(err) if err.kind() == ErrorKind::TimedOut && attempts < self.retries => {
+                    let Some((invoke_id, frame)) = self.pending.lock().unwrap().clone() else {
+                        return Err(err);
+                    };
+                    attempts += 1;
+                    debug!(
+                        "Retransmitting request {invoke_id} to {:?}, attempt {attempts}",
+                        self.peer
+                    );
+                    #[cfg(feature = "metrics")]
+                    if let Some((_, service)) = confirmed_request(&frame) {
+                        crate::metrics::record_retransmission(&self.peer, service);
+                    }
+                    self.inner.write(&frame).await?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn write(&self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if let Some((invoke_id, _)) = confirmed_request(buf) {
+            *self.pending.lock().unwrap() = Some((invoke_id, buf.to_vec()));
+        }
+        self.inner.write(buf).await
+    }
+}
+
+/// Space confirmed requests by at least an interval, for devices that drop
+/// requests sent in bursts.
+///
+/// Transports wrapped by the same layer share its budget, so clients
+/// built from one `RateLimit` together stay under the rate.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    interval: Duration,
+    next: Arc<Mutex<Instant>>,
+}
+
+impl RateLimit {
+    /// At most one request per `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// At most `requests` requests per second.
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(Duration::from_secs(1) / requests.max(1))
+    }
+}
+
+impl<T: NetworkIo<Error = io::Error>> Layer<T> for RateLimit {
+    type Io = RateLimitIo<T>;
+
+    fn layer(&self, inner: T) -> RateLimitIo<T> {
+        RateLimitIo {
+            inner,
+            limit: self.clone(),
+        }
+    }
+}
+
+/// A transport wrapped by a [`RateLimit`] layer.
+#[derive(Debug, Clone)]
+pub struct RateLimitIo<T> {
+    inner: T,
+    limit: RateLimit,
+}
+
+impl<T: NetworkIo<Error = io::Error>> NetworkIo for RateLimitIo<T> {
+    type Error = io::Error;
+
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.inner.read(buf).await
+    }
+
+    async fn write(&self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if confirmed_request(buf).is_some() {
+            let at = {
+                let mut next = self.limit.next.lock().unwrap();
+                let at = (*next).max(Instant::now());
+                *next = at + self.limit.interval;
+                at
+            };
+            sleep_until(at).await;
+        }
+        self.inner.write(buf).await
+    }
+}
+
+/// Record the confirmed requests sent through a transport, with their
+/// errors and latency, and its timeouts, under the given `peer` label, with
+/// the `metrics` feature. See [`crate::metrics`] for the metrics.
+///
+/// Requests are followed from the frames seen, so apply this layer after
+/// [`Retry`], for a retried request to count once. Requests abandoned on a
+/// client deadline aren't recorded.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    peer: String,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    pub fn new(peer: impl Into<String>) -> Self {
+        Self { peer: peer.into() }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<T: NetworkIo<Error = io::Error>> Layer<T> for Metrics {
+    type Io = MetricsIo<T>;
+
+    fn layer(&self, inner: T) -> MetricsIo<T> {
+        MetricsIo {
+            inner,
+            tracker: Arc::new(crate::metrics::RequestTracker::new(self.peer.clone())),
+        }
+    }
+}
+
+/// A transport wrapped by a [`Metrics`] layer.
+///
+/// Clones share the requests awaiting a response, with the service and
+/// time they were sent.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone)]
+pub struct MetricsIo<T> {
+    inner: T,
+    tracker: Arc<crate::metrics::RequestTracker>,
+}
+
+#[cfg(feature = "metrics")]
+impl<T: NetworkIo<Error = io::Error>> NetworkIo for MetricsIo<T> {
+    type Error = io::Error;
+
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = self
+            .inner
+            .read(buf)
+            .await
+            .inspect_err(|err| self.tracker.read_failed(err))?;
+        self.tracker.received(&buf[..n]);
+        Ok(n)
+    }
+
+    async fn write(&self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let invoke_id = self.tracker.sending(buf);
+        self.inner
+            .write(buf)
+            .await
+            .inspect_err(|err| self.tracker.write_failed(invoke_id, err))
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{
+        apdu::Encoder,
+        testing::{MockIo, VirtualDevice},
+        ObjectId, ObjectType, PropertyId,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn mock() -> MockIo {
+        let mut io = MockIo::new(VirtualDevice::new(1234));
+        io.set_timeout(TIMEOUT);
+        io
+    }
+
+    /// A ReadProperty of the device's object name.
+    fn read_property(invoke_id: u8) -> Vec<u8> {
+        let mut request = Encoder::new();
+        request.context_object_id(0, ObjectId::new(ObjectType::ObjectDevice, 1234));
+        request.context_enumerated(1, PropertyId::PropObjectName as u32);
+        let apdu = apdu::confirmed_request(
+            invoke_id,
+            apdu::confirmed::READ_PROPERTY,
+            &request.into_bytes(),
+        );
+        apdu::unicast_frame(&apdu, true)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_retransmits_until_out_of_retries() {
+        let mock = mock();
+        mock.device().set_loss(1.0);
+        let io = mock.clone().layer(Retry::new(2));
+
+        io.write(&read_property(1)).await.unwrap();
+        let start = Instant::now();
+        let err = io.read(&mut [0; 1500]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert_eq!(start.elapsed(), TIMEOUT * 3);
+        assert_eq!(mock.device().requests(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_stops_once_the_response_arrives() {
+        let mock = mock();
+        let io = mock.clone().layer(Retry::new(2));
+
+        io.write(&read_property(1)).await.unwrap();
+        let mut buf = [0; 1500];
+        let n = io.read(&mut buf).await.unwrap();
+        assert_eq!(
+            apdu::parse_frame(&buf[..n]).and_then(|r| r.apdu.invoke_id()),
+            Some(1)
+        );
+        // Nothing left to retransmit
+        let err = io.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert_eq!(mock.device().requests(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_spaces_confirmed_requests() {
+        let interval = Duration::from_millis(100);
+        let io = mock().layer(RateLimit::new(interval));
+
+        let start = Instant::now();
+        io.write(&read_property(1)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        io.write(&read_property(2)).await.unwrap();
+        assert_eq!(start.elapsed(), interval);
+    }
+
+    #[tokio::test]
+    async fn intercept_errors_fail_the_operation() {
+        let refuse = |refused: PacketDirection| {
+            Intercept::new(move |direction, _: &mut Vec<u8>| {
+                if direction == refused {
+                    return Err(io::Error::new(ErrorKind::PermissionDenied, "refused"));
+                }
+                Ok(())
+            })
+        };
+
+        let mock = mock();
+        let io = mock.clone().layer(refuse(PacketDirection::Sent));
+        let err = io.write(&read_property(1)).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(mock.device().requests(), 0);
+
+        let io = mock.layer(refuse(PacketDirection::Received));
+        io.write(&read_property(1)).await.unwrap();
+        let err = io.read(&mut [0; 1500]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn intercepted_frames_beyond_the_buffer_fail_the_read() {
+        let io = mock().layer(Intercept::new(|direction, frame: &mut Vec<u8>| {
+            if direction == PacketDirection::Received {
+                frame.resize(frame.len() + 64, 0);
+            }
+            Ok(())
+        }));
+        io.write(&read_property(1)).await.unwrap();
+        let err = io.read(&mut [0; 64]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
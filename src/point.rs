@@ -0,0 +1,255 @@
+use crate::{
+    client::Client,
+    error::{DecodeError, Error},
+    health::StatusFlags,
+    io::TokioUdpIo,
+    priority::PriorityArray,
+    units::Units,
+    value::Value,
+    NetworkIo, ObjectId, ObjectType, PropertyId,
+};
+
+/// Point metadata cached by the client after the first read.
+///
+/// Each field is `None` until read; the inner `Option` is `None` when the
+/// object doesn't have the property.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PointMetadata {
+    pub name: Option<String>,
+    pub units: Option<Option<Units>>,
+    pub state_text: Option<Option<Vec<String>>>,
+}
+
+/// The static metadata of an object, see [`Client::read_point_info`].
+///
+/// Each field is `None` when the object doesn't have the property or it
+/// failed to read.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointInfo {
+    pub description: Option<String>,
+    pub units: Option<Units>,
+    pub min_present_value: Option<f64>,
+    pub max_present_value: Option<f64>,
+    pub cov_increment: Option<f64>,
+    pub notification_class: Option<u32>,
+}
+
+/// A handle on a single BACnet object, see [`Client::point`].
+///
+/// Metadata that rarely changes (object name, units, state texts) is read
+/// once and cached in the client, so it survives dropping the handle.
+#[derive(Debug)]
+pub struct Point<'a, T: NetworkIo = TokioUdpIo> {
+    client: &'a mut Client<T>,
+    object_id: ObjectId,
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Get a handle on the given object.
+    pub fn point(&mut self, object_id: ObjectId) -> Point<'_, T> {
+        Point {
+            client: self,
+            object_id,
+        }
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read the static metadata of `objects`, batched as
+    /// [`Client::read_many`] does, e.g. to attach to exported values once
+    /// so consumers don't read it themselves.
+    ///
+    /// The units read are cached for [`Point::units`].
+    pub async fn read_point_info(
+        &mut self,
+        objects: &[ObjectId],
+    ) -> Result<Vec<(ObjectId, PointInfo)>, Error<T>> {
+        const PROPERTIES: [PropertyId; 6] = [
+            PropertyId::PropDescription,
+            PropertyId::PropUnits,
+            PropertyId::PropMinPresValue,
+            PropertyId::PropMaxPresValue,
+            PropertyId::PropCovIncrement,
+            PropertyId::PropNotificationClass,
+        ];
+        let requests = objects
+            .iter()
+            .flat_map(|object_id| PROPERTIES.map(|property_id| (*object_id, property_id)))
+            .collect();
+        let results = self.read_many(requests).await?;
+
+        let mut infos = Vec::with_capacity(objects.len());
+        for (object_id, results) in objects.iter().zip(results.chunks(PROPERTIES.len())) {
+            let value = |i: usize| results[i].as_ref().ok();
+            let info = PointInfo {
+                description: value(0).and_then(Value::as_str).map(str::to_string),
+                units: value(1).and_then(Value::as_u32).map(Units),
+                min_present_value: value(2).and_then(Value::as_f64),
+                max_present_value: value(3).and_then(Value::as_f64),
+                cov_increment: value(4).and_then(Value::as_f64),
+                notification_class: value(5).and_then(Value::as_u32),
+            };
+            match &results[1] {
+                Ok(_) => self.point_metadata(*object_id).units = Some(info.units),
+                Err(Error::Service(err)) if err.is_unknown_property() => {
+                    self.point_metadata(*object_id).units = Some(None)
+                }
+                Err(_) => {}
+            }
+            infos.push((*object_id, info));
+        }
+        Ok(infos)
+    }
+}
+
+impl<T: NetworkIo + Clone> Point<'_, T> {
+    pub fn object_id(&self) -> ObjectId {
+        self.object_id
+    }
+
+    /// Whether the object is a multi-state input, output or value.
+    pub fn is_multi_state(&self) -> bool {
+        matches!(
+            self.object_id.object_type,
+            ObjectType::ObjectMultiStateInput
+                | ObjectType::ObjectMultiStateOutput
+                | ObjectType::ObjectMultiStateValue
+        )
+    }
+
+    pub async fn read_present_value(&mut self) -> Result<Value, Error<T>> {
+        self.client
+            .read_value(self.object_id, PropertyId::PropPresentValue)
+            .await
+    }
+
+    /// Write the present value, at the given priority for commandable objects.
+    pub async fn write_present_value(
+        &mut self,
+        value: &Value,
+        priority: Option<u8>,
+    ) -> Result<(), Error<T>> {
+        self.client
+            .write_value(
+                self.object_id,
+                PropertyId::PropPresentValue,
+                value,
+                priority,
+            )
+            .await
+    }
+
+    pub async fn priority_array(&mut self) -> Result<PriorityArray, Error<T>> {
+        self.client.read_priority_array(self.object_id).await
+    }
+
+    /// Relinquish the command at the given priority (1-16).
+    pub async fn relinquish(&mut self, priority: u8) -> Result<(), Error<T>> {
+        self.client.relinquish(self.object_id, priority).await
+    }
+
+    pub async fn status_flags(&mut self) -> Result<StatusFlags, Error<T>> {
+        self.client.read_status_flags(self.object_id).await
+    }
+
+    /// The object name, read once then cached.
+    pub async fn name(&mut self) -> Result<String, Error<T>> {
+        if let Some(name) = &self.client.point_metadata(self.object_id).name {
+            return Ok(name.clone());
+        }
+        let name = self
+            .client
+            .read_string(self.object_id, PropertyId::PropObjectName)
+            .await?
+            .text_lossy();
+        self.client.point_metadata(self.object_id).name = Some(name.clone());
+        Ok(name)
+    }
+
+    /// The engineering units, read once then cached. `None` for objects
+    /// without a `units` property.
+    pub async fn units(&mut self) -> Result<Option<Units>, Error<T>> {
+        if let Some(units) = self.client.point_metadata(self.object_id).units {
+            return Ok(units);
+        }
+        let units = match self
+            .client
+            .read_value(self.object_id, PropertyId::PropUnits)
+            .await
+        {
+            Ok(value) => value.as_u32().map(Units),
+            Err(Error::Service(err)) if err.is_unknown_property() => None,
+            Err(err) => return Err(err),
+        };
+        self.client.point_metadata(self.object_id).units = Some(units);
+        Ok(units)
+    }
+
+    /// The state texts of a multi-state object, read once then cached.
+    /// `None` for other objects or when the object has no `state-text`.
+    pub async fn state_text(&mut self) -> Result<Option<Vec<String>>, Error<T>> {
+        if let Some(state_text) = &self.client.point_metadata(self.object_id).state_text {
+            return Ok(state_text.clone());
+        }
+        let state_text = match self.is_multi_state() {
+            false => None,
+            true => match self
+                .client
+                .read_strings(self.object_id, PropertyId::PropStateText)
+                .await
+            {
+                Ok(strings) => Some(strings.iter().map(|s| s.text_lossy()).collect()),
+                Err(Error::Service(err)) if err.is_unknown_property() => None,
+                Err(err) => return Err(err),
+            },
+        };
+        self.client.point_metadata(self.object_id).state_text = Some(state_text.clone());
+        Ok(state_text)
+    }
+
+    /// The present value of a multi-state object as its state text.
+    pub async fn read_state_label(&mut self) -> Result<String, Error<T>> {
+        let state = self
+            .read_present_value()
+            .await?
+            .as_u32()
+            .ok_or(DecodeError("present value is not a state number"))?;
+        let state_text = self
+            .state_text()
+            .await?
+            .ok_or(DecodeError("object has no state-text"))?;
+        state
+            .checked_sub(1)
+            .and_then(|i| state_text.get(i as usize))
+            .cloned()
+            .ok_or(Error::Decode("present value has no state text"))
+    }
+
+    /// The state number of a state text, matched exactly first, then
+    /// ignoring case.
+    pub async fn state_number(&mut self, label: &str) -> Result<u32, Error<T>> {
+        let state_text = self.state_text().await?.unwrap_or_default();
+        state_text
+            .iter()
+            .position(|text| text == label)
+            .or_else(|| {
+                state_text
+                    .iter()
+                    .position(|text| text.eq_ignore_ascii_case(label))
+            })
+            .map(|i| i as u32 + 1)
+            .ok_or_else(|| Error::UnknownState(label.to_string()))
+    }
+
+    /// Write the present value of a multi-state object by state text.
+    pub async fn write_state_label(
+        &mut self,
+        label: &str,
+        priority: Option<u8>,
+    ) -> Result<(), Error<T>> {
+        let state = self.state_number(label).await?;
+        self.write_present_value(&Value::Unsigned(state as u64), priority)
+            .await
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use futures_util::Stream;
+
+use crate::{client::Client, error::Error, value::Value, NetworkIo, ObjectId, PropertyId};
+
+/// Settings for [`Client::poll_with`].
+#[derive(Debug, Copy, Clone)]
+pub struct PollOptions {
+    /// Nominal time between two reads.
+    pub interval: Duration,
+    /// Only emit numeric values that moved by at least this much since the
+    /// last emitted value. Other values are emitted whenever they change.
+    pub cov_increment: Option<f64>,
+    /// Random spread applied to each interval, as a fraction of it (0.1 = ±10%),
+    /// so that many pollers don't hit a device in lockstep.
+    pub jitter: f64,
+}
+
+impl PollOptions {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            cov_increment: None,
+            jitter: 0.1,
+        }
+    }
+
+    /// The interval with its jitter applied, drawing from the xorshift64
+    /// state `seed`.
+    pub(crate) fn next_delay(&self, seed: &mut u64) -> Duration {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        let unit = (*seed >> 11) as f64 / (1u64 << 53) as f64;
+        let factor = 1.0 + self.jitter * (unit * 2.0 - 1.0);
+        self.interval.mul_f64(factor.max(0.0))
+    }
+
+    /// Whether `value` differs enough from the `last` one emitted to be
+    /// emitted.
+    pub(crate) fn changed(&self, last: Option<&Value>, value: &Value) -> bool {
+        let Some(last) = last else {
+            return true;
+        };
+        match (self.cov_increment, last.as_f64(), value.as_f64()) {
+            (Some(increment), Some(last), Some(new)) => (new - last).abs() >= increment,
+            _ => last != value,
+        }
+    }
+}
+
+/// A seed for [`PollOptions::next_delay`], differing between pollers.
+pub(crate) fn jitter_seed(object_id: ObjectId) -> u64 {
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u32(object_id.id);
+    hasher.finish() | 1
+}
+
+struct PollState<'a, T: NetworkIo> {
+    client: &'a mut Client<T>,
+    object_id: ObjectId,
+    property_id: PropertyId,
+    options: PollOptions,
+    last: Option<Value>,
+    started: bool,
+    seed: u64,
+}
+
+impl<T: NetworkIo> PollState<'_, T> {
+    fn next_delay(&mut self) -> Duration {
+        self.options.next_delay(&mut self.seed)
+    }
+
+    fn changed(&self, value: &Value) -> bool {
+        self.options.changed(self.last.as_ref(), value)
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Poll a property and yield its value each time it changes.
+    ///
+    /// See [`Client::poll_with`].
+    pub fn poll(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<Value, Error<T>>> + '_ {
+        self.poll_with(object_id, property_id, PollOptions::new(interval))
+    }
+
+    /// Poll a property and yield its value each time it changes, giving
+    /// devices without COV support the same interface as a subscription.
+    ///
+    /// The first value is yielded immediately. Read errors are yielded and
+    /// polling continues. Polling stops when the stream is dropped.
+    pub fn poll_with(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+        options: PollOptions,
+    ) -> impl Stream<Item = Result<Value, Error<T>>> + '_ {
+        let state = PollState {
+            client: self,
+            object_id,
+            property_id,
+            options,
+            last: None,
+            started: false,
+            seed: jitter_seed(object_id),
+        };
+
+        futures_util::stream::unfold(state, |mut state| async move {
+            loop {
+                if state.started {
+                    tokio::time::sleep(state.next_delay()).await;
+                }
+                state.started = true;
+                match state
+                    .client
+                    .read_value(state.object_id, state.property_id)
+                    .await
+                {
+                    Ok(value) if state.changed(&value) => {
+                        state.last = Some(value.clone());
+                        return Some((Ok(value), state));
+                    }
+                    Ok(_) => continue,
+                    Err(err) => return Some((Err(err), state)),
+                }
+            }
+        })
+    }
+}
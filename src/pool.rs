@@ -0,0 +1,486 @@
+//! Many devices over one UDP socket.
+//!
+//! A [`ClientPool`] owns a single socket and hands out a [`Client`] per
+//! device. A dispatcher task reads every incoming datagram and routes it to
+//! the handle waiting for it, by source address and invoke ID.
+//!
+//! Each handle picks its invoke IDs independently, so the pool rewrites them
+//! on the way out to IDs unique per device, and back on the way in.
+//!
+//! With the `metrics` feature, handles record their requests under the
+//! device's address, see [`crate::metrics`].
+//!
+//! A handle keeps the invoke ID of its last request until it sends the
+//! next one or is dropped, so that a late response to a request that timed
+//! out is dropped rather than taken for the answer to another. A device has
+//! 256 invoke IDs, so requests to it only fail for lack of one with about
+//! 256 handles to it alive at once.
+//!
+//! Some devices answer from an ephemeral port rather than the one requests
+//! were sent to. Unless the device was made strict with
+//! [`ClientPool::set_port_tolerant`], a response that no request from its
+//! source address awaits goes to the request with its invoke ID sent to
+//! another port of the same IP address.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+    time::timeout,
+};
+
+use crate::{apdu, client::Client, error::Error, io::TokioUdpIo};
+
+const PDU_CONFIRMED_REQUEST: u8 = 0x0;
+const PDU_COMPLEX_ACK: u8 = 0x3;
+const PDU_SEGMENT_ACK: u8 = 0x4;
+const PDU_ABORT: u8 = 0x7;
+
+/// Shares one UDP socket between clients for many devices.
+#[derive(Debug, Clone)]
+pub struct ClientPool {
+    router: Arc<Router>,
+}
+
+impl ClientPool {
+    /// Create a pool on an ephemeral local port, allowing 4 requests in
+    /// flight per device.
+    pub async fn new() -> Result<Self, Error> {
+        Self::bind("0.0.0.0:0".parse().unwrap(), 4).await
+    }
+
+    /// Create a pool bound to `local`, allowing at most `max_in_flight`
+    /// outstanding requests per device.
+    pub async fn bind(local: SocketAddr, max_in_flight: usize) -> Result<Self, Error> {
+        let socket = Arc::new(UdpSocket::bind(local).await?);
+        let router = Arc::new(Router {
+            socket: socket.clone(),
+            peers: Mutex::new(HashMap::new()),
+            max_in_flight: max_in_flight.max(1),
+            dispatcher: Mutex::new(None),
+        });
+        let dispatcher = tokio::spawn(dispatch(Arc::downgrade(&router), socket));
+        *router.dispatcher.lock().unwrap() = Some(dispatcher);
+        Ok(Self { router })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.router.socket.local_addr()?)
+    }
+
+    /// A client for the given device, sending through the pool's socket.
+    ///
+    /// Handles are cheap; several handles for one device share its
+    /// in-flight limit.
+    pub fn client(&self, peer: SocketAddr) -> Client {
+        Client::from_udp(self.io(peer))
+    }
+
+    /// Whether responses from `peer`'s IP address but another port are
+    /// matched to its requests by invoke ID, which is the default. Turn it
+    /// off for devices sharing an IP address, such as simulators on one
+    /// host, so they can't receive each other's responses.
+    pub fn set_port_tolerant(&self, peer: SocketAddr, tolerant: bool) {
+        let mut peers = self.router.peers.lock().unwrap();
+        self.router.peer(&mut peers, peer).port_tolerant = tolerant;
+    }
+
+    /// The pooled transport for the given device.
+    pub fn io(&self, peer: SocketAddr) -> TokioUdpIo {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mailbox = Mailbox {
+            router: self.router.clone(),
+            peer,
+            tx,
+            rx: tokio::sync::Mutex::new(rx),
+            ids: Mutex::new(HashMap::new()),
+            permit: Mutex::new(None),
+            #[cfg(feature = "metrics")]
+            metrics: crate::metrics::RequestTracker::new(peer.to_string()),
+        };
+        TokioUdpIo::pooled(self.router.socket.clone(), peer, Arc::new(mailbox))
+    }
+}
+
+#[derive(Debug)]
+struct Router {
+    socket: Arc<UdpSocket>,
+    peers: Mutex<HashMap<SocketAddr, Peer>>,
+    max_in_flight: usize,
+    dispatcher: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[derive(Debug)]
+struct Peer {
+    pending: HashMap<u8, Pending>,
+    next_invoke_id: u8,
+    in_flight: Arc<Semaphore>,
+    port_tolerant: bool,
+}
+
+#[derive(Debug)]
+struct Pending {
+    mailbox: mpsc::UnboundedSender<Vec<u8>>,
+    original: u8,
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        if let Some(dispatcher) = self.dispatcher.lock().unwrap().take() {
+            dispatcher.abort();
+        }
+    }
+}
+
+impl Router {
+    fn in_flight(&self, peer: SocketAddr) -> Arc<Semaphore> {
+        let mut peers = self.peers.lock().unwrap();
+        self.peer(&mut peers, peer).in_flight.clone()
+    }
+
+    fn peer<'a>(&self, peers: &'a mut HashMap<SocketAddr, Peer>, peer: SocketAddr) -> &'a mut Peer {
+        peers.entry(peer).or_insert_with(|| Peer {
+            pending: HashMap::new(),
+            next_invoke_id: 0,
+            in_flight: Arc::new(Semaphore::new(self.max_in_flight)),
+            port_tolerant: true,
+        })
+    }
+
+    /// Allocate an invoke ID unique for `peer` and route responses carrying
+    /// it to `mailbox`, restoring the `original` ID. Returns `None` when all
+    /// 256 are awaiting a response.
+    fn register(
+        &self,
+        peer: SocketAddr,
+        original: u8,
+        mailbox: mpsc::UnboundedSender<Vec<u8>>,
+    ) -> Option<u8> {
+        let mut peers = self.peers.lock().unwrap();
+        let peer = self.peer(&mut peers, peer);
+        let invoke_id = (0..=u8::MAX)
+            .map(|i| peer.next_invoke_id.wrapping_add(i))
+            .find(|invoke_id| !peer.pending.contains_key(invoke_id))?;
+        peer.next_invoke_id = invoke_id.wrapping_add(1);
+        peer.pending
+            .insert(invoke_id, Pending { mailbox, original });
+        Some(invoke_id)
+    }
+
+    fn unregister(&self, peer: SocketAddr, invoke_id: u8) {
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&peer) {
+            peer.pending.remove(&invoke_id);
+        }
+    }
+
+    fn deliver(&self, from: SocketAddr, mut frame: Vec<u8>) {
+        let Some(pos) = apdu::apdu_offset(&frame) else {
+            return;
+        };
+        let pdu_type = frame[pos] >> 4;
+        if pdu_type <= 0x1 || frame.len() < pos + 2 {
+            debug!("Pool dropping unsolicited request from {from}");
+            return;
+        }
+        let invoke_id = frame[pos + 1];
+        let final_response = is_final(&frame[pos..]);
+
+        let mut peers = self.peers.lock().unwrap();
+        let Some(addr) = matching_peer(&peers, from, invoke_id) else {
+            debug!("Pool dropping response from {from} for unknown invoke ID {invoke_id}");
+            #[cfg(feature = "metrics")]
+            crate::metrics::record_dropped_response(from);
+            return;
+        };
+        let peer = peers.get_mut(&addr).unwrap();
+        let pending = &peer.pending[&invoke_id];
+        frame[pos + 1] = pending.original;
+        let delivered = pending.mailbox.send(frame).is_ok();
+        if final_response || !delivered {
+            peer.pending.remove(&invoke_id);
+        }
+    }
+}
+
+/// The device a response from `from` with `invoke_id` answers: `from`
+/// itself, or else the only port-tolerant device at the same IP address
+/// awaiting that invoke ID.
+fn matching_peer(
+    peers: &HashMap<SocketAddr, Peer>,
+    from: SocketAddr,
+    invoke_id: u8,
+) -> Option<SocketAddr> {
+    if peers
+        .get(&from)
+        .is_some_and(|peer| peer.pending.contains_key(&invoke_id))
+    {
+        return Some(from);
+    }
+    let mut candidates = peers.iter().filter(|(addr, peer)| {
+        peer.port_tolerant && addr.ip() == from.ip() && peer.pending.contains_key(&invoke_id)
+    });
+    match (candidates.next(), candidates.next()) {
+        (Some((addr, _)), None) => {
+            debug!("Pool matching response from {from} to a request sent to {addr}");
+            Some(*addr)
+        }
+        _ => None,
+    }
+}
+
+/// Whether a response APDU ends its transaction.
+fn is_final(apdu: &[u8]) -> bool {
+    match apdu[0] >> 4 {
+        PDU_COMPLEX_ACK => apdu[0] & 0x04 == 0,
+        PDU_SEGMENT_ACK => false,
+        _ => true,
+    }
+}
+
+async fn dispatch(router: Weak<Router>, socket: Arc<UdpSocket>) {
+    let mut buf = vec![0u8; 1500];
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) => {
+                warn!("Pool socket receive failed: {err}");
+                continue;
+            }
+        };
+        let Some(router) = router.upgrade() else {
+            break;
+        };
+        router.deliver(from, buf[..n].to_vec());
+    }
+}
+
+/// Per-handle state of a pooled [`TokioUdpIo`], shared by its clones.
+#[derive(Debug)]
+pub(crate) struct Mailbox {
+    router: Arc<Router>,
+    peer: SocketAddr,
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+    /// Invoke IDs chosen by the handle, mapped to the IDs sent on the wire.
+    ids: Mutex<HashMap<u8, u8>>,
+    permit: Mutex<Option<OwnedSemaphorePermit>>,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::RequestTracker,
+}
+
+impl Mailbox {
+    pub(crate) async fn send(&self, frame: &[u8], duration: Duration) -> std::io::Result<usize> {
+        #[cfg(feature = "metrics")]
+        let invoke_id = self.metrics.sending(frame);
+        let result = self.send_frame(frame, duration).await;
+        #[cfg(feature = "metrics")]
+        if let Err(err) = &result {
+            self.metrics.write_failed(invoke_id, err);
+        }
+        result
+    }
+
+    async fn send_frame(&self, frame: &[u8], duration: Duration) -> std::io::Result<usize> {
+        let mut frame = frame.to_vec();
+        if let Some(pos) = apdu::apdu_offset(&frame) {
+            let pdu_type = frame[pos] >> 4;
+            let from_client = frame[pos] & 0x01 == 0;
+            match pdu_type {
+                PDU_CONFIRMED_REQUEST if frame.len() > pos + 2 => {
+                    // A handle runs one transaction at a time: a new request
+                    // ends whatever it had in flight.
+                    self.release();
+                    let permit = self
+                        .router
+                        .in_flight(self.peer)
+                        .acquire_owned()
+                        .await
+                        .map_err(std::io::Error::other)?;
+                    *self.permit.lock().unwrap() = Some(permit);
+
+                    let original = frame[pos + 2];
+                    let Some(invoke_id) =
+                        self.router.register(self.peer, original, self.tx.clone())
+                    else {
+                        self.release();
+                        return Err(std::io::Error::other(
+                            "every invoke ID of the device awaits a response",
+                        ));
+                    };
+                    self.ids.lock().unwrap().insert(original, invoke_id);
+                    frame[pos + 2] = invoke_id;
+                }
+                PDU_SEGMENT_ACK | PDU_ABORT if from_client && frame.len() > pos + 1 => {
+                    if let Some(invoke_id) = self.ids.lock().unwrap().get(&frame[pos + 1]) {
+                        frame[pos + 1] = *invoke_id;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match timeout(duration, self.router.socket.send_to(&frame, self.peer)).await {
+            Ok(result) => result,
+            Err(_elapsed) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "write timed out",
+            )),
+        }
+    }
+
+    pub(crate) async fn recv(&self, buf: &mut [u8], duration: Duration) -> std::io::Result<usize> {
+        let result = self.recv_frame(buf, duration).await;
+        #[cfg(feature = "metrics")]
+        match &result {
+            Ok(n) => self.metrics.received(&buf[..*n]),
+            Err(err) => self.metrics.read_failed(err),
+        }
+        result
+    }
+
+    async fn recv_frame(&self, buf: &mut [u8], duration: Duration) -> std::io::Result<usize> {
+        let mut rx = self.rx.lock().await;
+        let frame = match timeout(duration, rx.recv()).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => unreachable!("mailbox holds its own sender"),
+            Err(_elapsed) => {
+                self.permit.lock().unwrap().take();
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "read timed out",
+                ));
+            }
+        };
+        if apdu::apdu_offset(&frame).is_some_and(|pos| is_final(&frame[pos..])) {
+            self.permit.lock().unwrap().take();
+        }
+        if frame.len() > buf.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "response larger than read buffer",
+            ));
+        }
+        buf[..frame.len()].copy_from_slice(&frame);
+        Ok(frame.len())
+    }
+
+    fn release(&self) {
+        self.permit.lock().unwrap().take();
+        for (_, invoke_id) in self.ids.lock().unwrap().drain() {
+            self.router.unregister(self.peer, invoke_id);
+        }
+    }
+}
+
+impl Drop for Mailbox {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_bacnet::simple::NetworkIo;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn invoke_ids_run_out_with_256_handles() {
+        let pool = ClientPool::bind("127.0.0.1:0".parse().unwrap(), 512)
+            .await
+            .unwrap();
+        // Never answers
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer = device.local_addr().unwrap();
+        let request = apdu::unicast_frame(
+            &apdu::confirmed_request(0, apdu::confirmed::READ_PROPERTY, &[]),
+            true,
+        );
+
+        // Each request releases the invoke ID of the handle's last one
+        let io = pool.io(peer);
+        for _ in 0..300 {
+            io.write(&request).await.unwrap();
+        }
+
+        let mut handles = vec![io];
+        for _ in 1..256 {
+            let io = pool.io(peer);
+            io.write(&request).await.unwrap();
+            handles.push(io);
+        }
+        let io = pool.io(peer);
+        assert!(io.write(&request).await.is_err());
+
+        // Dropping a handle releases its invoke ID
+        handles.pop();
+        io.write(&request).await.unwrap();
+    }
+
+    #[cfg(all(feature = "metrics", feature = "testing"))]
+    #[test]
+    fn pooled_requests_are_recorded() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        use crate::{
+            testing::{UdpDevice, VirtualDevice},
+            ObjectId, ObjectType, PropertyId, Value,
+        };
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let peer = ::metrics::with_local_recorder(&recorder, || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let object_id = ObjectId::new(ObjectType::ObjectAnalogValue, 1);
+                let mut device = VirtualDevice::new(1234);
+                device.add_object(
+                    object_id,
+                    [(PropertyId::PropPresentValue, Value::Real(21.5))],
+                );
+                let device = UdpDevice::bind(device, "127.0.0.1:0".parse().unwrap())
+                    .await
+                    .unwrap();
+                let pool = ClientPool::bind("127.0.0.1:0".parse().unwrap(), 4)
+                    .await
+                    .unwrap();
+                let mut client = pool.client(device.local_addr());
+                let value = client
+                    .read_value(object_id, PropertyId::PropPresentValue)
+                    .await
+                    .unwrap();
+                assert_eq!(value, Value::Real(21.5));
+                device.local_addr().to_string()
+            })
+        });
+
+        let requests =
+            snapshotter
+                .snapshot()
+                .into_vec()
+                .into_iter()
+                .find_map(|(key, _, _, value)| {
+                    let key = key.key();
+                    let recorded = key.name() == "bacnet_requests_total"
+                        && key
+                            .labels()
+                            .any(|label| label.key() == "peer" && label.value() == peer);
+                    recorded.then_some(value)
+                });
+        assert!(
+            matches!(requests, Some(DebugValue::Counter(1))),
+            "unexpected requests: {requests:?}"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{
+    client::Client,
+    error::{DecodeError, Error},
+    value::Value,
+    NetworkIo, ObjectId, PropertyId,
+};
+
+/// Decoded `priority-array` of a commandable object.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriorityArray {
+    /// Slot `i` holds the command at priority `i + 1`; `None` when relinquished.
+    pub slots: [Option<Value>; 16],
+}
+
+impl PriorityArray {
+    fn from_value(value: Value) -> Result<Self, DecodeError> {
+        let Value::Array(values) = value else {
+            return Err(DecodeError("priority-array is not an array"));
+        };
+        if values.len() != 16 {
+            return Err(DecodeError("priority-array does not have 16 slots"));
+        }
+        let mut array = Self::default();
+        for (slot, value) in array.slots.iter_mut().zip(values) {
+            *slot = (!value.is_null()).then_some(value);
+        }
+        Ok(array)
+    }
+
+    /// The command at the given priority (1-16).
+    pub fn get(&self, priority: u8) -> Option<&Value> {
+        let index = (priority as usize).checked_sub(1)?;
+        self.slots.get(index)?.as_ref()
+    }
+
+    /// The highest (numerically lowest) priority holding a command, with its value.
+    pub fn highest_active_priority(&self) -> Option<(u8, &Value)> {
+        self.iter_active().next()
+    }
+
+    /// Iterate over the priorities holding a command, highest first.
+    pub fn iter_active(&self) -> impl Iterator<Item = (u8, &Value)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| slot.as_ref().map(|value| (i as u8 + 1, value)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(Option::is_none)
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read and decode the `priority-array` of a commandable object.
+    pub async fn read_priority_array(
+        &mut self,
+        object_id: ObjectId,
+    ) -> Result<PriorityArray, Error<T>> {
+        let value = self
+            .read_value(object_id, PropertyId::PropPriorityArray)
+            .await?;
+        Ok(PriorityArray::from_value(value)?)
+    }
+
+    /// Relinquish the command at the given priority (1-16) by writing NULL
+    /// to the present value.
+    pub async fn relinquish(&mut self, object_id: ObjectId, priority: u8) -> Result<(), Error<T>> {
+        self.write_value(
+            object_id,
+            PropertyId::PropPresentValue,
+            &Value::Null,
+            Some(priority),
+        )
+        .await
+    }
+}
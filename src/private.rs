@@ -0,0 +1,48 @@
+//! Vendor-proprietary services carried by ConfirmedPrivateTransfer.
+
+use crate::{
+    apdu::{self, Decoder, Encoder},
+    client::Client,
+    error::{DecodeError, Error},
+    NetworkIo,
+};
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Call a vendor-proprietary service with ConfirmedPrivateTransfer and
+    /// return its result block.
+    ///
+    /// `parameters` are the encoded service parameters, tags included,
+    /// as documented by the vendor; they are left out of the request when
+    /// empty. The result block is returned the same way, empty when the
+    /// device sent none. A device answering for another vendor or service
+    /// fails with [`Error::Decode`].
+    pub async fn private_transfer(
+        &mut self,
+        vendor_id: u16,
+        service_number: u32,
+        parameters: &[u8],
+    ) -> Result<Vec<u8>, Error<T>> {
+        let mut request = Encoder::new();
+        request.context_unsigned(0, vendor_id as u64);
+        request.context_unsigned(1, service_number as u64);
+        if !parameters.is_empty() {
+            request.opening(2);
+            request.raw(parameters);
+            request.closing(2);
+        }
+        let ack = self
+            .confirmed_request(apdu::confirmed::PRIVATE_TRANSFER, &request.into_bytes())
+            .await?;
+
+        let mut decoder = Decoder::new(&ack);
+        let ack_vendor_id = apdu::decode_unsigned(decoder.expect_context(0)?);
+        let ack_service_number = apdu::decode_unsigned(decoder.expect_context(1)?);
+        if ack_vendor_id != vendor_id as u64 || ack_service_number != service_number as u64 {
+            return Err(DecodeError("private transfer ack for another service").into());
+        }
+        match decoder.is_empty() {
+            true => Ok(Vec::new()),
+            false => Ok(decoder.enclosed(2)?.to_vec()),
+        }
+    }
+}
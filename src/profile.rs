@@ -0,0 +1,238 @@
+//! Measuring how a device copes with requests, to tune the settings used
+//! with it.
+
+use std::time::Duration;
+
+use log::debug;
+use tokio::time::Instant;
+
+use crate::{
+    apdu::{self, Encoder},
+    client::Client,
+    device,
+    error::Error,
+    value::Value,
+    NetworkIo, ObjectId, PropertyId,
+};
+
+/// Largest ReadPropertyMultiple probed, in properties.
+const MAX_PROBE: usize = 256;
+/// Complex-ack header, not counted in the ack data.
+const COMPLEX_ACK_HEADER: usize = 3;
+/// Shortest timeout recommended, however fast the device.
+const MIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Whether a device sends and accepts segmented messages
+/// (BACnetSegmentation).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Segmentation {
+    SegmentedBoth,
+    SegmentedTransmit,
+    SegmentedReceive,
+    NoSegmentation,
+    Other(u32),
+}
+
+impl From<u32> for Segmentation {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Self::SegmentedBoth,
+            1 => Self::SegmentedTransmit,
+            2 => Self::SegmentedReceive,
+            3 => Self::NoSegmentation,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Response times of the requests a device answered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Latency {
+    pub min: Duration,
+    pub median: Duration,
+    pub max: Duration,
+}
+
+impl Latency {
+    fn of(mut samples: Vec<Duration>) -> Option<Self> {
+        samples.sort();
+        Some(Self {
+            min: *samples.first()?,
+            median: samples[samples.len() / 2],
+            max: *samples.last()?,
+        })
+    }
+}
+
+/// What [`Client::profile`] measured, with the settings it recommends.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceProfile {
+    pub device: ObjectId,
+    /// Max-APDU the device reports, `None` when it doesn't say.
+    pub max_apdu: Option<usize>,
+    pub segmentation: Option<Segmentation>,
+    pub latency: Latency,
+    /// Requests sent to measure latency.
+    pub requests: usize,
+    /// Requests left unanswered, which a caller would have retried.
+    pub lost: usize,
+    /// Most properties read by one ReadPropertyMultiple, up to 256. `None`
+    /// when the device doesn't support the service.
+    pub rpm_max_properties: Option<usize>,
+    /// Size of the largest ReadPropertyMultiple ack received, header
+    /// included.
+    pub rpm_max_ack: Option<usize>,
+    /// Whether the device sent an ack larger than its max-APDU, which it
+    /// can only do segmented.
+    pub segments_responses: bool,
+    /// Timeout recommended, see [`Client::set_timeout`]: three times the
+    /// slowest response, at least 500 ms.
+    pub recommended_timeout: Duration,
+    /// Max-APDU to size [`Client::read_many`] batches with, see
+    /// [`Client::set_max_apdu`]. `None` when the device doesn't support
+    /// ReadPropertyMultiple.
+    pub recommended_max_apdu: Option<usize>,
+}
+
+impl DeviceProfile {
+    /// Fraction of the requests left unanswered.
+    pub fn loss_rate(&self) -> f64 {
+        match self.requests {
+            0 => 0.0,
+            requests => self.lost as f64 / requests as f64,
+        }
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Measure the device's response latency and losses over `samples`
+    /// reads, and probe the largest ReadPropertyMultiple it answers, to
+    /// recommend the timeout and batch size to use with it.
+    ///
+    /// Probing sends up to about 20 requests with growing acks, some of
+    /// which the device is expected to refuse; a device dropping a probe
+    /// makes the limit found smaller than its actual one. Profile devices
+    /// when the network is quiet, and keep the report, e.g. with the site
+    /// configuration, rather than profiling on every start.
+    pub async fn profile(&mut self, samples: usize) -> Result<DeviceProfile, Error<T>> {
+        let wildcard = device::wildcard_device();
+        let requests = samples.max(1);
+        let mut latencies = Vec::with_capacity(requests);
+        let mut device = None;
+        let mut last_error = None;
+        for _ in 0..requests {
+            let started = Instant::now();
+            match self
+                .read_value(wildcard, PropertyId::PropObjectIdentifier)
+                .await
+            {
+                Ok(Value::ObjectId(object_id)) => {
+                    latencies.push(started.elapsed());
+                    device = Some(object_id);
+                }
+                Ok(_) => return Err(Error::Decode("object identifier is not an object id")),
+                Err(Error::DeadlineExceeded) => return Err(Error::DeadlineExceeded),
+                Err(err) if err.is_transport() => last_error = Some(err),
+                Err(err) => return Err(err),
+            }
+        }
+        let lost = requests - latencies.len();
+        let (Some(device), Some(latency)) = (device, Latency::of(latencies)) else {
+            return Err(last_error.unwrap_or(Error::Decode("device never answered")));
+        };
+
+        let max_apdu = self
+            .optional(wildcard, PropertyId::PropMaxApduLengthAccepted)
+            .await?
+            .map(|max_apdu| max_apdu as usize);
+        let segmentation = self
+            .optional(wildcard, PropertyId::PropSegmentationSupported)
+            .await?
+            .map(Segmentation::from);
+
+        // Double the properties read until the device fails, then bisect
+        let (mut answered, mut refused) = (0, None);
+        let mut rpm_max_ack = None;
+        loop {
+            let count = match refused {
+                None if answered == 0 => 1,
+                None => answered * 2,
+                Some(refused) => (answered + refused) / 2,
+            };
+            if count <= answered || count > MAX_PROBE {
+                break;
+            }
+            match self.probe_rpm(device, count).await {
+                Ok(ack) => {
+                    answered = count;
+                    rpm_max_ack = Some(ack + COMPLEX_ACK_HEADER);
+                }
+                Err(Error::DeadlineExceeded) => return Err(Error::DeadlineExceeded),
+                Err(err) => {
+                    debug!("ReadPropertyMultiple of {count} properties failed: {err:?}");
+                    refused = Some(count);
+                }
+            }
+        }
+
+        let recommended_max_apdu = rpm_max_ack.map(|ack| {
+            // Without a failure, the device may answer more than probed
+            let ack = match refused {
+                Some(_) => ack,
+                None => ack.max(max_apdu.unwrap_or_default()),
+            };
+            ack.min(apdu::MAX_APDU)
+        });
+        Ok(DeviceProfile {
+            device,
+            max_apdu,
+            segmentation,
+            latency,
+            requests,
+            lost,
+            rpm_max_properties: (answered > 0).then_some(answered),
+            rpm_max_ack,
+            segments_responses: rpm_max_ack
+                .zip(max_apdu)
+                .is_some_and(|(ack, max_apdu)| ack > max_apdu),
+            recommended_timeout: (latency.max * 3).max(MIN_TIMEOUT),
+            recommended_max_apdu,
+        })
+    }
+
+    /// Read an unsigned or enumerated property of the device object,
+    /// `None` when the device doesn't have it.
+    async fn optional(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+    ) -> Result<Option<u32>, Error<T>> {
+        match self.read_value(object_id, property_id).await {
+            Ok(value) => Ok(value.as_u32()),
+            Err(err) if err.is_transport() => Err(err),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Read the object identifier of `device` `count` times with one
+    /// ReadPropertyMultiple, returning the size of the ack data.
+    async fn probe_rpm(&mut self, device: ObjectId, count: usize) -> Result<usize, Error<T>> {
+        let mut request = Encoder::new();
+        request.context_object_id(0, device);
+        request.opening(1);
+        for _ in 0..count {
+            request.context_enumerated(0, PropertyId::PropObjectIdentifier as u32);
+        }
+        request.closing(1);
+        let ack = self
+            .confirmed_request(
+                apdu::confirmed::READ_PROPERTY_MULTIPLE,
+                &request.into_bytes(),
+            )
+            .await?;
+        Ok(ack.len())
+    }
+}
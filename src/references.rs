@@ -0,0 +1,260 @@
+//! Object references held by trend logs, event enrollments, schedules and
+//! loops, and a site-wide check for references whose target is gone.
+//!
+//! Re-engineering a site (renumbering devices, deleting or recreating
+//! objects) silently breaks the objects referring to them: trend logs stop
+//! logging and schedules stop writing without reporting a fault.
+//! [`check_references`] follows every reference to find them.
+
+use std::collections::{BTreeMap, HashMap};
+
+use log::debug;
+
+use crate::{
+    apdu::{self, Decoder},
+    client::Client,
+    control::ObjectPropertyReference,
+    device::WILDCARD_INSTANCE,
+    error::{DecodeError, Error},
+    NetworkIo, ObjectId, ObjectType, PropertyId,
+};
+
+/// A property of an object, in the same device unless `device` is set
+/// (BACnetDeviceObjectPropertyReference).
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceObjectPropertyReference {
+    pub object_id: ObjectId,
+    pub property_id: u32,
+    pub array_index: Option<u32>,
+    pub device: Option<ObjectId>,
+}
+
+impl DeviceObjectPropertyReference {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let object_id = apdu::decode_object_id(decoder.expect_context(0)?)?;
+        let property_id = apdu::decode_unsigned(decoder.expect_context(1)?) as u32;
+        let array_index = decoder.context_unsigned(2)?.map(|i| i as u32);
+        let device = decoder.context_object_id(3)?;
+        Ok(Self {
+            object_id,
+            property_id,
+            array_index,
+            device,
+        })
+    }
+
+    /// Decode every reference of a property value, skipping unconfigured
+    /// ones (object instance 4194303).
+    fn decode_all(data: &[u8]) -> Result<Vec<Self>, DecodeError> {
+        let mut decoder = Decoder::new(data);
+        let mut references = Vec::new();
+        while !decoder.is_empty() {
+            let reference = Self::decode(&mut decoder)?;
+            if reference.object_id.id != WILDCARD_INSTANCE {
+                references.push(reference);
+            }
+        }
+        Ok(references)
+    }
+}
+
+impl From<ObjectPropertyReference> for DeviceObjectPropertyReference {
+    fn from(value: ObjectPropertyReference) -> Self {
+        Self {
+            object_id: value.object_id,
+            property_id: value.property_id,
+            array_index: value.array_index,
+            device: None,
+        }
+    }
+}
+
+/// A reference found in an object, see [`Client::read_references`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reference {
+    /// The object holding the reference.
+    pub source: ObjectId,
+    /// The property of `source` holding the reference.
+    pub source_property: u32,
+    pub target: DeviceObjectPropertyReference,
+}
+
+/// Why a reference is dangling.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReferenceProblem {
+    /// The target device isn't part of the checked site.
+    DeviceMissing,
+    /// The target device didn't answer.
+    DeviceUnreachable,
+    /// The target device doesn't have the object.
+    ObjectMissing,
+    /// The target object doesn't have the property.
+    PropertyMissing,
+}
+
+/// A reference whose target couldn't be found, see [`check_references`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DanglingReference {
+    /// Instance of the device holding the reference.
+    pub device: u32,
+    pub reference: Reference,
+    pub problem: ReferenceProblem,
+}
+
+/// The types of the objects whose references are followed.
+fn holds_references(object_type: ObjectType) -> bool {
+    matches!(
+        object_type,
+        ObjectType::ObjectTrendlog
+            | ObjectType::ObjectTrendLogMultiple
+            | ObjectType::ObjectEventEnrollment
+            | ObjectType::ObjectSchedule
+            | ObjectType::ObjectLoop
+    )
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read the references held by an object: the logged properties of a
+    /// trend log, the monitored property of an event enrollment, the
+    /// properties written by a schedule, and the variables of a loop.
+    ///
+    /// Other objects hold no references. Unconfigured references are left
+    /// out.
+    pub async fn read_references(
+        &mut self,
+        object_id: ObjectId,
+    ) -> Result<Vec<Reference>, Error<T>> {
+        let reference = |source_property: PropertyId, target| Reference {
+            source: object_id,
+            source_property: source_property as u32,
+            target,
+        };
+        let property_id = match object_id.object_type {
+            ObjectType::ObjectTrendlog | ObjectType::ObjectTrendLogMultiple => {
+                PropertyId::PropLogDeviceObjectProperty
+            }
+            ObjectType::ObjectEventEnrollment => PropertyId::PropObjectPropertyReference,
+            ObjectType::ObjectSchedule => PropertyId::PropListOfObjectPropertyReferences,
+            ObjectType::ObjectLoop => {
+                let mut references = Vec::new();
+                for property_id in [
+                    PropertyId::PropControlledVariableReference,
+                    PropertyId::PropManipulatedVariableReference,
+                ] {
+                    let target = self.read_reference(object_id, property_id).await?;
+                    references.push(reference(property_id, target.into()));
+                }
+                if let Some(target) = self.read_setpoint_reference(object_id).await? {
+                    references.push(reference(PropertyId::PropSetpointReference, target.into()));
+                }
+                return Ok(references);
+            }
+            _ => return Ok(Vec::new()),
+        };
+        let data = match self
+            .read_property_raw(object_id, property_id as u32, None)
+            .await
+        {
+            Ok(data) => data,
+            Err(Error::Service(err)) if err.is_unknown_property() => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        Ok(DeviceObjectPropertyReference::decode_all(&data)?
+            .into_iter()
+            .map(|target| reference(property_id, target))
+            .collect())
+    }
+
+    /// Read the references held by every trend log, event enrollment,
+    /// schedule and loop of the device.
+    ///
+    /// Objects whose references can't be read or decoded are skipped.
+    pub async fn read_device_references(&mut self) -> Result<Vec<Reference>, Error<T>> {
+        let mut references = Vec::new();
+        for object_id in self.read_object_list().await? {
+            if !holds_references(object_id.object_type) {
+                continue;
+            }
+            match self.read_references(object_id).await {
+                Ok(found) => references.extend(found),
+                Err(err) if err.is_transport() => return Err(err),
+                Err(err) => debug!("Skipping references of {object_id:?}: {err:?}"),
+            }
+        }
+        Ok(references)
+    }
+}
+
+/// Follow the references held by the objects of every device of a site, and
+/// report those whose target device, object or property is missing.
+///
+/// `devices` maps device instances to a client for each device, e.g. from
+/// a [`crate::ClientPool`]. References without a device point into the
+/// device holding them. Each target is read once; a target device that
+/// doesn't answer is reported rather than failing the check, but failing
+/// to read the references themselves does fail it.
+pub async fn check_references<T: NetworkIo + Clone>(
+    devices: &mut BTreeMap<u32, Client<T>>,
+) -> Result<Vec<DanglingReference>, Error<T>> {
+    let mut references = Vec::new();
+    for (device, client) in devices.iter_mut() {
+        for reference in client.read_device_references().await? {
+            references.push((*device, reference));
+        }
+    }
+
+    let mut checked = HashMap::new();
+    let mut dangling = Vec::new();
+    for (device, reference) in references {
+        let target = reference.target;
+        let target_device = target.device.map_or(device, |device| device.id);
+        let key = (
+            target_device,
+            apdu::object_id_value(target.object_id),
+            target.property_id,
+            target.array_index,
+        );
+        let problem = match checked.get(&key) {
+            Some(problem) => *problem,
+            None => {
+                let problem = match devices.get_mut(&target_device) {
+                    None => Some(ReferenceProblem::DeviceMissing),
+                    Some(client) => match client
+                        .read_property_raw(target.object_id, target.property_id, target.array_index)
+                        .await
+                    {
+                        Ok(_) => None,
+                        Err(Error::Service(err)) if err.is_unknown_object() => {
+                            Some(ReferenceProblem::ObjectMissing)
+                        }
+                        Err(Error::Service(err)) if err.is_unknown_property() => {
+                            Some(ReferenceProblem::PropertyMissing)
+                        }
+                        Err(err) if err.is_transport() => {
+                            debug!("Device {target_device} unreachable: {err:?}");
+                            Some(ReferenceProblem::DeviceUnreachable)
+                        }
+                        Err(err) => {
+                            debug!("Reading {target:?} failed: {err:?}");
+                            None
+                        }
+                    },
+                };
+                checked.insert(key, problem);
+                problem
+            }
+        };
+        if let Some(problem) = problem {
+            dangling.push(DanglingReference {
+                device,
+                reference,
+                problem,
+            });
+        }
+    }
+    Ok(dangling)
+}
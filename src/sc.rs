@@ -0,0 +1,498 @@
+//! BACnet Secure Connect (Annex AB) transport.
+//!
+//! [`ScIo`] connects to a BACnet/SC hub over a TLS WebSocket and exchanges
+//! BVLC-SC frames with it. Only the hub-connected node role is implemented:
+//! every NPDU is relayed through the hub, no direct connections are made.
+//!
+//! The transport speaks BACnet/IP (BVLL) framing towards
+//! `embedded_bacnet::simple::Bacnet`, so outgoing BVLL frames are unwrapped
+//! into Encapsulated-NPDU messages and incoming NPDUs are re-wrapped as
+//! original-unicast BVLL frames.
+
+use std::{
+    fmt::Debug,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use embedded_bacnet::simple::NetworkIo;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use log::{debug, warn};
+use tokio::{net::TcpStream, sync::Mutex, task::JoinHandle, time::timeout};
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    Connector, MaybeTlsStream, WebSocketStream,
+};
+
+use crate::io::request_timeout;
+
+/// WebSocket subprotocol used for hub connections.
+const HUB_SUBPROTOCOL: &str = "hub.bsc.bacnet.org";
+
+const BVLC_RESULT: u8 = 0x00;
+const ENCAPSULATED_NPDU: u8 = 0x01;
+const CONNECT_REQUEST: u8 = 0x06;
+const CONNECT_ACCEPT: u8 = 0x07;
+const DISCONNECT_REQUEST: u8 = 0x08;
+const DISCONNECT_ACK: u8 = 0x09;
+const HEARTBEAT_REQUEST: u8 = 0x0a;
+const HEARTBEAT_ACK: u8 = 0x0b;
+
+const FLAG_ORIGINATING: u8 = 0x08;
+const FLAG_DESTINATION: u8 = 0x04;
+const FLAG_DESTINATION_OPTIONS: u8 = 0x02;
+const FLAG_DATA_OPTIONS: u8 = 0x01;
+
+/// BVLL header used towards `Bacnet`: type, function, length.
+const BVLL_HEADER_LEN: usize = 4;
+const BVLL_ORIGINAL_UNICAST: u8 = 0x0a;
+const BVLL_ORIGINAL_BROADCAST: u8 = 0x0b;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A BACnet/SC virtual MAC address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Vmac(pub [u8; 6]);
+
+impl Vmac {
+    /// The local broadcast VMAC.
+    pub const BROADCAST: Vmac = Vmac([0xff; 6]);
+
+    /// Generate a random-48 VMAC (locally administered, unicast).
+    pub fn random() -> Self {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u64(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default(),
+        );
+        let bytes = hasher.finish().to_be_bytes();
+        let mut vmac = [0u8; 6];
+        vmac.copy_from_slice(&bytes[..6]);
+        vmac[0] = (vmac[0] & 0xf0) | 0x02;
+        Self(vmac)
+    }
+}
+
+/// Connection settings for [`ScIo`].
+#[derive(Debug, Clone)]
+pub struct ScConfig {
+    /// Primary hub URI, e.g. `wss://hub.example.com:4443`.
+    pub hub_uri: String,
+    /// VMAC of this node.
+    pub vmac: Vmac,
+    /// Device UUID of this node.
+    pub device_uuid: [u8; 16],
+    /// VMAC of the device requests are addressed to.
+    pub peer: Vmac,
+    /// Maximum BVLC message length accepted by this node.
+    pub max_bvlc_len: u16,
+    /// Maximum NPDU length accepted by this node.
+    pub max_npdu_len: u16,
+    /// Interval between heartbeat requests sent to the hub.
+    pub heartbeat_interval: Duration,
+}
+
+impl ScConfig {
+    pub fn new(hub_uri: impl Into<String>, device_uuid: [u8; 16], peer: Vmac) -> Self {
+        Self {
+            hub_uri: hub_uri.into(),
+            vmac: Vmac::random(),
+            device_uuid,
+            peer,
+            max_bvlc_len: 1600,
+            max_npdu_len: 1497,
+            heartbeat_interval: Duration::from_secs(300),
+        }
+    }
+}
+
+/// A BACnet/SC I/O implementation for `embedded_bacnet::simple::Bacnet<T>`.
+///
+/// Clones share the hub connection, which is kept alive until the last one
+/// is dropped, and the timeout.
+#[derive(Clone)]
+pub struct ScIo {
+    sink: Arc<Mutex<SplitSink<WsStream, Message>>>,
+    stream: Arc<Mutex<SplitStream<WsStream>>>,
+    config: ScConfig,
+    message_id: Arc<AtomicU16>,
+    heartbeat: Arc<Heartbeat>,
+    timeout: Arc<std::sync::Mutex<Duration>>,
+}
+
+/// The heartbeat task, stopped when dropped.
+struct Heartbeat(JoinHandle<()>);
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl Debug for ScIo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScIo")
+            .field("hub_uri", &self.config.hub_uri)
+            .field("vmac", &self.config.vmac)
+            .field("peer", &self.config.peer)
+            .field("timeout", &self.timeout())
+            .finish()
+    }
+}
+
+impl ScIo {
+    /// Connect to the hub and perform the Connect-Request/Connect-Accept exchange.
+    ///
+    /// The TLS configuration must carry the node's operational certificate,
+    /// as required by BACnet/SC for mutual authentication.
+    pub async fn connect(
+        config: ScConfig,
+        tls: Arc<rustls::ClientConfig>,
+    ) -> Result<Self, std::io::Error> {
+        let mut request = config
+            .hub_uri
+            .as_str()
+            .into_client_request()
+            .map_err(std::io::Error::other)?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(HUB_SUBPROTOCOL),
+        );
+
+        let (ws, _response) =
+            connect_async_tls_with_config(request, None, true, Some(Connector::Rustls(tls)))
+                .await
+                .map_err(std::io::Error::other)?;
+        let (sink, stream) = ws.split();
+        let sink = Arc::new(Mutex::new(sink));
+        let message_id = Arc::new(AtomicU16::new(1));
+
+        let mut payload = Vec::with_capacity(26);
+        payload.extend_from_slice(&config.vmac.0);
+        payload.extend_from_slice(&config.device_uuid);
+        payload.extend_from_slice(&config.max_bvlc_len.to_be_bytes());
+        payload.extend_from_slice(&config.max_npdu_len.to_be_bytes());
+        let connect = encode(
+            CONNECT_REQUEST,
+            message_id.fetch_add(1, Ordering::Relaxed),
+            None,
+            &payload,
+        );
+        sink.lock()
+            .await
+            .send(Message::Binary(connect))
+            .await
+            .map_err(std::io::Error::other)?;
+
+        let heartbeat = tokio::spawn(heartbeat(
+            sink.clone(),
+            message_id.clone(),
+            config.heartbeat_interval,
+        ));
+        let io = Self {
+            sink,
+            stream: Arc::new(Mutex::new(stream)),
+            config,
+            message_id,
+            heartbeat: Arc::new(Heartbeat(heartbeat)),
+            timeout: Arc::new(std::sync::Mutex::new(Duration::from_secs(5))),
+        };
+        io.await_connect_accept().await?;
+        debug!("BACnet/SC connected to {}", io.config.hub_uri);
+        Ok(io)
+    }
+
+    pub fn config(&self) -> &ScConfig {
+        &self.config
+    }
+
+    /// How long reads wait for a frame and writes for the WebSocket, unless
+    /// the request being made sets its own, see [`request_timeout`].
+    pub fn timeout(&self) -> Duration {
+        *self.timeout.lock().unwrap()
+    }
+
+    pub fn set_timeout(&self, duration: Duration) {
+        *self.timeout.lock().unwrap() = duration;
+    }
+
+    /// Send a Disconnect-Request to the hub and close the WebSocket.
+    pub async fn disconnect(&self) -> Result<(), std::io::Error> {
+        self.send(DISCONNECT_REQUEST, None, &[]).await?;
+        self.sink
+            .lock()
+            .await
+            .close()
+            .await
+            .map_err(std::io::Error::other)
+    }
+
+    async fn await_connect_accept(&self) -> Result<(), std::io::Error> {
+        loop {
+            let frame = self.next_frame().await?;
+            match frame.function {
+                CONNECT_ACCEPT => return Ok(()),
+                BVLC_RESULT => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        "hub rejected Connect-Request",
+                    ))
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    async fn send(
+        &self,
+        function: u8,
+        destination: Option<Vmac>,
+        payload: &[u8],
+    ) -> Result<(), std::io::Error> {
+        let message_id = self.message_id.fetch_add(1, Ordering::Relaxed);
+        self.send_frame(encode(function, message_id, destination, payload))
+            .await
+    }
+
+    /// Answer a request of the hub, with the message ID of the request.
+    async fn reply(&self, function: u8, message_id: u16) -> Result<(), std::io::Error> {
+        self.send_frame(encode(function, message_id, None, &[]))
+            .await
+    }
+
+    async fn send_frame(&self, frame: Vec<u8>) -> Result<(), std::io::Error> {
+        let duration = request_timeout(self.timeout());
+        let mut sink = self.sink.lock().await;
+        timeout(duration, sink.send(Message::Binary(frame)))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "write timed out"))?
+            .map_err(std::io::Error::other)
+    }
+
+    /// Receive the next BVLC-SC frame, answering heartbeats and disconnects.
+    async fn next_frame(&self) -> Result<Frame, std::io::Error> {
+        let mut stream = self.stream.lock().await;
+        loop {
+            let message = match timeout(request_timeout(self.timeout()), stream.next()).await {
+                Ok(Some(message)) => message.map_err(std::io::Error::other)?,
+                Ok(None) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        "hub connection closed",
+                    ))
+                }
+                Err(_elapsed) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "read timed out",
+                    ))
+                }
+            };
+            let data = match message {
+                Message::Binary(data) => data,
+                Message::Close(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        "hub connection closed",
+                    ))
+                }
+                _ => continue,
+            };
+            let frame = match decode(&data) {
+                Some(frame) => frame,
+                None => {
+                    warn!("Dropping malformed BVLC-SC frame: {:02x?}", data);
+                    continue;
+                }
+            };
+            match frame.function {
+                HEARTBEAT_REQUEST => self.reply(HEARTBEAT_ACK, frame.message_id).await?,
+                HEARTBEAT_ACK => continue,
+                DISCONNECT_REQUEST => {
+                    let _ = self.reply(DISCONNECT_ACK, frame.message_id).await;
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        "hub requested disconnect",
+                    ));
+                }
+                _ => return Ok(frame),
+            }
+        }
+    }
+}
+
+impl NetworkIo for ScIo {
+    type Error = std::io::Error;
+
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            let frame = self.next_frame().await?;
+            if frame.function != ENCAPSULATED_NPDU {
+                debug!("Ignoring BVLC-SC function {:#04x}", frame.function);
+                continue;
+            }
+            if frame
+                .origin
+                .is_some_and(|origin| origin != self.config.peer)
+                && self.config.peer != Vmac::BROADCAST
+            {
+                continue;
+            }
+
+            let len = BVLL_HEADER_LEN + frame.payload.len();
+            if len > buf.len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "NPDU larger than read buffer",
+                ));
+            }
+            buf[0] = 0x81;
+            buf[1] = BVLL_ORIGINAL_UNICAST;
+            buf[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+            buf[BVLL_HEADER_LEN..len].copy_from_slice(&frame.payload);
+            return Ok(len);
+        }
+    }
+
+    async fn write(&self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.len() < BVLL_HEADER_LEN || buf[0] != 0x81 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "expected a BACnet/IP BVLL frame",
+            ));
+        }
+        let destination = match buf[1] {
+            BVLL_ORIGINAL_BROADCAST => Vmac::BROADCAST,
+            _ => self.config.peer,
+        };
+        self.send(
+            ENCAPSULATED_NPDU,
+            Some(destination),
+            &buf[BVLL_HEADER_LEN..],
+        )
+        .await?;
+        Ok(buf.len())
+    }
+}
+
+async fn heartbeat(
+    sink: Arc<Mutex<SplitSink<WsStream, Message>>>,
+    message_id: Arc<AtomicU16>,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let frame = encode(
+            HEARTBEAT_REQUEST,
+            message_id.fetch_add(1, Ordering::Relaxed),
+            None,
+            &[],
+        );
+        if let Err(err) = sink.lock().await.send(Message::Binary(frame)).await {
+            warn!("BACnet/SC heartbeat failed: {err}");
+            break;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    function: u8,
+    message_id: u16,
+    origin: Option<Vmac>,
+    payload: Vec<u8>,
+}
+
+fn encode(function: u8, message_id: u16, destination: Option<Vmac>, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(10 + payload.len());
+    let flags = if destination.is_some() {
+        FLAG_DESTINATION
+    } else {
+        0
+    };
+    frame.push(function);
+    frame.push(flags);
+    frame.extend_from_slice(&message_id.to_be_bytes());
+    if let Some(destination) = destination {
+        frame.extend_from_slice(&destination.0);
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn decode(data: &[u8]) -> Option<Frame> {
+    let function = *data.first()?;
+    let flags = *data.get(1)?;
+    let message_id = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]);
+    let mut pos = 4;
+
+    let mut origin = None;
+    if flags & FLAG_ORIGINATING != 0 {
+        let mut vmac = [0u8; 6];
+        vmac.copy_from_slice(data.get(pos..pos + 6)?);
+        origin = Some(Vmac(vmac));
+        pos += 6;
+    }
+    if flags & FLAG_DESTINATION != 0 {
+        pos += 6;
+    }
+    if flags & FLAG_DESTINATION_OPTIONS != 0 {
+        pos = skip_options(data, pos)?;
+    }
+    if flags & FLAG_DATA_OPTIONS != 0 {
+        pos = skip_options(data, pos)?;
+    }
+
+    Some(Frame {
+        function,
+        message_id,
+        origin,
+        payload: data.get(pos..)?.to_vec(),
+    })
+}
+
+/// Skip a header option list, returning the position after its last option.
+fn skip_options(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let marker = *data.get(pos)?;
+        pos += 1;
+        if marker & 0x20 != 0 {
+            let len = u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+            pos += 2 + len;
+        }
+        if marker & 0x80 == 0 {
+            return Some(pos);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_round_trip() {
+        let peer = Vmac([1, 2, 3, 4, 5, 6]);
+        let data = encode(ENCAPSULATED_NPDU, 0x1234, Some(peer), &[0x01, 0x04]);
+        let frame = decode(&data).unwrap();
+        assert_eq!(frame.function, ENCAPSULATED_NPDU);
+        assert_eq!(frame.message_id, 0x1234);
+        assert_eq!(frame.origin, None);
+        assert_eq!(frame.payload, [0x01, 0x04]);
+
+        let frame = decode(&encode(HEARTBEAT_REQUEST, 7, None, &[])).unwrap();
+        assert_eq!((frame.function, frame.message_id), (HEARTBEAT_REQUEST, 7));
+        assert!(frame.payload.is_empty());
+        assert!(decode(&[HEARTBEAT_REQUEST, 0, 0]).is_none());
+    }
+}
@@ -0,0 +1,342 @@
+//! Schedule and calendar edits, batched into as few requests as the device
+//! accepts and verified by reading them back.
+
+use log::debug;
+
+use crate::{
+    apdu::{self, Encoder},
+    client::Client,
+    error::{DecodeError, Error, ServiceError},
+    io::TokioUdpIo,
+    value::Value,
+    NetworkIo, ObjectId, PropertyId,
+};
+
+/// Confirmed-request header.
+const REQUEST_HEADER: usize = 4;
+
+/// One change to a schedule, a calendar or another object with array and
+/// list properties.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScheduleEdit {
+    /// Write a property, or one element of an array property such as a day
+    /// of the weekly-schedule or an entry of the exception-schedule.
+    Write {
+        object_id: ObjectId,
+        property_id: PropertyId,
+        array_index: Option<u32>,
+        value: Value,
+    },
+    /// Add elements to a list property, e.g. entries of a calendar's
+    /// date-list, with AddListElement.
+    AddToList {
+        object_id: ObjectId,
+        property_id: PropertyId,
+        elements: Vec<Value>,
+    },
+    /// Remove elements from a list property, with RemoveListElement.
+    RemoveFromList {
+        object_id: ObjectId,
+        property_id: PropertyId,
+        elements: Vec<Value>,
+    },
+}
+
+impl ScheduleEdit {
+    fn target(&self) -> (ObjectId, PropertyId) {
+        match self {
+            Self::Write {
+                object_id,
+                property_id,
+                ..
+            }
+            | Self::AddToList {
+                object_id,
+                property_id,
+                ..
+            }
+            | Self::RemoveFromList {
+                object_id,
+                property_id,
+                ..
+            } => (*object_id, *property_id),
+        }
+    }
+
+    fn array_index(&self) -> Option<u32> {
+        match self {
+            Self::Write { array_index, .. } => *array_index,
+            _ => None,
+        }
+    }
+
+    /// Whether this edit changes what `earlier` did, so the device can't
+    /// be expected to show `earlier` anymore.
+    fn replaces(&self, earlier: &Self) -> bool {
+        self.target() == earlier.target()
+            && match (self.array_index(), earlier.array_index()) {
+                (Some(index), Some(earlier)) => index == earlier,
+                _ => true,
+            }
+    }
+
+    /// Elements added or removed by a list edit.
+    fn elements(&self) -> &[Value] {
+        match self {
+            Self::AddToList { elements, .. } | Self::RemoveFromList { elements, .. } => elements,
+            Self::Write { .. } => &[],
+        }
+    }
+
+    /// Encode a write as a WritePropertyMultiple access specification.
+    fn encode_write(&self, request: &mut Encoder) {
+        let Self::Write {
+            object_id,
+            property_id,
+            array_index,
+            value,
+        } = self
+        else {
+            return;
+        };
+        request.context_object_id(0, *object_id);
+        request.opening(1);
+        request.context_enumerated(0, *property_id as u32);
+        if let Some(index) = array_index {
+            request.context_unsigned(1, *index as u64);
+        }
+        request.opening(2);
+        value.encode(request);
+        request.closing(2);
+        request.closing(1);
+    }
+}
+
+/// Outcome of [`Client::apply_schedule_edits`].
+#[derive(Debug)]
+pub struct ScheduleEditReport<T: NetworkIo = TokioUdpIo> {
+    /// Requests sent to make the edits, read-back excluded.
+    pub requests: usize,
+    /// Edits the read-back doesn't show, by index, with the error reading
+    /// the property back when it failed.
+    pub unverified: Vec<(usize, Option<Error<T>>)>,
+}
+
+impl<T: NetworkIo> Default for ScheduleEditReport<T> {
+    fn default() -> Self {
+        Self {
+            requests: 0,
+            unverified: Vec::new(),
+        }
+    }
+}
+
+impl<T: NetworkIo> ScheduleEditReport<T> {
+    /// Whether the device shows every edit.
+    pub fn is_verified(&self) -> bool {
+        self.unverified.is_empty()
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Make `edits` in order, with as few requests as the device's
+    /// max-APDU allows, then read the edited properties back.
+    ///
+    /// Consecutive writes are sent together with WritePropertyMultiple, or
+    /// one by one to devices that don't support it. Consecutive list edits
+    /// of the same property are merged into one AddListElement or
+    /// RemoveListElement. A failing request stops the run; edits before it
+    /// were made. Edits overwritten by a later edit of the same property
+    /// or array element aren't verified.
+    pub async fn apply_schedule_edits(
+        &mut self,
+        edits: &[ScheduleEdit],
+    ) -> Result<ScheduleEditReport<T>, Error<T>> {
+        let max_request = self.max_apdu().await?.saturating_sub(REQUEST_HEADER);
+        let mut report = ScheduleEditReport::default();
+        let mut start = 0;
+        while start < edits.len() {
+            let end = match &edits[start] {
+                ScheduleEdit::Write { .. } => {
+                    let end = write_group_end(edits, start, max_request);
+                    report.requests += self.write_group(&edits[start..end]).await?;
+                    end
+                }
+                ScheduleEdit::AddToList { .. } | ScheduleEdit::RemoveFromList { .. } => {
+                    let end = list_group_end(edits, start);
+                    report.requests += self.edit_list(&edits[start..end], max_request).await?;
+                    end
+                }
+            };
+            start = end;
+        }
+
+        for (i, edit) in edits.iter().enumerate() {
+            if edits[i + 1..].iter().any(|later| later.replaces(edit)) {
+                continue;
+            }
+            match self.verify(edit).await {
+                Ok(true) => {}
+                Ok(false) => report.unverified.push((i, None)),
+                Err(err) if err.is_transport() => return Err(err),
+                Err(err) => report.unverified.push((i, Some(err))),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Send writes with one WritePropertyMultiple when the device supports
+    /// it, returning the number of requests sent.
+    async fn write_group(&mut self, writes: &[ScheduleEdit]) -> Result<usize, Error<T>> {
+        if writes.len() > 1 && !self.device_info().wpm_unsupported {
+            let mut request = Encoder::new();
+            writes
+                .iter()
+                .for_each(|write| write.encode_write(&mut request));
+            match self
+                .confirmed_request(
+                    apdu::confirmed::WRITE_PROPERTY_MULTIPLE,
+                    &request.into_bytes(),
+                )
+                .await
+            {
+                Ok(_) => return Ok(1),
+                Err(Error::Reject(reason)) => {
+                    debug!("WritePropertyMultiple rejected ({reason}), writing one by one");
+                    self.device_info().wpm_unsupported = true;
+                }
+                Err(Error::Service(err)) if err.class == ServiceError::CLASS_SERVICES => {
+                    debug!("WritePropertyMultiple refused ({err:?}), writing one by one");
+                    self.device_info().wpm_unsupported = true;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        for write in writes {
+            if let ScheduleEdit::Write {
+                object_id,
+                property_id,
+                array_index,
+                value,
+            } = write
+            {
+                self.write_property_raw(
+                    *object_id,
+                    *property_id as u32,
+                    *array_index,
+                    &value.to_bytes(),
+                    None,
+                )
+                .await?;
+            }
+        }
+        Ok(writes.len())
+    }
+
+    /// Add or remove the elements of list edits of the same property, in
+    /// as few requests as fit, returning the number of requests sent.
+    async fn edit_list(
+        &mut self,
+        edits: &[ScheduleEdit],
+        max_request: usize,
+    ) -> Result<usize, Error<T>> {
+        let (object_id, property_id) = edits[0].target();
+        let service = match edits[0] {
+            ScheduleEdit::AddToList { .. } => apdu::confirmed::ADD_LIST_ELEMENT,
+            _ => apdu::confirmed::REMOVE_LIST_ELEMENT,
+        };
+        let elements = edits.iter().flat_map(ScheduleEdit::elements);
+
+        let header = {
+            let mut header = Encoder::new();
+            header.context_object_id(0, object_id);
+            header.context_enumerated(1, property_id as u32);
+            header.opening(3);
+            header.into_bytes()
+        };
+        let mut chunks: Vec<Vec<u8>> = Vec::new();
+        let mut chunk = Vec::new();
+        for element in elements {
+            let element = element.to_bytes();
+            if !chunk.is_empty() && header.len() + chunk.len() + element.len() + 1 > max_request {
+                chunks.push(std::mem::take(&mut chunk));
+            }
+            chunk.extend_from_slice(&element);
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+
+        for chunk in &chunks {
+            let mut request = Encoder::new();
+            request.raw(&header);
+            request.raw(chunk);
+            request.closing(3);
+            self.confirmed_request(service, &request.into_bytes())
+                .await?;
+        }
+        Ok(chunks.len())
+    }
+
+    /// Whether the device holds what `edit` wrote.
+    async fn verify(&mut self, edit: &ScheduleEdit) -> Result<bool, Error<T>> {
+        let (object_id, property_id) = edit.target();
+        let data = self
+            .read_property_raw(object_id, property_id as u32, edit.array_index())
+            .await?;
+        Ok(match edit {
+            ScheduleEdit::Write { value, .. } => data == value.to_bytes(),
+            ScheduleEdit::AddToList { elements, .. } => {
+                let held = list_elements(&data)?;
+                elements
+                    .iter()
+                    .all(|element| held.contains(&element.to_bytes()))
+            }
+            ScheduleEdit::RemoveFromList { elements, .. } => {
+                let held = list_elements(&data)?;
+                !elements
+                    .iter()
+                    .any(|element| held.contains(&element.to_bytes()))
+            }
+        })
+    }
+}
+
+/// End of the writes starting at `start` that fit in one request.
+fn write_group_end(edits: &[ScheduleEdit], start: usize, max_request: usize) -> usize {
+    let mut size = 0;
+    let mut end = start;
+    while let Some(edit @ ScheduleEdit::Write { .. }) = edits.get(end) {
+        let mut encoded = Encoder::new();
+        edit.encode_write(&mut encoded);
+        size += encoded.into_bytes().len();
+        if end > start && size > max_request {
+            break;
+        }
+        end += 1;
+    }
+    end
+}
+
+/// End of the list edits starting at `start` that change the same property
+/// the same way.
+fn list_group_end(edits: &[ScheduleEdit], start: usize) -> usize {
+    let same = |edit: &ScheduleEdit| {
+        std::mem::discriminant(edit) == std::mem::discriminant(&edits[start])
+            && edit.target() == edits[start].target()
+    };
+    start
+        + 1
+        + edits[start + 1..]
+            .iter()
+            .take_while(|edit| same(edit))
+            .count()
+}
+
+/// The encoding of each element of a list read back.
+fn list_elements(data: &[u8]) -> Result<Vec<Vec<u8>>, DecodeError> {
+    let elements = Value::decode_list(data)?;
+    Ok(elements.iter().map(Value::to_bytes).collect())
+}
@@ -0,0 +1,207 @@
+//! The services and object types a device supports, from the
+//! protocol-services-supported and protocol-object-types-supported bit
+//! strings of its device object.
+
+use log::debug;
+
+use crate::{client::Client, device, error::Error, value::Value, NetworkIo, ObjectType};
+
+const PROTOCOL_OBJECT_TYPES_SUPPORTED: u32 = 96;
+const PROTOCOL_SERVICES_SUPPORTED: u32 = 97;
+
+/// A service of protocol-services-supported (BACnetServicesSupported), by
+/// bit position. Bits of services removed from the standard are left out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Service {
+    AcknowledgeAlarm = 0,
+    ConfirmedCovNotification = 1,
+    ConfirmedEventNotification = 2,
+    GetAlarmSummary = 3,
+    GetEnrollmentSummary = 4,
+    SubscribeCov = 5,
+    AtomicReadFile = 6,
+    AtomicWriteFile = 7,
+    AddListElement = 8,
+    RemoveListElement = 9,
+    CreateObject = 10,
+    DeleteObject = 11,
+    ReadProperty = 12,
+    ReadPropertyMultiple = 14,
+    WriteProperty = 15,
+    WritePropertyMultiple = 16,
+    DeviceCommunicationControl = 17,
+    ConfirmedPrivateTransfer = 18,
+    ConfirmedTextMessage = 19,
+    ReinitializeDevice = 20,
+    VtOpen = 21,
+    VtClose = 22,
+    VtData = 23,
+    IAm = 26,
+    IHave = 27,
+    UnconfirmedCovNotification = 28,
+    UnconfirmedEventNotification = 29,
+    UnconfirmedPrivateTransfer = 30,
+    UnconfirmedTextMessage = 31,
+    TimeSynchronization = 32,
+    WhoHas = 33,
+    WhoIs = 34,
+    ReadRange = 35,
+    UtcTimeSynchronization = 36,
+    LifeSafetyOperation = 37,
+    SubscribeCovProperty = 38,
+    GetEventInformation = 39,
+    WriteGroup = 40,
+    SubscribeCovPropertyMultiple = 41,
+    ConfirmedCovNotificationMultiple = 42,
+    UnconfirmedCovNotificationMultiple = 43,
+    ConfirmedAuditNotification = 44,
+    AuditLogQuery = 45,
+    UnconfirmedAuditNotification = 46,
+    WhoAmI = 47,
+    YouAre = 48,
+}
+
+/// The services a device executes, see [`Client::services_supported`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServicesSupported {
+    bits: Vec<bool>,
+}
+
+impl ServicesSupported {
+    pub fn supports(&self, service: Service) -> bool {
+        self.bits.get(service as usize).copied().unwrap_or_default()
+    }
+}
+
+/// The object types a device can hold, see
+/// [`Client::read_object_types_supported`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ObjectTypesSupported {
+    bits: Vec<bool>,
+}
+
+impl ObjectTypesSupported {
+    pub fn supports(&self, object_type: ObjectType) -> bool {
+        self.bits
+            .get(object_type as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The supported object types this crate knows, in order.
+    pub fn iter(&self) -> impl Iterator<Item = ObjectType> + '_ {
+        self.bits
+            .iter()
+            .enumerate()
+            .filter(|(_, supported)| **supported)
+            .filter_map(|(bit, _)| ObjectType::try_from(bit as u32).ok())
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// The services the device supports, read once then cached.
+    ///
+    /// Knowing them, bulk reads and schedule edits go straight to single
+    /// requests on devices without ReadPropertyMultiple or
+    /// WritePropertyMultiple, rather than finding out from a reject.
+    pub async fn services_supported(&mut self) -> Result<ServicesSupported, Error<T>> {
+        if let Some(services) = &self.device_info().services {
+            return Ok(services.clone());
+        }
+        let services = ServicesSupported {
+            bits: self.read_device_bits(PROTOCOL_SERVICES_SUPPORTED).await?,
+        };
+        let info = self.device_info();
+        info.rpm_unsupported |= !services.supports(Service::ReadPropertyMultiple);
+        info.wpm_unsupported |= !services.supports(Service::WritePropertyMultiple);
+        debug!("Device services supported: {services:?}");
+        info.services = Some(services.clone());
+        Ok(services)
+    }
+
+    /// Read the object types the device supports.
+    pub async fn read_object_types_supported(&mut self) -> Result<ObjectTypesSupported, Error<T>> {
+        Ok(ObjectTypesSupported {
+            bits: self
+                .read_device_bits(PROTOCOL_OBJECT_TYPES_SUPPORTED)
+                .await?,
+        })
+    }
+
+    async fn read_device_bits(&mut self, property: u32) -> Result<Vec<bool>, Error<T>> {
+        let data = self
+            .read_property_raw(device::wildcard_device(), property, None)
+            .await?;
+        match Value::decode_all(&data)? {
+            Value::BitString(bits) => Ok(bits),
+            _ => Err(Error::Decode("supported set is not a bit string")),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{MockIo, VirtualDevice},
+        PropertyId,
+    };
+
+    fn supported(services: &[usize], len: usize) -> ServicesSupported {
+        let mut bits = vec![false; len];
+        for bit in services {
+            bits[*bit] = true;
+        }
+        ServicesSupported { bits }
+    }
+
+    #[test]
+    fn services_map_to_their_bits() {
+        let services = supported(&[14], 49);
+        assert!(services.supports(Service::ReadPropertyMultiple));
+        assert!(!services.supports(Service::ReadProperty));
+        assert!(!services.supports(Service::WriteProperty));
+
+        // Bit 13, read-property-conditional, was removed
+        let services = supported(&[13], 49);
+        assert!(!services.supports(Service::ReadProperty));
+        assert!(!services.supports(Service::ReadPropertyMultiple));
+        assert!(!services.supports(Service::WriteProperty));
+    }
+
+    #[test]
+    fn services_beyond_a_short_bit_string_are_unsupported() {
+        let services = supported(&[0, 9], 10);
+        assert!(services.supports(Service::AcknowledgeAlarm));
+        assert!(services.supports(Service::RemoveListElement));
+        assert!(!services.supports(Service::CreateObject));
+        assert!(!services.supports(Service::WhoIs));
+    }
+
+    #[tokio::test]
+    async fn services_supported_are_cached_in_device_info() {
+        let mut device = VirtualDevice::new(1234);
+        let device_id = device.device_id();
+        // ReadProperty and ReadPropertyMultiple, no WritePropertyMultiple
+        device.set_property(
+            device_id,
+            PropertyId::PropProtocolServicesSupported,
+            Value::BitString(supported(&[12, 14], 49).bits),
+        );
+        let io = MockIo::new(device);
+        let mut client = Client::from_io(io.clone());
+
+        let services = client.services_supported().await.unwrap();
+        assert!(services.supports(Service::ReadPropertyMultiple));
+        let info = client.device_info();
+        assert!(!info.rpm_unsupported);
+        assert!(info.wpm_unsupported);
+        assert_eq!(info.services, Some(services.clone()));
+
+        assert_eq!(client.services_supported().await.unwrap(), services);
+        assert_eq!(io.device().requests(), 1);
+    }
+}
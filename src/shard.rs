@@ -0,0 +1,96 @@
+//! Splitting devices between collector instances by device instance, so
+//! that several collectors polling one estate each poll every device once.
+
+use std::ops::RangeInclusive;
+
+/// How device instances are assigned to collectors. Every collector must
+/// use the same sharding for the assignment to be consistent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Sharding {
+    /// Spread instances evenly over `collectors`, by jump consistent hash:
+    /// adding a collector only moves the devices it takes over.
+    Hash { collectors: u32 },
+    /// The instance ranges of each collector, by collector index. An
+    /// instance in the ranges of several collectors goes to the first, one
+    /// in no range to none.
+    Ranges(Vec<Vec<RangeInclusive<u32>>>),
+}
+
+impl Sharding {
+    /// The collector a device instance is assigned to, `None` when no
+    /// range holds it.
+    pub fn collector(&self, instance: u32) -> Option<u32> {
+        match self {
+            Self::Hash { collectors } => Some(jump_hash(instance as u64, (*collectors).max(1))),
+            Self::Ranges(ranges) => ranges
+                .iter()
+                .position(|ranges| ranges.iter().any(|range| range.contains(&instance)))
+                .map(|collector| collector as u32),
+        }
+    }
+
+    /// Whether `collector` polls the device `instance`.
+    pub fn owns(&self, collector: u32, instance: u32) -> bool {
+        self.collector(instance) == Some(collector)
+    }
+}
+
+/// Jump consistent hash (Lamping and Veach, 2014) of `key` into `buckets`.
+fn jump_hash(mut key: u64, buckets: u32) -> u32 {
+    let (mut bucket, mut next) = (-1i64, 0i64);
+    while next < buckets as i64 {
+        bucket = next;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    bucket as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jump_hash_reference_outputs() {
+        assert_eq!(jump_hash(1, 1), 0);
+        assert_eq!(jump_hash(42, 57), 43);
+        assert_eq!(jump_hash(0xdead10cc, 1), 0);
+        assert_eq!(jump_hash(0xdead10cc, 666), 361);
+        assert_eq!(jump_hash(256, 1024), 520);
+    }
+
+    #[test]
+    fn adding_a_collector_moves_its_share() {
+        let before = Sharding::Hash { collectors: 10 };
+        let after = Sharding::Hash { collectors: 11 };
+        let mut moved = 0;
+        for instance in 0..10_000 {
+            let (from, to) = (before.collector(instance), after.collector(instance));
+            if from != to {
+                // Only to the new collector
+                assert_eq!(to, Some(10));
+                moved += 1;
+            }
+        }
+        // 1/11 of the devices, give or take
+        assert!((800..1000).contains(&moved), "{moved} devices moved");
+    }
+
+    #[test]
+    fn no_collectors_is_one() {
+        let sharding = Sharding::Hash { collectors: 0 };
+        assert_eq!(sharding.collector(1234), Some(0));
+        assert!(sharding.owns(0, 1234));
+    }
+
+    #[test]
+    fn overlapping_ranges_go_to_the_first_collector() {
+        let sharding = Sharding::Ranges(vec![vec![0..=100], vec![50..=200, 1000..=1000]]);
+        assert_eq!(sharding.collector(75), Some(0));
+        assert_eq!(sharding.collector(150), Some(1));
+        assert_eq!(sharding.collector(1000), Some(1));
+        assert_eq!(sharding.collector(300), None);
+        assert!(!sharding.owns(1, 75));
+    }
+}
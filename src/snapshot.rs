@@ -0,0 +1,130 @@
+//! Snapshots of the volatile state of objects, refreshed by re-reading only
+//! present-value and status-flags, e.g. for dashboards of large devices.
+
+use std::time::Duration;
+
+use futures_util::Stream;
+
+use crate::{
+    client::Client, error::Error, health::StatusFlags, value::Value, NetworkIo, ObjectId,
+    PropertyId,
+};
+
+/// The volatile properties of an object.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ObjectState {
+    /// `None` when the object has no present-value or it was never read.
+    pub present_value: Option<Value>,
+    /// `None` when the object has no status-flags or they were never read.
+    pub status_flags: Option<StatusFlags>,
+}
+
+/// The state of a set of objects, see [`Client::snapshot`].
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub objects: Vec<(ObjectId, ObjectState)>,
+}
+
+impl Snapshot {
+    pub fn get(&self, object_id: ObjectId) -> Option<&ObjectState> {
+        self.objects
+            .iter()
+            .find(|(id, _)| *id == object_id)
+            .map(|(_, state)| state)
+    }
+}
+
+/// An object whose state changed between two reads of a [`Snapshot`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotChange {
+    pub object_id: ObjectId,
+    pub previous: ObjectState,
+    pub current: ObjectState,
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read the present-value and status-flags of `objects`, batched as
+    /// [`Client::read_many`] does. Properties an object lacks or that fail
+    /// to read are left `None`.
+    pub async fn snapshot(&mut self, objects: &[ObjectId]) -> Result<Snapshot, Error<T>> {
+        let mut snapshot = Snapshot {
+            objects: objects
+                .iter()
+                .map(|object_id| (*object_id, ObjectState::default()))
+                .collect(),
+        };
+        self.refresh_snapshot(&mut snapshot).await?;
+        Ok(snapshot)
+    }
+
+    /// Re-read the present-value and status-flags of the objects of
+    /// `snapshot`, update it, and return the objects that changed.
+    ///
+    /// A property that fails to read keeps its previous value, so a
+    /// transient error isn't reported as a change.
+    pub async fn refresh_snapshot(
+        &mut self,
+        snapshot: &mut Snapshot,
+    ) -> Result<Vec<SnapshotChange>, Error<T>> {
+        let requests = snapshot
+            .objects
+            .iter()
+            .flat_map(|(object_id, _)| {
+                [
+                    (*object_id, PropertyId::PropPresentValue),
+                    (*object_id, PropertyId::PropStatusFlags),
+                ]
+            })
+            .collect();
+        let results = self.read_many(requests).await?;
+
+        let mut changes = Vec::new();
+        for ((object_id, state), results) in snapshot.objects.iter_mut().zip(results.chunks(2)) {
+            let mut current = state.clone();
+            if let Ok(value) = &results[0] {
+                current.present_value = Some(value.clone());
+            }
+            if let Ok(Value::BitString(bits)) = &results[1] {
+                current.status_flags = Some(StatusFlags::from_bits(bits));
+            }
+            if current != *state {
+                changes.push(SnapshotChange {
+                    object_id: *object_id,
+                    previous: std::mem::replace(state, current.clone()),
+                    current,
+                });
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Refresh `snapshot` every `interval` and yield the changes of each
+    /// refresh that found any, starting with the first refresh.
+    ///
+    /// Read errors are yielded and refreshing continues. Refreshing stops
+    /// when the stream is dropped.
+    pub fn snapshot_changes(
+        &mut self,
+        snapshot: Snapshot,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<Vec<SnapshotChange>, Error<T>>> + '_ {
+        futures_util::stream::unfold(
+            (self, snapshot, false),
+            move |(client, mut snapshot, mut started)| async move {
+                loop {
+                    if started {
+                        tokio::time::sleep(interval).await;
+                    }
+                    started = true;
+                    match client.refresh_snapshot(&mut snapshot).await {
+                        Ok(changes) if changes.is_empty() => continue,
+                        result => return Some((result, (client, snapshot, started))),
+                    }
+                }
+            },
+        )
+    }
+}
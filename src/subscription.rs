@@ -0,0 +1,620 @@
+//! COV subscriptions and the process identifiers they are made under.
+//!
+//! A [`SubscriptionRegistry`] hands out subscriber process identifiers and
+//! maps them to the callback of each subscription, so independent parts of
+//! an application can subscribe through the same socket without colliding.
+//! Identifiers share a 16-bit prefix, which tells this application's
+//! subscriptions apart from other clients' in a device's subscription list.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::debug;
+use tokio::time::Instant;
+
+use crate::{
+    apdu::{self, Apdu, Decoder, Encoder},
+    client::Client,
+    device,
+    error::{DecodeError, Error},
+    io::TokioUdpIo,
+    value::Value,
+    NetworkIo, ObjectId,
+};
+
+const ACTIVE_COV_SUBSCRIPTIONS: u32 = 152;
+
+/// A recipient of notifications (BACnetRecipient).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Recipient {
+    Device(ObjectId),
+    Address { network: u16, mac: Vec<u8> },
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recipient::Device(object_id) => write!(f, "device {}", object_id.id),
+            Recipient::Address { network, mac } => {
+                write!(f, "{network}:")?;
+                mac.iter().try_for_each(|b| write!(f, "{b:02x}"))
+            }
+        }
+    }
+}
+
+impl Recipient {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        if let Some(device) = decoder.context_object_id(0)? {
+            return Ok(Recipient::Device(device));
+        }
+        let mut address = Decoder::new(decoder.enclosed(1)?);
+        Ok(Recipient::Address {
+            network: address.unsigned()? as u16,
+            mac: address.octet_string()?.to_vec(),
+        })
+    }
+
+    /// The UDP address of a BACnet/IP address, whose MAC is the IPv4
+    /// address and the port.
+    fn udp_address(&self) -> Option<SocketAddr> {
+        match self {
+            Recipient::Address { mac, .. } if mac.len() == 6 => Some(SocketAddr::from((
+                [mac[0], mac[1], mac[2], mac[3]],
+                u16::from_be_bytes([mac[4], mac[5]]),
+            ))),
+            _ => None,
+        }
+    }
+}
+
+/// A recipient together with the process identifier notifications are
+/// addressed to (BACnetRecipientProcess).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecipientProcess {
+    pub recipient: Recipient,
+    pub process_id: u32,
+}
+
+impl RecipientProcess {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let recipient = Recipient::decode(&mut Decoder::new(decoder.enclosed(0)?))?;
+        let process_id = apdu::decode_unsigned(decoder.expect_context(1)?) as u32;
+        Ok(Self {
+            recipient,
+            process_id,
+        })
+    }
+}
+
+/// A property value carried by a COV notification.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CovValue {
+    pub property_id: u32,
+    pub array_index: Option<u32>,
+    pub value: Value,
+    pub priority: Option<u8>,
+}
+
+/// A decoded COV notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CovNotification {
+    pub process_id: u32,
+    pub device: ObjectId,
+    pub object_id: ObjectId,
+    /// Lifetime left on the subscription, zero for indefinite ones.
+    pub time_remaining: Duration,
+    pub values: Vec<CovValue>,
+}
+
+impl CovNotification {
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut decoder = Decoder::new(data);
+        let process_id = apdu::decode_unsigned(decoder.expect_context(0)?) as u32;
+        let device = apdu::decode_object_id(decoder.expect_context(1)?)?;
+        let object_id = apdu::decode_object_id(decoder.expect_context(2)?)?;
+        let time_remaining = apdu::decode_unsigned(decoder.expect_context(3)?);
+        decoder.expect_opening(4)?;
+        let mut values = Vec::new();
+        while !decoder.is_closing(4) {
+            let property_id = apdu::decode_unsigned(decoder.expect_context(0)?) as u32;
+            let array_index = decoder.context_unsigned(1)?.map(|i| i as u32);
+            let value = Value::decode_all(decoder.enclosed(2)?)?;
+            let priority = decoder.context_unsigned(3)?.map(|p| p as u8);
+            values.push(CovValue {
+                property_id,
+                array_index,
+                value,
+                priority,
+            });
+        }
+        decoder.expect_closing(4)?;
+        Ok(Self {
+            process_id,
+            device,
+            object_id,
+            time_remaining: Duration::from_secs(time_remaining),
+            values,
+        })
+    }
+}
+
+/// A subscription listed in a device's active-cov-subscriptions
+/// (BACnetCOVSubscription).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceSubscription {
+    pub recipient: RecipientProcess,
+    pub object_id: ObjectId,
+    /// The monitored property, present-value unless subscribed with
+    /// SubscribeCOVProperty.
+    pub property_id: u32,
+    pub array_index: Option<u32>,
+    pub confirmed: bool,
+    /// Lifetime left, zero for indefinite subscriptions.
+    pub time_remaining: Duration,
+    pub cov_increment: Option<f32>,
+}
+
+impl DeviceSubscription {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let recipient = RecipientProcess::decode(&mut Decoder::new(decoder.enclosed(0)?))?;
+        let mut reference = Decoder::new(decoder.enclosed(1)?);
+        let object_id = apdu::decode_object_id(reference.expect_context(0)?)?;
+        let property_id = apdu::decode_unsigned(reference.expect_context(1)?) as u32;
+        let array_index = reference.context_unsigned(2)?.map(|i| i as u32);
+        let confirmed = apdu::decode_unsigned(decoder.expect_context(2)?) != 0;
+        let time_remaining = apdu::decode_unsigned(decoder.expect_context(3)?);
+        let cov_increment = decoder.context(4)?.map(apdu::decode_real).transpose()?;
+        Ok(Self {
+            recipient,
+            object_id,
+            property_id,
+            array_index,
+            confirmed,
+            time_remaining: Duration::from_secs(time_remaining),
+            cov_increment,
+        })
+    }
+
+    /// Whether both designate the same subscription of the device.
+    fn is_same(&self, other: &Self) -> bool {
+        self.recipient == other.recipient && self.object_id == other.object_id
+    }
+}
+
+/// Outcome of [`Client::cancel_stale_subscriptions`].
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    /// Stale subscriptions the device no longer lists.
+    pub cancelled: Vec<DeviceSubscription>,
+    /// Stale subscriptions the device still lists after being cancelled,
+    /// e.g. addressed to a device instance.
+    pub remaining: Vec<DeviceSubscription>,
+    /// Stale subscriptions left alone, as their recipient address isn't an
+    /// interface of this host or its port is bound by another socket.
+    pub uncancellable: Vec<DeviceSubscription>,
+    /// Stale subscriptions whose cancellation failed.
+    pub failed: Vec<(DeviceSubscription, Error)>,
+}
+
+/// Callback invoked for each notification of a subscription.
+pub type CovCallback = Arc<dyn Fn(&CovNotification) + Send + Sync>;
+
+/// A subscription held by a [`SubscriptionRegistry`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveSubscription {
+    pub process_id: u32,
+    /// Address of the device, unless the client's transport isn't UDP.
+    pub peer: Option<SocketAddr>,
+    pub object_id: ObjectId,
+    pub confirmed: bool,
+    /// The requested lifetime, `None` for indefinite subscriptions.
+    pub lifetime: Option<Duration>,
+    /// Lifetime left, `None` for indefinite subscriptions.
+    pub remaining: Option<Duration>,
+}
+
+/// Process identifier allocation and callbacks for COV subscriptions.
+///
+/// Clones share the same registry, so one registry can serve every client
+/// of an application, see [`Client::set_subscriptions`].
+#[derive(Clone)]
+pub struct SubscriptionRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+struct Registry {
+    prefix: u16,
+    next: u16,
+    entries: HashMap<u32, Entry>,
+}
+
+struct Entry {
+    peer: Option<SocketAddr>,
+    object_id: ObjectId,
+    confirmed: bool,
+    lifetime: Option<Duration>,
+    expires: Option<Instant>,
+    callback: CovCallback,
+}
+
+impl fmt::Debug for SubscriptionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registry = self.inner.lock().unwrap();
+        f.debug_struct("SubscriptionRegistry")
+            .field("prefix", &registry.prefix)
+            .field("subscriptions", &registry.entries.len())
+            .finish()
+    }
+}
+
+impl Default for SubscriptionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SubscriptionRegistry {
+    /// Prefix used by [`SubscriptionRegistry::new`].
+    pub const DEFAULT_PREFIX: u16 = 0xbac0;
+
+    pub fn new() -> Self {
+        Self::with_prefix(Self::DEFAULT_PREFIX)
+    }
+
+    /// Create a registry allocating process identifiers in
+    /// `prefix << 16 ..= prefix << 16 | 0xffff`.
+    ///
+    /// Applications sharing devices should use distinct prefixes.
+    pub fn with_prefix(prefix: u16) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Registry {
+                prefix,
+                next: 1,
+                entries: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn prefix(&self) -> u16 {
+        self.inner.lock().unwrap().prefix
+    }
+
+    /// Whether the process identifier was allocated under this registry's
+    /// prefix, by this process or a previous run of the application.
+    pub fn owns(&self, process_id: u32) -> bool {
+        (process_id >> 16) as u16 == self.prefix()
+    }
+
+    /// The subscriptions currently held, with their remaining lifetime.
+    /// Expired subscriptions are dropped.
+    pub fn active(&self) -> Vec<ActiveSubscription> {
+        let now = Instant::now();
+        let mut registry = self.inner.lock().unwrap();
+        registry
+            .entries
+            .retain(|_, entry| entry.expires.map_or(true, |expires| expires > now));
+        let mut active: Vec<_> = registry
+            .entries
+            .iter()
+            .map(|(process_id, entry)| ActiveSubscription {
+                process_id: *process_id,
+                peer: entry.peer,
+                object_id: entry.object_id,
+                confirmed: entry.confirmed,
+                lifetime: entry.lifetime,
+                remaining: entry.expires.map(|expires| expires - now),
+            })
+            .collect();
+        active.sort_by_key(|subscription| subscription.process_id);
+        active
+    }
+
+    /// Invoke the callback of the subscription the notification belongs to.
+    /// Returns `false` if no subscription uses its process identifier.
+    pub fn dispatch(&self, notification: &CovNotification) -> bool {
+        let callback = {
+            let mut registry = self.inner.lock().unwrap();
+            let Some(entry) = registry.entries.get_mut(&notification.process_id) else {
+                return false;
+            };
+            if entry.lifetime.is_some() {
+                entry.expires = Some(Instant::now() + notification.time_remaining);
+            }
+            entry.callback.clone()
+        };
+        callback(notification);
+        true
+    }
+
+    /// Register a subscription under a free process identifier, or return
+    /// `None` when all of the prefix's are in use.
+    fn allocate(
+        &self,
+        peer: Option<SocketAddr>,
+        object_id: ObjectId,
+        confirmed: bool,
+        lifetime: Option<Duration>,
+        callback: CovCallback,
+    ) -> Option<u32> {
+        let mut registry = self.inner.lock().unwrap();
+        let prefix = (registry.prefix as u32) << 16;
+        let mut process_id = None;
+        for _ in 0..=u16::MAX {
+            let candidate = prefix | registry.next as u32;
+            registry.next = registry.next.wrapping_add(1);
+            if !registry.entries.contains_key(&candidate) {
+                process_id = Some(candidate);
+                break;
+            }
+        }
+        let process_id = process_id?;
+        registry.entries.insert(
+            process_id,
+            Entry {
+                peer,
+                object_id,
+                confirmed,
+                lifetime,
+                expires: lifetime.map(|lifetime| Instant::now() + lifetime),
+                callback,
+            },
+        );
+        Some(process_id)
+    }
+
+    fn object_id(&self, process_id: u32) -> Option<ObjectId> {
+        let registry = self.inner.lock().unwrap();
+        Some(registry.entries.get(&process_id)?.object_id)
+    }
+
+    fn remove(&self, process_id: u32) {
+        self.inner.lock().unwrap().entries.remove(&process_id);
+    }
+
+    /// Dispatch the COV notification carried by a received frame, if any.
+    /// Returns the Simple-ACK to send back for confirmed notifications.
+    pub(crate) fn handle_frame(&self, frame: &[u8]) -> Option<Vec<u8>> {
+        let received = apdu::parse_frame(frame)?;
+        let (data, ack) = match received.apdu {
+            Apdu::UnconfirmedRequest {
+                service: apdu::unconfirmed::COV_NOTIFICATION,
+                data,
+            } => (data, None),
+            Apdu::ConfirmedRequest {
+                invoke_id,
+                service: apdu::confirmed::COV_NOTIFICATION,
+                data,
+            } => (
+                data,
+                Some(apdu::simple_ack(
+                    invoke_id,
+                    apdu::confirmed::COV_NOTIFICATION,
+                )),
+            ),
+            _ => return None,
+        };
+        match CovNotification::decode(data) {
+            Ok(notification) => {
+                if !self.dispatch(&notification) {
+                    debug!(
+                        "Ignoring COV notification for unknown process {}",
+                        notification.process_id
+                    );
+                }
+            }
+            Err(err) => debug!("Ignoring malformed COV notification: {err:?}"),
+        }
+        ack.map(|ack| apdu::unicast_frame(&ack, false))
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Subscribe to COV notifications of an object and return the process
+    /// identifier allocated for the subscription.
+    ///
+    /// `lifetime` is rounded down to whole seconds; `None` subscribes
+    /// indefinitely. Notifications are dispatched to `callback` while the
+    /// client waits for responses and in [`Client::receive_notifications`].
+    /// Clients from a [`crate::ClientPool`] don't receive notifications.
+    pub async fn subscribe_cov(
+        &mut self,
+        object_id: ObjectId,
+        confirmed: bool,
+        lifetime: Option<Duration>,
+        callback: impl Fn(&CovNotification) + Send + Sync + 'static,
+    ) -> Result<u32, Error<T>> {
+        let process_id = self
+            .subscriptions()
+            .allocate(
+                self.peer(),
+                object_id,
+                confirmed,
+                lifetime,
+                Arc::new(callback),
+            )
+            .ok_or(Error::ProcessIdsExhausted)?;
+        let mut request = Encoder::new();
+        request.context_unsigned(0, process_id as u64);
+        request.context_object_id(1, object_id);
+        request.context_boolean(2, confirmed);
+        request.context_unsigned(3, lifetime.map_or(0, |lifetime| lifetime.as_secs()));
+        match self
+            .confirmed_request(apdu::confirmed::SUBSCRIBE_COV, &request.into_bytes())
+            .await
+        {
+            Ok(_) => Ok(process_id),
+            Err(err) => {
+                self.subscriptions().remove(process_id);
+                Err(err)
+            }
+        }
+    }
+
+    /// Cancel a subscription made with [`Client::subscribe_cov`]. Unknown
+    /// process identifiers are ignored.
+    pub async fn unsubscribe_cov(&mut self, process_id: u32) -> Result<(), Error<T>> {
+        let Some(object_id) = self.subscriptions().object_id(process_id) else {
+            return Ok(());
+        };
+        let mut request = Encoder::new();
+        request.context_unsigned(0, process_id as u64);
+        request.context_object_id(1, object_id);
+        self.confirmed_request(apdu::confirmed::SUBSCRIBE_COV, &request.into_bytes())
+            .await?;
+        self.subscriptions().remove(process_id);
+        Ok(())
+    }
+
+    /// Read the subscriptions the device holds, from every subscriber.
+    pub async fn read_active_cov_subscriptions(
+        &mut self,
+    ) -> Result<Vec<DeviceSubscription>, Error<T>> {
+        let data = self
+            .read_property_raw(device::wildcard_device(), ACTIVE_COV_SUBSCRIPTIONS, None)
+            .await?;
+        let mut decoder = Decoder::new(&data);
+        let mut subscriptions = Vec::new();
+        while !decoder.is_empty() {
+            subscriptions.push(DeviceSubscription::decode(&mut decoder)?);
+        }
+        Ok(subscriptions)
+    }
+}
+
+impl Client {
+    /// Cancel the subscriptions the device holds under the registry's
+    /// process identifier prefix that the registry doesn't, e.g. left by a
+    /// previous run of the application that crashed, as they use up the
+    /// device's subscription table.
+    ///
+    /// Devices only accept cancellations from the subscriber's address, so
+    /// each is sent from the address the subscription was made from, when
+    /// it is an interface of this host and its port can be bound
+    /// exclusively. Subscriptions of other hosts, or whose port another
+    /// socket uses, are reported as uncancellable: binding alongside that
+    /// socket would steal its traffic. The subscriptions are read again
+    /// afterwards to tell which are gone. Other applications sharing the
+    /// prefix would lose theirs.
+    pub async fn cancel_stale_subscriptions(&mut self) -> Result<CleanupReport, Error> {
+        let registry = self.subscriptions().clone();
+        let held = registry.active();
+        let stale: Vec<_> = self
+            .read_active_cov_subscriptions()
+            .await?
+            .into_iter()
+            .filter(|subscription| {
+                let process_id = subscription.recipient.process_id;
+                registry.owns(process_id)
+                    && !held.iter().any(|held| {
+                        held.process_id == process_id && held.object_id == subscription.object_id
+                    })
+            })
+            .collect();
+
+        let mut report = CleanupReport::default();
+        let mut attempted = Vec::new();
+        for subscription in stale {
+            match self.cancel_subscription_of(&subscription).await {
+                Ok(true) => attempted.push(subscription),
+                Ok(false) => report.uncancellable.push(subscription),
+                Err(Error::DeadlineExceeded) => return Err(Error::DeadlineExceeded),
+                Err(err) => report.failed.push((subscription, err)),
+            }
+        }
+        let listed = self.read_active_cov_subscriptions().await?;
+        for subscription in attempted {
+            match listed.iter().any(|listed| listed.is_same(&subscription)) {
+                true => report.remaining.push(subscription),
+                false => report.cancelled.push(subscription),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Cancel a subscription from the address it was made from, or from
+    /// this client's socket for subscriptions without a UDP recipient.
+    /// Returns `false` without sending anything when that address can't be
+    /// bound exclusively.
+    async fn cancel_subscription_of(
+        &mut self,
+        subscription: &DeviceSubscription,
+    ) -> Result<bool, Error> {
+        let mut request = Encoder::new();
+        request.context_unsigned(0, subscription.recipient.process_id as u64);
+        request.context_object_id(1, subscription.object_id);
+        let request = request.into_bytes();
+        let (Some(local), Some(peer)) =
+            (subscription.recipient.recipient.udp_address(), self.peer())
+        else {
+            self.confirmed_request(apdu::confirmed::SUBSCRIBE_COV, &request)
+                .await?;
+            return Ok(true);
+        };
+        let io = match TokioUdpIo::bound(local, peer) {
+            Ok(io) => io,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable
+                ) =>
+            {
+                debug!("Not cancelling the subscription of {local}: {err}");
+                return Ok(false);
+            }
+            Err(err) => return Err(Error::Io(err)),
+        };
+        let mut client = Client::from_udp(io);
+        if let Some(timeout) = self.timeout() {
+            client.set_timeout(timeout);
+        }
+        client.set_deadline(self.deadline());
+        client
+            .confirmed_request(apdu::confirmed::SUBSCRIBE_COV, &request)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipient_udp_address() {
+        let recipient = Recipient::Address {
+            network: 0,
+            mac: vec![192, 168, 1, 10, 0xba, 0xc0],
+        };
+        assert_eq!(
+            recipient.udp_address(),
+            Some(SocketAddr::from(([192, 168, 1, 10], 47808)))
+        );
+        let device = Recipient::Device(ObjectId::new(crate::ObjectType::ObjectDevice, 1));
+        assert_eq!(device.udp_address(), None);
+    }
+
+    #[tokio::test]
+    async fn cancellation_ports_are_bound_exclusively() {
+        let peer = SocketAddr::from(([127, 0, 0, 1], 47808));
+        let shared = crate::io::bind_shared(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let local = shared.local_addr().unwrap();
+        let err = TokioUdpIo::bound(local, peer).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+
+        // 192.0.2.0/24 is reserved for documentation, so not an interface
+        let remote = SocketAddr::from(([192, 0, 2, 1], local.port()));
+        let err = TokioUdpIo::bound(remote, peer).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrNotAvailable);
+    }
+}
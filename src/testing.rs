@@ -0,0 +1,988 @@
+//! In-memory transport and scripted virtual device, for testing code built
+//! on [`Client`](crate::Client) without hardware.
+//!
+//! [`MockIo`] implements `NetworkIo` by handing every request to a
+//! [`VirtualDevice`], which answers ReadProperty, ReadPropertyMultiple,
+//! WriteProperty, WritePropertyMultiple, AddListElement, RemoveListElement,
+//! AtomicReadFile, AtomicWriteFile and Who-Is from canned property values
+//! and files, and records SubscribeCOV without sending notifications.
+//! Acks for other services can be queued.
+//! Errors, rejects, latency and packet loss can be scripted to exercise
+//! error paths. [`UdpDevice`] serves a device on a local UDP socket instead,
+//! for code that makes its own BACnet/IP clients.
+//!
+//! ```ignore
+//! use async_bacnet::{testing::{MockIo, VirtualDevice}, Client, ObjectId, ObjectType, PropertyId, Value};
+//!
+//! let ai = ObjectId::new(ObjectType::ObjectAnalogInput, 1);
+//! let mut device = VirtualDevice::new(1234);
+//! device.add_object(ai, [(PropertyId::PropPresentValue, Value::Real(21.5))]);
+//! let mut client = Client::from_io(MockIo::new(device));
+//! assert_eq!(client.read_value(ai, PropertyId::PropPresentValue).await?, Value::Real(21.5));
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use embedded_bacnet::simple::NetworkIo;
+use tokio::{
+    net::UdpSocket,
+    sync::Notify,
+    task::JoinHandle,
+    time::{sleep, sleep_until, timeout_at, Instant},
+};
+
+use crate::{
+    apdu::{self, Apdu, Decoder, Encoder},
+    backup::FileData,
+    error::{DecodeError, ServiceError},
+    value::Value,
+    ObjectId, ObjectType, PropertyId,
+};
+
+const PDU_COMPLEX_ACK: u8 = 0x3;
+const PDU_ERROR: u8 = 0x5;
+const PDU_REJECT: u8 = 0x6;
+const PDU_ABORT: u8 = 0x7;
+
+const UNCONFIRMED_WHO_IS: u8 = 8;
+
+const PROPERTY_ALL: u32 = 8;
+const PROPERTY_FILE_ACCESS_METHOD: u32 = 41;
+
+const REJECT_UNRECOGNIZED_SERVICE: u8 = 9;
+const ABORT_SEGMENTATION_NOT_SUPPORTED: u8 = 4;
+const CODE_INVALID_DATA_TYPE: u32 = 9;
+const CODE_INVALID_ARRAY_INDEX: u32 = 42;
+
+/// A simulated BACnet device answering from canned property values.
+///
+/// The device object is created with its identifier, name, max-APDU,
+/// segmentation and vendor identifier; its `object-list` always lists the
+/// objects added. Written values replace the stored ones; command priorities
+/// are ignored. Responses that don't fit the max-APDU are aborted, as the
+/// device doesn't segment.
+#[derive(Debug, Clone)]
+pub struct VirtualDevice {
+    device_id: ObjectId,
+    objects: Vec<(ObjectId, BTreeMap<u32, Value>)>,
+    max_apdu: usize,
+    failures: HashMap<(u32, u32), ServiceError>,
+    rejects: HashMap<u8, u8>,
+    latency: Duration,
+    loss: f64,
+    seed: u64,
+    requests: usize,
+    subscriptions: BTreeMap<u32, ObjectId>,
+    files: BTreeMap<u32, FileData>,
+    acks: HashMap<u8, VecDeque<Vec<u8>>>,
+}
+
+impl VirtualDevice {
+    pub fn new(instance: u32) -> Self {
+        let device_id = ObjectId::new(ObjectType::ObjectDevice, instance);
+        let mut device = Self {
+            device_id,
+            objects: Vec::new(),
+            max_apdu: apdu::MAX_APDU,
+            failures: HashMap::new(),
+            rejects: HashMap::new(),
+            latency: Duration::ZERO,
+            loss: 0.0,
+            seed: 0x2545_f491_4f6c_dd1d,
+            requests: 0,
+            subscriptions: BTreeMap::new(),
+            files: BTreeMap::new(),
+            acks: HashMap::new(),
+        };
+        device.add_object(
+            device_id,
+            [
+                (
+                    PropertyId::PropObjectName,
+                    Value::CharacterString(format!("Virtual device {instance}")),
+                ),
+                (
+                    PropertyId::PropMaxApduLengthAccepted,
+                    Value::Unsigned(apdu::MAX_APDU as u64),
+                ),
+                // no-segmentation
+                (PropertyId::PropSegmentationSupported, Value::Enumerated(3)),
+                (PropertyId::PropVendorIdentifier, Value::Unsigned(0)),
+                // Answered from the objects, see `lookup`
+                (PropertyId::PropObjectList, Value::Null),
+            ],
+        );
+        device
+    }
+
+    pub fn device_id(&self) -> ObjectId {
+        self.device_id
+    }
+
+    /// Add an object with the given properties, or add the properties to
+    /// an existing object. The object identifier and type are set
+    /// automatically.
+    pub fn add_object(
+        &mut self,
+        object_id: ObjectId,
+        properties: impl IntoIterator<Item = (PropertyId, Value)>,
+    ) -> &mut Self {
+        if self.object(object_id).is_none() {
+            let mut stored = BTreeMap::new();
+            stored.insert(
+                PropertyId::PropObjectIdentifier as u32,
+                Value::ObjectId(object_id),
+            );
+            stored.insert(
+                PropertyId::PropObjectType as u32,
+                Value::Enumerated(object_id.object_type as u32),
+            );
+            self.objects.push((object_id, stored));
+        }
+        for (property_id, value) in properties {
+            self.set_property(object_id, property_id, value);
+        }
+        self
+    }
+
+    /// Remove an object, so that requests for it fail with unknown-object.
+    pub fn remove_object(&mut self, object_id: ObjectId) -> &mut Self {
+        self.objects.retain(|(id, _)| *id != object_id);
+        self
+    }
+
+    /// Set a property value, adding the object if needed.
+    pub fn set_property(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+        value: Value,
+    ) -> &mut Self {
+        if self.object(object_id).is_none() {
+            self.add_object(object_id, []);
+        }
+        if let Some(properties) = self.object_mut(object_id) {
+            properties.insert(property_id as u32, value);
+        }
+        self
+    }
+
+    /// Add a File object holding `data`, accessed as a stream or by records
+    /// as its variant says.
+    pub fn set_file(&mut self, file: ObjectId, data: FileData) -> &mut Self {
+        let method = match data {
+            FileData::Records(_) => 0,
+            FileData::Stream(_) => 1,
+        };
+        self.add_object(file, []);
+        if let Some(properties) = self.object_mut(file) {
+            properties.insert(PROPERTY_FILE_ACCESS_METHOD, Value::Enumerated(method));
+        }
+        self.files.insert(file.id, data);
+        self
+    }
+
+    /// The contents of a File object, e.g. to check what a client wrote.
+    pub fn file(&self, file: ObjectId) -> Option<&FileData> {
+        self.files.get(&file.id)
+    }
+
+    /// The stored value of a property, e.g. to check what a client wrote.
+    pub fn property(&self, object_id: ObjectId, property_id: PropertyId) -> Option<&Value> {
+        self.object(object_id)?.get(&(property_id as u32))
+    }
+
+    /// Answer reads and writes of a property with an error PDU.
+    pub fn fail_property(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+        error: ServiceError,
+    ) -> &mut Self {
+        self.failures.insert(
+            (apdu::object_id_value(object_id), property_id as u32),
+            error,
+        );
+        self
+    }
+
+    /// Reject every request for a confirmed service (by service choice,
+    /// e.g. 14 for ReadPropertyMultiple) with the given reject reason.
+    pub fn reject_service(&mut self, service: u8, reason: u8) -> &mut Self {
+        self.rejects.insert(service, reason);
+        self
+    }
+
+    /// Answer the next request for a confirmed service (by service choice)
+    /// with a Complex-ACK carrying `ack`, e.g. for services the device
+    /// doesn't simulate. Queued acks are sent in order, ahead of the
+    /// device's own answers.
+    pub fn queue_ack(&mut self, service: u8, ack: Vec<u8>) -> &mut Self {
+        self.acks.entry(service).or_default().push_back(ack);
+        self
+    }
+
+    /// Set the largest APDU the device sends and reports.
+    pub fn set_max_apdu(&mut self, max_apdu: usize) -> &mut Self {
+        self.max_apdu = max_apdu;
+        let device_id = self.device_id;
+        self.set_property(
+            device_id,
+            PropertyId::PropMaxApduLengthAccepted,
+            Value::Unsigned(max_apdu as u64),
+        )
+    }
+
+    /// Delay every response by `latency`.
+    pub fn set_latency(&mut self, latency: Duration) -> &mut Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Drop requests with the given probability (0.0 to 1.0), leaving the
+    /// client to time out. The sequence of drops is deterministic.
+    pub fn set_loss(&mut self, probability: f64) -> &mut Self {
+        self.loss = probability;
+        self
+    }
+
+    /// Number of requests received, dropped ones included.
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// The COV subscriptions made and not cancelled, by process identifier.
+    pub fn subscriptions(&self) -> Vec<(u32, ObjectId)> {
+        self.subscriptions
+            .iter()
+            .map(|(process_id, object_id)| (*process_id, *object_id))
+            .collect()
+    }
+
+    fn object(&self, object_id: ObjectId) -> Option<&BTreeMap<u32, Value>> {
+        let object_id = self.resolve(object_id);
+        self.objects
+            .iter()
+            .find(|(id, _)| *id == object_id)
+            .map(|(_, properties)| properties)
+    }
+
+    fn object_mut(&mut self, object_id: ObjectId) -> Option<&mut BTreeMap<u32, Value>> {
+        let object_id = self.resolve(object_id);
+        self.objects
+            .iter_mut()
+            .find(|(id, _)| *id == object_id)
+            .map(|(_, properties)| properties)
+    }
+
+    /// Map the wildcard device instance to this device.
+    fn resolve(&self, object_id: ObjectId) -> ObjectId {
+        match object_id.object_type {
+            ObjectType::ObjectDevice if object_id.id == crate::device::WILDCARD_INSTANCE => {
+                self.device_id
+            }
+            _ => object_id,
+        }
+    }
+
+    fn dropped(&mut self) -> bool {
+        // xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let unit = (self.seed >> 11) as f64 / (1u64 << 53) as f64;
+        unit < self.loss
+    }
+
+    /// Handle a frame sent by the client and return the response frames.
+    fn handle(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        self.requests += 1;
+        if self.dropped() {
+            return Vec::new();
+        }
+        let Some(received) = apdu::parse_frame(frame) else {
+            return Vec::new();
+        };
+        let response = match received.apdu {
+            Apdu::ConfirmedRequest {
+                invoke_id,
+                service,
+                data,
+            } => self.confirmed(invoke_id, service, data),
+            Apdu::UnconfirmedRequest {
+                service: UNCONFIRMED_WHO_IS,
+                data,
+            } => match self.who_is(data) {
+                Some(i_am) => i_am,
+                None => return Vec::new(),
+            },
+            _ => return Vec::new(),
+        };
+        vec![apdu::unicast_frame(&response, false)]
+    }
+
+    fn confirmed(&mut self, invoke_id: u8, service: u8, data: &[u8]) -> Vec<u8> {
+        if let Some(reason) = self.rejects.get(&service) {
+            return vec![PDU_REJECT << 4, invoke_id, *reason];
+        }
+        let queued = self.acks.get_mut(&service).and_then(VecDeque::pop_front);
+        let result = match service {
+            _ if queued.is_some() => Ok(queued),
+            apdu::confirmed::READ_PROPERTY => self.read_property(data),
+            apdu::confirmed::READ_PROPERTY_MULTIPLE => self.read_property_multiple(data),
+            apdu::confirmed::WRITE_PROPERTY => self.write_property(data),
+            apdu::confirmed::WRITE_PROPERTY_MULTIPLE => self.write_property_multiple(data),
+            apdu::confirmed::ATOMIC_READ_FILE => self.read_file(data),
+            apdu::confirmed::ATOMIC_WRITE_FILE => self.write_file(data),
+            apdu::confirmed::ADD_LIST_ELEMENT => self.edit_list(data, true),
+            apdu::confirmed::REMOVE_LIST_ELEMENT => self.edit_list(data, false),
+            apdu::confirmed::SUBSCRIBE_COV => self.subscribe_cov(data),
+            _ => return vec![PDU_REJECT << 4, invoke_id, REJECT_UNRECOGNIZED_SERVICE],
+        };
+        match result {
+            Ok(None) => apdu::simple_ack(invoke_id, service),
+            Ok(Some(ack)) if ack.len() + 3 > self.max_apdu => {
+                vec![
+                    (PDU_ABORT << 4) | 0x01,
+                    invoke_id,
+                    ABORT_SEGMENTATION_NOT_SUPPORTED,
+                ]
+            }
+            Ok(Some(ack)) => {
+                let mut apdu = vec![PDU_COMPLEX_ACK << 4, invoke_id, service];
+                apdu.extend_from_slice(&ack);
+                apdu
+            }
+            Err(Failure::Service(error)) => {
+                let mut encoder = Encoder::new();
+                encoder.enumerated(error.class);
+                encoder.enumerated(error.code);
+                let mut apdu = vec![PDU_ERROR << 4, invoke_id, service];
+                apdu.extend_from_slice(&encoder.into_bytes());
+                apdu
+            }
+            Err(Failure::Malformed) => {
+                // missing-required-parameter
+                vec![PDU_REJECT << 4, invoke_id, 5]
+            }
+        }
+    }
+
+    /// Look up a property value, or the error answering its read.
+    fn lookup(
+        &self,
+        object_id: ObjectId,
+        property_id: u32,
+        array_index: Option<u32>,
+    ) -> Result<Value, ServiceError> {
+        if let Some(error) = self
+            .failures
+            .get(&(apdu::object_id_value(object_id), property_id))
+        {
+            return Err(*error);
+        }
+        let properties = self.object(object_id).ok_or(ServiceError {
+            class: ServiceError::CLASS_OBJECT,
+            code: ServiceError::CODE_UNKNOWN_OBJECT,
+        })?;
+        let value = match property_id {
+            id if id == PropertyId::PropObjectList as u32
+                && self.resolve(object_id) == self.device_id =>
+            {
+                Value::Array(
+                    self.objects
+                        .iter()
+                        .map(|(id, _)| Value::ObjectId(*id))
+                        .collect(),
+                )
+            }
+            _ => properties.get(&property_id).cloned().ok_or(ServiceError {
+                class: ServiceError::CLASS_PROPERTY,
+                code: ServiceError::CODE_UNKNOWN_PROPERTY,
+            })?,
+        };
+        let invalid_index = ServiceError {
+            class: ServiceError::CLASS_PROPERTY,
+            code: CODE_INVALID_ARRAY_INDEX,
+        };
+        match (array_index, value) {
+            (None, value) => Ok(value),
+            (Some(0), Value::Array(values)) => Ok(Value::Unsigned(values.len() as u64)),
+            (Some(i), Value::Array(mut values)) if (i as usize) <= values.len() => {
+                Ok(values.swap_remove(i as usize - 1))
+            }
+            _ => Err(invalid_index),
+        }
+    }
+
+    fn read_property(&self, data: &[u8]) -> Result<Option<Vec<u8>>, Failure> {
+        let mut decoder = Decoder::new(data);
+        let object_id = apdu::decode_object_id(decoder.expect_context(0)?)?;
+        let property_id = apdu::decode_unsigned(decoder.expect_context(1)?) as u32;
+        let array_index = decoder.context_unsigned(2)?.map(|i| i as u32);
+        let value = self.lookup(object_id, property_id, array_index)?;
+
+        let mut ack = Encoder::new();
+        ack.context_object_id(0, object_id);
+        ack.context_enumerated(1, property_id);
+        if let Some(index) = array_index {
+            ack.context_unsigned(2, index as u64);
+        }
+        ack.opening(3);
+        value.encode(&mut ack);
+        ack.closing(3);
+        Ok(Some(ack.into_bytes()))
+    }
+
+    fn read_property_multiple(&self, data: &[u8]) -> Result<Option<Vec<u8>>, Failure> {
+        let mut decoder = Decoder::new(data);
+        let mut ack = Encoder::new();
+        while !decoder.is_empty() {
+            let object_id = apdu::decode_object_id(decoder.expect_context(0)?)?;
+            ack.context_object_id(0, object_id);
+            ack.opening(1);
+            decoder.expect_opening(1)?;
+            while !decoder.is_closing(1) {
+                let property_id = apdu::decode_unsigned(decoder.expect_context(0)?) as u32;
+                let array_index = decoder.context_unsigned(1)?.map(|i| i as u32);
+                let properties = match (property_id, self.object(object_id)) {
+                    (PROPERTY_ALL, Some(properties)) => properties.keys().copied().collect(),
+                    _ => vec![property_id],
+                };
+                for property_id in properties {
+                    ack.context_enumerated(2, property_id);
+                    if let Some(index) = array_index {
+                        ack.context_unsigned(3, index as u64);
+                    }
+                    match self.lookup(object_id, property_id, array_index) {
+                        Ok(value) => {
+                            ack.opening(4);
+                            value.encode(&mut ack);
+                            ack.closing(4);
+                        }
+                        Err(error) => {
+                            ack.opening(5);
+                            ack.enumerated(error.class);
+                            ack.enumerated(error.code);
+                            ack.closing(5);
+                        }
+                    }
+                }
+            }
+            decoder.expect_closing(1)?;
+            ack.closing(1);
+        }
+        Ok(Some(ack.into_bytes()))
+    }
+
+    fn write_property(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, Failure> {
+        let mut decoder = Decoder::new(data);
+        let object_id = apdu::decode_object_id(decoder.expect_context(0)?)?;
+        let property_id = apdu::decode_unsigned(decoder.expect_context(1)?) as u32;
+        let array_index = decoder.context_unsigned(2)?;
+        let value = Value::decode_all(decoder.enclosed(3)?)?;
+        self.store(object_id, property_id, array_index, value)?;
+        Ok(None)
+    }
+
+    /// Make the writes in order, stopping at the first failing one.
+    fn write_property_multiple(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, Failure> {
+        let mut decoder = Decoder::new(data);
+        while !decoder.is_empty() {
+            let object_id = apdu::decode_object_id(decoder.expect_context(0)?)?;
+            decoder.expect_opening(1)?;
+            while !decoder.is_closing(1) {
+                let property_id = apdu::decode_unsigned(decoder.expect_context(0)?) as u32;
+                let array_index = decoder.context_unsigned(1)?;
+                let value = Value::decode_all(decoder.enclosed(2)?)?;
+                // Command priorities are ignored
+                decoder.context_unsigned(3)?;
+                self.store(object_id, property_id, array_index, value)?;
+            }
+            decoder.expect_closing(1)?;
+        }
+        Ok(None)
+    }
+
+    /// Add elements missing from a list property, or remove those it holds.
+    fn edit_list(&mut self, data: &[u8], add: bool) -> Result<Option<Vec<u8>>, Failure> {
+        let mut decoder = Decoder::new(data);
+        let object_id = apdu::decode_object_id(decoder.expect_context(0)?)?;
+        let property_id = apdu::decode_unsigned(decoder.expect_context(1)?) as u32;
+        let elements = Value::decode_list(decoder.enclosed(3)?)?;
+        self.lookup(object_id, property_id, None)?;
+        let properties = self.object_mut(object_id).ok_or(Failure::Malformed)?;
+        let Some(Value::Array(list)) = properties.get_mut(&property_id) else {
+            return Err(Failure::Service(ServiceError {
+                class: ServiceError::CLASS_PROPERTY,
+                code: CODE_INVALID_DATA_TYPE,
+            }));
+        };
+        for element in elements {
+            match (add, list.contains(&element)) {
+                (true, false) => list.push(element),
+                (false, true) => list.retain(|held| *held != element),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read octets of a stream file, or records of a record file.
+    fn read_file(&self, data: &[u8]) -> Result<Option<Vec<u8>>, Failure> {
+        let mut decoder = Decoder::new(data);
+        let file = decoder.object_id()?;
+        let mut ack = Encoder::new();
+        match self.files.get(&file.id).ok_or(UNKNOWN_FILE)? {
+            FileData::Stream(contents) => {
+                decoder.expect_opening(0)?;
+                let start = (decoder.signed()?.max(0) as usize).min(contents.len());
+                let end = (start + decoder.unsigned()? as usize).min(contents.len());
+                ack.boolean(end == contents.len());
+                ack.opening(0);
+                ack.signed(start as i64);
+                ack.octet_string(&contents[start..end]);
+                ack.closing(0);
+            }
+            FileData::Records(records) => {
+                decoder.expect_opening(1)?;
+                let start = (decoder.signed()?.max(0) as usize).min(records.len());
+                let end = (start + decoder.unsigned()? as usize).min(records.len());
+                ack.boolean(end == records.len());
+                ack.opening(1);
+                ack.signed(start as i64);
+                ack.unsigned((end - start) as u64);
+                records[start..end]
+                    .iter()
+                    .for_each(|record| ack.octet_string(record));
+                ack.closing(1);
+            }
+        }
+        Ok(Some(ack.into_bytes()))
+    }
+
+    /// Write octets to a stream file, or records to a record file, from
+    /// the given start position, extending the file as needed.
+    fn write_file(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, Failure> {
+        let mut decoder = Decoder::new(data);
+        let file = decoder.object_id()?;
+        let (number, start) = match self.files.get_mut(&file.id).ok_or(UNKNOWN_FILE)? {
+            FileData::Stream(contents) => {
+                decoder.expect_opening(0)?;
+                let start = decoder.signed()?.max(0) as usize;
+                let octets = decoder.octet_string()?;
+                let end = start + octets.len();
+                if contents.len() < end {
+                    contents.resize(end, 0);
+                }
+                contents[start..end].copy_from_slice(octets);
+                (0, start)
+            }
+            FileData::Records(records) => {
+                decoder.expect_opening(1)?;
+                let start = decoder.signed()?.max(0) as usize;
+                let count = decoder.unsigned()? as usize;
+                if records.len() < start + count {
+                    records.resize(start + count, Vec::new());
+                }
+                for record in &mut records[start..start + count] {
+                    *record = decoder.octet_string()?.to_vec();
+                }
+                (1, start)
+            }
+        };
+        // The start position, context tagged with the access method
+        let mut position = Encoder::new();
+        position.signed(start as i64);
+        let mut ack = position.into_bytes();
+        ack[0] = (number << 4) | 0x08 | (ack[0] & 0x07);
+        Ok(Some(ack))
+    }
+
+    /// Write a property value, or one element of an array property.
+    fn store(
+        &mut self,
+        object_id: ObjectId,
+        property_id: u32,
+        array_index: Option<u64>,
+        value: Value,
+    ) -> Result<(), Failure> {
+        // Only existing properties are writable, as on a real device
+        self.lookup(object_id, property_id, None)?;
+        let properties = self.object_mut(object_id).ok_or(Failure::Malformed)?;
+        let stored = properties.entry(property_id).or_insert(Value::Null);
+        match (array_index, stored) {
+            (None, stored) => *stored = value,
+            (Some(i), Value::Array(values)) if i >= 1 && (i as usize) <= values.len() => {
+                values[i as usize - 1] = value;
+            }
+            _ => {
+                return Err(Failure::Service(ServiceError {
+                    class: ServiceError::CLASS_PROPERTY,
+                    code: CODE_INVALID_ARRAY_INDEX,
+                }))
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a COV subscription, or its cancellation. No notification is
+    /// sent.
+    fn subscribe_cov(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, Failure> {
+        let mut decoder = Decoder::new(data);
+        let process_id = apdu::decode_unsigned(decoder.expect_context(0)?) as u32;
+        let object_id = apdu::decode_object_id(decoder.expect_context(1)?)?;
+        // A cancellation has no issue-confirmed-notifications
+        if decoder.context_unsigned(2)?.is_none() {
+            self.subscriptions.remove(&process_id);
+            return Ok(None);
+        }
+        self.object(object_id).ok_or(ServiceError {
+            class: ServiceError::CLASS_OBJECT,
+            code: ServiceError::CODE_UNKNOWN_OBJECT,
+        })?;
+        self.subscriptions.insert(process_id, object_id);
+        Ok(None)
+    }
+
+    /// Answer a Who-Is whose range, if any, includes this device.
+    fn who_is(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut decoder = Decoder::new(data);
+        let low = decoder.context_unsigned(0).ok()?;
+        let high = decoder.context_unsigned(1).ok()?;
+        let instance = self.device_id.id as u64;
+        if let (Some(low), Some(high)) = (low, high) {
+            if instance < low || instance > high {
+                return None;
+            }
+        }
+        let mut i_am = Encoder::new();
+        i_am.object_id(self.device_id);
+        i_am.unsigned(self.max_apdu as u64);
+        i_am.enumerated(3);
+        i_am.unsigned(0);
+        Some(apdu::unconfirmed_request(
+            apdu::unconfirmed::I_AM,
+            &i_am.into_bytes(),
+        ))
+    }
+}
+
+const UNKNOWN_FILE: ServiceError = ServiceError {
+    class: ServiceError::CLASS_OBJECT,
+    code: ServiceError::CODE_UNKNOWN_OBJECT,
+};
+
+/// Why a request couldn't be answered with an ack.
+enum Failure {
+    Service(ServiceError),
+    Malformed,
+}
+
+impl From<ServiceError> for Failure {
+    fn from(value: ServiceError) -> Self {
+        Failure::Service(value)
+    }
+}
+
+impl From<DecodeError> for Failure {
+    fn from(_: DecodeError) -> Self {
+        Failure::Malformed
+    }
+}
+
+/// An in-memory `NetworkIo` connected to a [`VirtualDevice`].
+///
+/// Clones share the device and the queue of responses, so a clone kept by
+/// the test can inspect or reconfigure the device while a client uses it.
+#[derive(Debug, Clone)]
+pub struct MockIo {
+    inner: Arc<Inner>,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Inner {
+    device: Mutex<VirtualDevice>,
+    responses: Mutex<VecDeque<(Instant, Vec<u8>)>>,
+    ready: Notify,
+}
+
+impl MockIo {
+    pub fn new(device: VirtualDevice) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                device: Mutex::new(device),
+                responses: Mutex::new(VecDeque::new()),
+                ready: Notify::new(),
+            }),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// The simulated device, to inspect or change it between requests.
+    pub fn device(&self) -> MutexGuard<'_, VirtualDevice> {
+        self.inner.device.lock().unwrap()
+    }
+
+    /// Time a read waits for a response before failing with `TimedOut`,
+    /// unless the request being made sets its own, see
+    /// [`crate::request_timeout`].
+    pub fn set_timeout(&mut self, duration: Duration) {
+        self.timeout = duration;
+    }
+
+    /// Queue a frame as if the device had sent it, e.g. a hand-built
+    /// notification or a malformed response.
+    pub fn inject(&self, frame: Vec<u8>) {
+        self.push(Instant::now(), frame);
+    }
+
+    fn push(&self, at: Instant, frame: Vec<u8>) {
+        self.inner.responses.lock().unwrap().push_back((at, frame));
+        self.inner.ready.notify_one();
+    }
+}
+
+impl NetworkIo for MockIo {
+    type Error = std::io::Error;
+
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let deadline = Instant::now() + crate::request_timeout(self.timeout);
+        loop {
+            let next = self.inner.responses.lock().unwrap().pop_front();
+            if let Some((at, frame)) = next {
+                if at > deadline {
+                    self.inner.responses.lock().unwrap().push_front((at, frame));
+                    sleep_until(deadline).await;
+                    break;
+                }
+                sleep_until(at).await;
+                let len = frame.len().min(buf.len());
+                buf[..len].copy_from_slice(&frame[..len]);
+                return Ok(len);
+            }
+            if timeout_at(deadline, self.inner.ready.notified())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "read timed out",
+        ))
+    }
+
+    async fn write(&self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let (latency, responses) = {
+            let mut device = self.device();
+            (device.latency, device.handle(buf))
+        };
+        let at = Instant::now() + latency;
+        for response in responses {
+            self.push(at, response);
+        }
+        Ok(buf.len())
+    }
+}
+
+/// A [`VirtualDevice`] answering BACnet/IP requests on a local UDP socket,
+/// until dropped.
+#[derive(Debug)]
+pub struct UdpDevice {
+    local_addr: SocketAddr,
+    device: Arc<Mutex<VirtualDevice>>,
+    task: JoinHandle<()>,
+}
+
+impl UdpDevice {
+    /// Serve `device` on `local`, e.g. `127.0.0.1:0` for an ephemeral port.
+    pub async fn bind(device: VirtualDevice, local: SocketAddr) -> std::io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(local).await?);
+        let device = Arc::new(Mutex::new(device));
+        let task = tokio::spawn(serve(socket.clone(), device.clone()));
+        Ok(Self {
+            local_addr: socket.local_addr()?,
+            device,
+            task,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The served device, to inspect or change it between requests.
+    pub fn device(&self) -> MutexGuard<'_, VirtualDevice> {
+        self.device.lock().unwrap()
+    }
+}
+
+impl Drop for UdpDevice {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answer the datagrams received on `socket` from `device`, after its
+/// latency.
+async fn serve(socket: Arc<UdpSocket>, device: Arc<Mutex<VirtualDevice>>) {
+    let mut buf = vec![0u8; 1500];
+    loop {
+        let Ok((n, from)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let (latency, responses) = {
+            let mut device = device.lock().unwrap();
+            (device.latency, device.handle(&buf[..n]))
+        };
+        let socket = socket.clone();
+        tokio::spawn(async move {
+            sleep(latency).await;
+            for response in responses {
+                let _ = socket.send_to(&response, from).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analog_value() -> ObjectId {
+        ObjectId::new(ObjectType::ObjectAnalogValue, 1)
+    }
+
+    fn device() -> VirtualDevice {
+        let mut device = VirtualDevice::new(1234);
+        device.add_object(
+            analog_value(),
+            [
+                (PropertyId::PropPresentValue, Value::Real(21.5)),
+                (
+                    PropertyId::PropStateText,
+                    Value::Array(vec![
+                        Value::CharacterString("Off".to_string()),
+                        Value::CharacterString("On".to_string()),
+                    ]),
+                ),
+            ],
+        );
+        device
+    }
+
+    /// Send a confirmed request and return the APDU of the response.
+    fn request(device: &mut VirtualDevice, service: u8, payload: Vec<u8>) -> Vec<u8> {
+        let frame = apdu::unicast_frame(&apdu::confirmed_request(1, service, &payload), true);
+        let responses = device.handle(&frame);
+        assert_eq!(responses.len(), 1);
+        let offset = apdu::apdu_offset(&responses[0]).unwrap();
+        responses[0][offset..].to_vec()
+    }
+
+    #[test]
+    fn lookup_array_indices() {
+        let device = device();
+        let state_text = PropertyId::PropStateText as u32;
+        assert_eq!(
+            device.lookup(analog_value(), state_text, Some(0)),
+            Ok(Value::Unsigned(2))
+        );
+        assert_eq!(
+            device.lookup(analog_value(), state_text, Some(1)),
+            Ok(Value::CharacterString("Off".to_string()))
+        );
+        assert_eq!(
+            device.lookup(analog_value(), state_text, Some(2)),
+            Ok(Value::CharacterString("On".to_string()))
+        );
+        let invalid = Err(ServiceError {
+            class: ServiceError::CLASS_PROPERTY,
+            code: CODE_INVALID_ARRAY_INDEX,
+        });
+        assert_eq!(device.lookup(analog_value(), state_text, Some(3)), invalid);
+        let present_value = PropertyId::PropPresentValue as u32;
+        assert_eq!(
+            device.lookup(analog_value(), present_value, Some(1)),
+            invalid
+        );
+    }
+
+    #[test]
+    fn read_property_multiple_all() {
+        let mut device = device();
+        let mut payload = Encoder::new();
+        payload.context_object_id(0, analog_value());
+        payload.opening(1);
+        payload.context_enumerated(0, PROPERTY_ALL);
+        payload.closing(1);
+        let response = request(
+            &mut device,
+            apdu::confirmed::READ_PROPERTY_MULTIPLE,
+            payload.into_bytes(),
+        );
+        assert_eq!(response[0] >> 4, PDU_COMPLEX_ACK);
+
+        let mut decoder = Decoder::new(&response[3..]);
+        decoder.expect_context(0).unwrap();
+        decoder.expect_opening(1).unwrap();
+        let mut properties = Vec::new();
+        while !decoder.is_closing(1) {
+            properties.push(apdu::decode_unsigned(decoder.expect_context(2).unwrap()) as u32);
+            decoder.enclosed(4).unwrap();
+        }
+        decoder.expect_closing(1).unwrap();
+        assert!(decoder.is_empty());
+        // identifier, type, and the properties added, in property order
+        assert_eq!(
+            properties,
+            [
+                PropertyId::PropObjectIdentifier as u32,
+                PropertyId::PropObjectType as u32,
+                PropertyId::PropPresentValue as u32,
+                PropertyId::PropStateText as u32,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejected_service() {
+        let mut device = device();
+        device.reject_service(apdu::confirmed::READ_PROPERTY, REJECT_UNRECOGNIZED_SERVICE);
+        let response = request(&mut device, apdu::confirmed::READ_PROPERTY, Vec::new());
+        assert_eq!(response, [PDU_REJECT << 4, 1, REJECT_UNRECOGNIZED_SERVICE]);
+    }
+
+    #[tokio::test]
+    async fn latency_within_the_timeout() {
+        let mut device = device();
+        device.set_latency(Duration::from_millis(20));
+        let mut io = MockIo::new(device);
+        io.set_timeout(Duration::from_millis(500));
+        io.inject(vec![1, 2, 3]);
+        let mut buf = [0u8; 16];
+        assert_eq!(io.read(&mut buf).await.unwrap(), 3);
+
+        // a response due after the timeout stays queued for the next read
+        io.device().set_latency(Duration::from_millis(100));
+        io.set_timeout(Duration::from_millis(30));
+        io.write(&apdu::unicast_frame(
+            &apdu::confirmed_request(1, 12, &[]),
+            true,
+        ))
+        .await
+        .unwrap();
+        let err = io.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        io.set_timeout(Duration::from_millis(500));
+        assert!(io.read(&mut buf).await.is_ok());
+    }
+}
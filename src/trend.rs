@@ -0,0 +1,468 @@
+//! Trend log records read with ReadRange.
+
+use std::collections::{BTreeMap, HashMap};
+
+use log::warn;
+
+use crate::{
+    apdu::{self, Decoder, Encoder, TagKind},
+    client::Client,
+    error::{DecodeError, Error},
+    health::StatusFlags,
+    value::{Date, DateTime, Time, Value, UNSPECIFIED},
+    NetworkIo, ObjectId, PropertyId,
+};
+
+/// Records requested per ReadRange. Devices return fewer when the ack
+/// wouldn't fit their max-APDU, and say so with the more-items flag.
+const PAGE_SIZE: i64 = 100;
+
+/// The value logged by a trend log record (BACnetLogRecord log-datum).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "value", rename_all = "kebab-case")
+)]
+pub enum LogDatum {
+    /// Log status flags: log-disabled, buffer-purged, log-interrupted.
+    LogStatus(Vec<bool>),
+    Bool(bool),
+    Real(f32),
+    Enumerated(u32),
+    Unsigned(u64),
+    Signed(i64),
+    BitString(Vec<bool>),
+    Null,
+    /// The monitored property couldn't be read.
+    Failure {
+        class: u32,
+        code: u32,
+    },
+    /// The clock changed by this many seconds.
+    TimeChange(f32),
+    Any(Value),
+}
+
+/// A trend log record.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogRecord {
+    /// Position of the record in the log, when the device reports it.
+    pub sequence_number: Option<u32>,
+    pub timestamp: DateTime,
+    pub datum: LogDatum,
+    pub status_flags: Option<StatusFlags>,
+}
+
+impl LogRecord {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        decoder.expect_opening(0)?;
+        let timestamp = DateTime::decode(decoder)?;
+        decoder.expect_closing(0)?;
+
+        decoder.expect_opening(1)?;
+        let header = decoder.read_tag()?;
+        let datum = match header.kind {
+            TagKind::Opening => {
+                let mut data = Decoder::new(decoder.remaining());
+                while !data.is_closing(header.number) {
+                    data.skip()?;
+                }
+                let len = decoder.remaining().len() - data.remaining().len();
+                let content = decoder.bytes(len)?;
+                decoder.expect_closing(header.number)?;
+                match header.number {
+                    8 => {
+                        let error = apdu::parse_error(content);
+                        LogDatum::Failure {
+                            class: error.class,
+                            code: error.code,
+                        }
+                    }
+                    10 => LogDatum::Any(Value::decode_all(content)?),
+                    _ => return Err(DecodeError("unknown log datum")),
+                }
+            }
+            TagKind::Closing => return Err(DecodeError("empty log datum")),
+            TagKind::Value(len) => {
+                let bytes = decoder.bytes(len as usize)?;
+                match header.number {
+                    0 => LogDatum::LogStatus(apdu::decode_bit_string(bytes)),
+                    1 => LogDatum::Bool(apdu::decode_unsigned(bytes) != 0),
+                    2 => LogDatum::Real(apdu::decode_real(bytes)?),
+                    3 => LogDatum::Enumerated(apdu::decode_unsigned(bytes) as u32),
+                    4 => LogDatum::Unsigned(apdu::decode_unsigned(bytes)),
+                    5 => LogDatum::Signed(apdu::decode_signed(bytes)),
+                    6 => LogDatum::BitString(apdu::decode_bit_string(bytes)),
+                    7 => LogDatum::Null,
+                    9 => LogDatum::TimeChange(apdu::decode_real(bytes)?),
+                    _ => return Err(DecodeError("unknown log datum")),
+                }
+            }
+        };
+        decoder.expect_closing(1)?;
+
+        let status_flags = decoder
+            .context(2)?
+            .map(|bytes| StatusFlags::from_bits(&apdu::decode_bit_string(bytes)));
+        Ok(Self {
+            sequence_number: None,
+            timestamp,
+            datum,
+            status_flags,
+        })
+    }
+}
+
+/// Where collection of each trend log got to, see [`Client::collect_trend`].
+///
+/// Persist it (with the `serde` feature) to resume collection after a
+/// restart without losing or repeating records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrendCollector {
+    /// Last collected sequence number, by encoded trend log identifier.
+    last_sequence: HashMap<u32, u32>,
+}
+
+impl TrendCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sequence number of the last record collected from the trend log.
+    pub fn last_sequence(&self, trend_log: ObjectId) -> Option<u32> {
+        self.last_sequence
+            .get(&apdu::object_id_value(trend_log))
+            .copied()
+    }
+
+    /// Resume collection after the given record, or from the oldest record
+    /// held by the device with `None`.
+    pub fn set_last_sequence(&mut self, trend_log: ObjectId, sequence_number: Option<u32>) {
+        let key = apdu::object_id_value(trend_log);
+        match sequence_number {
+            Some(sequence_number) => self.last_sequence.insert(key, sequence_number),
+            None => self.last_sequence.remove(&key),
+        };
+    }
+}
+
+/// Reference time older than any record.
+const OLDEST: DateTime = DateTime {
+    date: Date {
+        year: 1900,
+        month: 1,
+        day: 1,
+        weekday: 1,
+    },
+    time: Time {
+        hour: 0,
+        minute: 0,
+        second: 0,
+        hundredths: 0,
+    },
+};
+
+/// The time one hundredth of a second before `datetime`, for a ReadRange
+/// by time, which returns the records strictly newer than its reference
+/// time, to include those logged at `datetime`. Times with unspecified
+/// fields are returned as is.
+fn just_before(datetime: DateTime) -> DateTime {
+    let DateTime { mut date, mut time } = datetime;
+    let fields = [
+        date.month,
+        date.day,
+        time.hour,
+        time.minute,
+        time.second,
+        time.hundredths,
+    ];
+    if fields.contains(&UNSPECIFIED) || date.year.saturating_sub(1900) >= UNSPECIFIED as u16 {
+        return datetime;
+    }
+    if time.hundredths > 0 {
+        time.hundredths -= 1;
+    } else if [time.hour, time.minute, time.second] != [0, 0, 0] {
+        let seconds = (time.hour as u32 * 60 + time.minute as u32) * 60 + time.second as u32 - 1;
+        time = Time {
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+            hundredths: 99,
+        };
+    } else {
+        time = Time {
+            hour: 23,
+            minute: 59,
+            second: 59,
+            hundredths: 99,
+        };
+        date = day_before(date);
+    }
+    DateTime::new(date, time)
+}
+
+fn day_before(date: Date) -> Date {
+    let weekday = match date.weekday {
+        1 => 7,
+        2..=7 => date.weekday - 1,
+        weekday => weekday,
+    };
+    let (year, month, day) = match (date.month, date.day) {
+        (month, 2..) => (date.year, month, date.day - 1),
+        (1, _) => (date.year - 1, 12, 31),
+        (month, _) => (date.year, month - 1, days_in_month(date.year, month - 1)),
+    };
+    Date {
+        year,
+        month,
+        day,
+        weekday,
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Which records a ReadRange asks for.
+#[derive(Debug, Copy, Clone)]
+enum Range {
+    BySequence(u32),
+    ByTime(DateTime),
+}
+
+/// One page of a ReadRange ack.
+#[derive(Debug)]
+struct Page {
+    records: Vec<LogRecord>,
+    more_items: bool,
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read the records of a trend log logged between `start` and `end`,
+    /// both inclusive, in log order.
+    ///
+    /// The first page is read by time from just before `start`, the
+    /// following ones by sequence number from the last record received.
+    /// Devices that don't report sequence numbers are read by time
+    /// throughout. Records returned by overlapping pages are only kept once.
+    pub async fn read_trend_between(
+        &mut self,
+        trend_log: ObjectId,
+        start: DateTime,
+        end: DateTime,
+    ) -> Result<Vec<LogRecord>, Error<T>> {
+        let mut by_sequence = BTreeMap::new();
+        let mut by_time = Vec::new();
+        let mut range = Range::ByTime(just_before(start));
+        loop {
+            let page = self.read_range(trend_log, range).await?;
+            let Some(last) = page.records.last().cloned() else {
+                break;
+            };
+            let mut past_end = false;
+            for record in page.records {
+                if record.timestamp > end {
+                    past_end = true;
+                    continue;
+                }
+                if record.timestamp < start {
+                    continue;
+                }
+                match record.sequence_number {
+                    Some(sequence_number) => {
+                        by_sequence.insert(sequence_number, record);
+                    }
+                    // Pages come in log order, so a repeated record is
+                    // among the last ones, logged at the same time.
+                    None if !by_time
+                        .iter()
+                        .rev()
+                        .take_while(|kept| kept.timestamp >= record.timestamp)
+                        .any(|kept| *kept == record) =>
+                    {
+                        by_time.push(record)
+                    }
+                    None => {}
+                }
+            }
+            if past_end || !page.more_items {
+                break;
+            }
+            let next = match last.sequence_number {
+                Some(sequence_number) => Range::BySequence(sequence_number.wrapping_add(1)),
+                None => Range::ByTime(last.timestamp),
+            };
+            if matches!((range, next), (Range::ByTime(a), Range::ByTime(b)) if a == b) {
+                // A whole page logged at the same time: no way to go further
+                break;
+            }
+            range = next;
+        }
+
+        let mut records: Vec<_> = by_sequence.into_values().collect();
+        records.extend(by_time);
+        records.sort_by_key(|record| (record.timestamp, record.sequence_number));
+        Ok(records)
+    }
+
+    /// Read the records logged since the last collection of the trend log,
+    /// and advance the collector past them.
+    ///
+    /// Records are read by sequence number, so a collector that was offline
+    /// backfills everything the device still holds. Records the device
+    /// overwrote in the meantime are lost; that is logged as a warning. A
+    /// log that was cleared on the device is collected from its start again.
+    ///
+    /// The collector only advances when the whole backlog was read.
+    pub async fn collect_trend(
+        &mut self,
+        collector: &mut TrendCollector,
+        trend_log: ObjectId,
+    ) -> Result<Vec<LogRecord>, Error<T>> {
+        let mut last = collector.last_sequence(trend_log);
+        let mut range = match last {
+            Some(sequence_number) => Range::BySequence(sequence_number.wrapping_add(1)),
+            None => Range::ByTime(OLDEST),
+        };
+        let mut records: Vec<LogRecord> = Vec::new();
+        loop {
+            let page = self.read_range(trend_log, range).await?;
+            let (Some(first), Some(newest)) = (page.records.first(), page.records.last()) else {
+                // Nothing new, unless the log was cleared and restarted below
+                // our sequence number
+                if let (true, Some(sequence_number)) = (records.is_empty(), last) {
+                    let total = self
+                        .read_value(trend_log, PropertyId::PropTotalRecordCount)
+                        .await?
+                        .as_u32()
+                        .ok_or(DecodeError("total-record-count is not unsigned"))?;
+                    if total < sequence_number {
+                        warn!("Trend log {trend_log:?} was cleared, collecting it again");
+                        last = None;
+                        range = Range::ByTime(OLDEST);
+                        continue;
+                    }
+                }
+                break;
+            };
+            let (Some(first), Some(newest)) = (first.sequence_number, newest.sequence_number)
+            else {
+                return Err(Error::Decode("trend log reports no sequence numbers"));
+            };
+            if let (true, Some(sequence_number)) = (records.is_empty(), last) {
+                let expected = sequence_number.wrapping_add(1);
+                if first > expected {
+                    let lost = first - expected;
+                    warn!("{lost} records of trend log {trend_log:?} were overwritten before collection");
+                }
+            }
+            records.extend(page.records);
+            if !page.more_items {
+                break;
+            }
+            range = Range::BySequence(newest.wrapping_add(1));
+        }
+
+        let newest = records.last().and_then(|record| record.sequence_number);
+        collector.set_last_sequence(trend_log, newest.or(last));
+        Ok(records)
+    }
+
+    async fn read_range(&mut self, object_id: ObjectId, range: Range) -> Result<Page, Error<T>> {
+        let mut request = Encoder::new();
+        request.context_object_id(0, object_id);
+        request.context_enumerated(1, PropertyId::PropLogBuffer as u32);
+        match range {
+            Range::BySequence(sequence_number) => {
+                request.opening(6);
+                request.unsigned(sequence_number as u64);
+                request.signed(PAGE_SIZE);
+                request.closing(6);
+            }
+            Range::ByTime(time) => {
+                request.opening(7);
+                time.encode(&mut request);
+                request.signed(PAGE_SIZE);
+                request.closing(7);
+            }
+        }
+        let ack = self
+            .confirmed_request(apdu::confirmed::READ_RANGE, &request.into_bytes())
+            .await?;
+
+        let mut decoder = Decoder::new(&ack);
+        decoder.expect_context(0)?;
+        decoder.expect_context(1)?;
+        decoder.context(2)?;
+        let flags = apdu::decode_bit_string(decoder.expect_context(3)?);
+        decoder.expect_context(4)?;
+        decoder.expect_opening(5)?;
+        let mut records = Vec::new();
+        while !decoder.is_closing(5) {
+            records.push(LogRecord::decode(&mut decoder)?);
+        }
+        decoder.expect_closing(5)?;
+        if let Some(first) = decoder.context_unsigned(6)? {
+            for (i, record) in records.iter_mut().enumerate() {
+                record.sequence_number = Some((first as u32).wrapping_add(i as u32));
+            }
+        }
+        Ok(Page {
+            records,
+            more_items: flags.get(2).copied().unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(date: (u16, u8, u8, u8), time: (u8, u8, u8, u8)) -> DateTime {
+        DateTime::new(
+            Date {
+                year: date.0,
+                month: date.1,
+                day: date.2,
+                weekday: date.3,
+            },
+            Time {
+                hour: time.0,
+                minute: time.1,
+                second: time.2,
+                hundredths: time.3,
+            },
+        )
+    }
+
+    #[test]
+    fn just_before_borrows_across_fields() {
+        assert_eq!(
+            just_before(datetime((2024, 5, 14, 2), (10, 30, 0, 50))),
+            datetime((2024, 5, 14, 2), (10, 30, 0, 49))
+        );
+        assert_eq!(
+            just_before(datetime((2024, 5, 14, 2), (10, 0, 0, 0))),
+            datetime((2024, 5, 14, 2), (9, 59, 59, 99))
+        );
+        assert_eq!(
+            just_before(datetime((2024, 3, 1, 5), (0, 0, 0, 0))),
+            datetime((2024, 2, 29, 4), (23, 59, 59, 99))
+        );
+        assert_eq!(
+            just_before(datetime((2024, 1, 1, 1), (0, 0, 0, 0))),
+            datetime((2023, 12, 31, 7), (23, 59, 59, 99))
+        );
+        let unspecified = datetime((2024, 1, UNSPECIFIED, 1), (0, 0, 0, 0));
+        assert_eq!(just_before(unspecified), unspecified);
+    }
+}
@@ -0,0 +1,330 @@
+//! Engineering units: symbols, names, and conversion between units of the
+//! same quantity.
+
+use std::{fmt, str::FromStr};
+
+/// Engineering units of a point (BACnetEngineeringUnits).
+///
+/// Displayed as their symbol, or their name when they have none, e.g.
+/// `°F` or `power-factor`. Units this crate doesn't know show as their
+/// number.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Units(pub u32);
+
+/// Quantity measured by a unit. Units convert only to units of the same
+/// quantity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Quantity {
+    Area,
+    Current,
+    Resistance,
+    Voltage,
+    ApparentPower,
+    ReactivePower,
+    Angle,
+    Energy,
+    Frequency,
+    Length,
+    Irradiance,
+    Illuminance,
+    Mass,
+    MassFlow,
+    Power,
+    Pressure,
+    Temperature,
+    TemperatureDelta,
+    TemperatureRate,
+    Time,
+    Velocity,
+    Volume,
+    VolumeFlow,
+    Ratio,
+    /// Not convertible to any other unit.
+    Unique,
+}
+
+use Quantity::*;
+
+/// A known unit, with the factor and offset turning a value into the
+/// reference unit of its quantity.
+struct UnitInfo {
+    number: u32,
+    name: &'static str,
+    symbol: Option<&'static str>,
+    quantity: Quantity,
+    factor: f64,
+    offset: f64,
+}
+
+const fn unit(
+    number: u32,
+    name: &'static str,
+    symbol: Option<&'static str>,
+    quantity: Quantity,
+    factor: f64,
+    offset: f64,
+) -> UnitInfo {
+    UnitInfo {
+        number,
+        name,
+        symbol,
+        quantity,
+        factor,
+        offset,
+    }
+}
+
+#[rustfmt::skip]
+const UNITS: &[UnitInfo] = &[
+    unit(0, "square-meters", Some("m²"), Area, 1.0, 0.0),
+    unit(1, "square-feet", Some("ft²"), Area, 0.092_903_04, 0.0),
+    unit(2, "milliamperes", Some("mA"), Current, 1e-3, 0.0),
+    unit(3, "amperes", Some("A"), Current, 1.0, 0.0),
+    unit(4, "ohms", Some("Ω"), Resistance, 1.0, 0.0),
+    unit(5, "volts", Some("V"), Voltage, 1.0, 0.0),
+    unit(6, "kilovolts", Some("kV"), Voltage, 1e3, 0.0),
+    unit(7, "megavolts", Some("MV"), Voltage, 1e6, 0.0),
+    unit(8, "volt-amperes", Some("VA"), ApparentPower, 1.0, 0.0),
+    unit(9, "kilovolt-amperes", Some("kVA"), ApparentPower, 1e3, 0.0),
+    unit(10, "megavolt-amperes", Some("MVA"), ApparentPower, 1e6, 0.0),
+    unit(11, "volt-amperes-reactive", Some("var"), ReactivePower, 1.0, 0.0),
+    unit(12, "kilovolt-amperes-reactive", Some("kvar"), ReactivePower, 1e3, 0.0),
+    unit(13, "megavolt-amperes-reactive", Some("Mvar"), ReactivePower, 1e6, 0.0),
+    unit(14, "degrees-phase", Some("°"), Angle, 1.0, 0.0),
+    unit(15, "power-factor", None, Unique, 1.0, 0.0),
+    unit(16, "joules", Some("J"), Energy, 1.0, 0.0),
+    unit(17, "kilojoules", Some("kJ"), Energy, 1e3, 0.0),
+    unit(18, "watt-hours", Some("Wh"), Energy, 3.6e3, 0.0),
+    unit(19, "kilowatt-hours", Some("kWh"), Energy, 3.6e6, 0.0),
+    unit(20, "btus", Some("BTU"), Energy, 1_055.055_852_62, 0.0),
+    unit(21, "therms", Some("thm"), Energy, 105_480_400.0, 0.0),
+    unit(22, "ton-hours", Some("ton·h"), Energy, 12_660_670.231_44, 0.0),
+    unit(25, "cycles-per-hour", Some("cph"), Frequency, 1.0 / 3600.0, 0.0),
+    unit(26, "cycles-per-minute", Some("cpm"), Frequency, 1.0 / 60.0, 0.0),
+    unit(27, "hertz", Some("Hz"), Frequency, 1.0, 0.0),
+    unit(29, "percent-relative-humidity", Some("%RH"), Unique, 1.0, 0.0),
+    unit(30, "millimeters", Some("mm"), Length, 1e-3, 0.0),
+    unit(31, "meters", Some("m"), Length, 1.0, 0.0),
+    unit(32, "inches", Some("in"), Length, 0.0254, 0.0),
+    unit(33, "feet", Some("ft"), Length, 0.3048, 0.0),
+    unit(34, "watts-per-square-foot", Some("W/ft²"), Irradiance, 10.763_910_4, 0.0),
+    unit(35, "watts-per-square-meter", Some("W/m²"), Irradiance, 1.0, 0.0),
+    unit(36, "lumens", Some("lm"), Unique, 1.0, 0.0),
+    unit(37, "luxes", Some("lx"), Illuminance, 1.0, 0.0),
+    unit(38, "foot-candles", Some("fc"), Illuminance, 10.763_910_4, 0.0),
+    unit(39, "kilograms", Some("kg"), Mass, 1.0, 0.0),
+    unit(40, "pounds-mass", Some("lb"), Mass, 0.453_592_37, 0.0),
+    unit(42, "kilograms-per-second", Some("kg/s"), MassFlow, 1.0, 0.0),
+    unit(43, "kilograms-per-minute", Some("kg/min"), MassFlow, 1.0 / 60.0, 0.0),
+    unit(44, "kilograms-per-hour", Some("kg/h"), MassFlow, 1.0 / 3600.0, 0.0),
+    unit(45, "pounds-mass-per-minute", Some("lb/min"), MassFlow, 0.453_592_37 / 60.0, 0.0),
+    unit(46, "pounds-mass-per-hour", Some("lb/h"), MassFlow, 0.453_592_37 / 3600.0, 0.0),
+    unit(47, "watts", Some("W"), Power, 1.0, 0.0),
+    unit(48, "kilowatts", Some("kW"), Power, 1e3, 0.0),
+    unit(49, "megawatts", Some("MW"), Power, 1e6, 0.0),
+    unit(50, "btus-per-hour", Some("BTU/h"), Power, 0.293_071_07, 0.0),
+    unit(51, "horsepower", Some("hp"), Power, 745.699_871_6, 0.0),
+    unit(52, "tons-refrigeration", Some("TR"), Power, 3_516.852_8, 0.0),
+    unit(53, "pascals", Some("Pa"), Pressure, 1.0, 0.0),
+    unit(54, "kilopascals", Some("kPa"), Pressure, 1e3, 0.0),
+    unit(55, "bars", Some("bar"), Pressure, 1e5, 0.0),
+    unit(56, "pounds-force-per-square-inch", Some("psi"), Pressure, 6_894.757_293, 0.0),
+    unit(57, "centimeters-of-water", Some("cmH₂O"), Pressure, 98.0665, 0.0),
+    unit(58, "inches-of-water", Some("inH₂O"), Pressure, 249.088_91, 0.0),
+    unit(59, "millimeters-of-mercury", Some("mmHg"), Pressure, 133.322_387, 0.0),
+    unit(60, "centimeters-of-mercury", Some("cmHg"), Pressure, 1_333.223_87, 0.0),
+    unit(61, "inches-of-mercury", Some("inHg"), Pressure, 3_386.389, 0.0),
+    unit(62, "degrees-celsius", Some("°C"), Temperature, 1.0, 273.15),
+    unit(63, "degrees-kelvin", Some("K"), Temperature, 1.0, 0.0),
+    unit(64, "degrees-fahrenheit", Some("°F"), Temperature, 5.0 / 9.0, 273.15 - 32.0 * 5.0 / 9.0),
+    unit(69, "weeks", Some("wk"), Time, 604_800.0, 0.0),
+    unit(70, "days", Some("d"), Time, 86_400.0, 0.0),
+    unit(71, "hours", Some("h"), Time, 3_600.0, 0.0),
+    unit(72, "minutes", Some("min"), Time, 60.0, 0.0),
+    unit(73, "seconds", Some("s"), Time, 1.0, 0.0),
+    unit(74, "meters-per-second", Some("m/s"), Velocity, 1.0, 0.0),
+    unit(75, "kilometers-per-hour", Some("km/h"), Velocity, 1.0 / 3.6, 0.0),
+    unit(76, "feet-per-second", Some("ft/s"), Velocity, 0.3048, 0.0),
+    unit(77, "feet-per-minute", Some("ft/min"), Velocity, 0.3048 / 60.0, 0.0),
+    unit(78, "miles-per-hour", Some("mph"), Velocity, 0.447_04, 0.0),
+    unit(79, "cubic-feet", Some("ft³"), Volume, 0.028_316_846_592, 0.0),
+    unit(80, "cubic-meters", Some("m³"), Volume, 1.0, 0.0),
+    unit(81, "imperial-gallons", Some("imp gal"), Volume, 0.004_546_09, 0.0),
+    unit(82, "liters", Some("L"), Volume, 1e-3, 0.0),
+    unit(83, "us-gallons", Some("gal"), Volume, 0.003_785_411_784, 0.0),
+    unit(84, "cubic-feet-per-minute", Some("cfm"), VolumeFlow, 0.028_316_846_592 / 60.0, 0.0),
+    unit(85, "cubic-meters-per-second", Some("m³/s"), VolumeFlow, 1.0, 0.0),
+    unit(86, "imperial-gallons-per-minute", Some("imp gal/min"), VolumeFlow, 0.004_546_09 / 60.0, 0.0),
+    unit(87, "liters-per-second", Some("L/s"), VolumeFlow, 1e-3, 0.0),
+    unit(88, "liters-per-minute", Some("L/min"), VolumeFlow, 1e-3 / 60.0, 0.0),
+    unit(89, "us-gallons-per-minute", Some("gpm"), VolumeFlow, 0.003_785_411_784 / 60.0, 0.0),
+    unit(90, "degrees-angular", Some("°"), Angle, 1.0, 0.0),
+    unit(91, "degrees-celsius-per-hour", Some("°C/h"), TemperatureRate, 1.0 / 3600.0, 0.0),
+    unit(92, "degrees-celsius-per-minute", Some("°C/min"), TemperatureRate, 1.0 / 60.0, 0.0),
+    unit(93, "degrees-fahrenheit-per-hour", Some("°F/h"), TemperatureRate, 5.0 / 9.0 / 3600.0, 0.0),
+    unit(94, "degrees-fahrenheit-per-minute", Some("°F/min"), TemperatureRate, 5.0 / 9.0 / 60.0, 0.0),
+    unit(95, "no-units", None, Unique, 1.0, 0.0),
+    unit(96, "parts-per-million", Some("ppm"), Ratio, 1e-6, 0.0),
+    unit(97, "parts-per-billion", Some("ppb"), Ratio, 1e-9, 0.0),
+    unit(98, "percent", Some("%"), Ratio, 1e-2, 0.0),
+    unit(100, "per-minute", Some("/min"), Frequency, 1.0 / 60.0, 0.0),
+    unit(101, "per-second", Some("/s"), Frequency, 1.0, 0.0),
+    unit(103, "radians", Some("rad"), Angle, 180.0 / std::f64::consts::PI, 0.0),
+    unit(104, "revolutions-per-minute", Some("rpm"), Frequency, 1.0 / 60.0, 0.0),
+    unit(120, "delta-degrees-fahrenheit", Some("Δ°F"), TemperatureDelta, 5.0 / 9.0, 0.0),
+    unit(121, "delta-degrees-kelvin", Some("ΔK"), TemperatureDelta, 1.0, 0.0),
+    unit(122, "kilohms", Some("kΩ"), Resistance, 1e3, 0.0),
+    unit(123, "megohms", Some("MΩ"), Resistance, 1e6, 0.0),
+    unit(124, "millivolts", Some("mV"), Voltage, 1e-3, 0.0),
+    unit(126, "megajoules", Some("MJ"), Energy, 1e6, 0.0),
+    unit(129, "kilohertz", Some("kHz"), Frequency, 1e3, 0.0),
+    unit(130, "megahertz", Some("MHz"), Frequency, 1e6, 0.0),
+    unit(131, "per-hour", Some("/h"), Frequency, 1.0 / 3600.0, 0.0),
+    unit(132, "milliwatts", Some("mW"), Power, 1e-3, 0.0),
+    unit(133, "hectopascals", Some("hPa"), Pressure, 1e2, 0.0),
+    unit(134, "millibars", Some("mbar"), Pressure, 1e2, 0.0),
+    unit(135, "cubic-meters-per-hour", Some("m³/h"), VolumeFlow, 1.0 / 3600.0, 0.0),
+    unit(136, "liters-per-hour", Some("L/h"), VolumeFlow, 1e-3 / 3600.0, 0.0),
+];
+
+impl Units {
+    pub const DEGREES_CELSIUS: Self = Self(62);
+    pub const DEGREES_FAHRENHEIT: Self = Self(64);
+    pub const NO_UNITS: Self = Self(95);
+    pub const PERCENT: Self = Self(98);
+
+    fn info(&self) -> Option<&'static UnitInfo> {
+        UNITS.iter().find(|info| info.number == self.0)
+    }
+
+    /// The standard name, e.g. `degrees-fahrenheit`.
+    pub fn name(&self) -> Option<&'static str> {
+        self.info().map(|info| info.name)
+    }
+
+    /// The usual symbol, e.g. `°F`. `None` for unknown units and units
+    /// without one, such as `no-units`.
+    pub fn symbol(&self) -> Option<&'static str> {
+        self.info().and_then(|info| info.symbol)
+    }
+
+    /// Convert `value` from these units to `to`, `None` when they don't
+    /// measure the same quantity or either is unknown.
+    pub fn convert(&self, value: f64, to: Units) -> Option<f64> {
+        if *self == to {
+            return Some(value);
+        }
+        let (from, to) = (self.info()?, to.info()?);
+        if from.quantity != to.quantity || from.quantity == Unique {
+            return None;
+        }
+        Some((value * from.factor + from.offset - to.offset) / to.factor)
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.info() {
+            Some(info) => f.write_str(info.symbol.unwrap_or(info.name)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Error parsing [`Units`] that aren't a number, a known name or a known
+/// symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownUnits(pub String);
+
+impl fmt::Display for UnknownUnits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown units `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownUnits {}
+
+impl FromStr for Units {
+    type Err = UnknownUnits;
+
+    /// Parse units from their number, their name, e.g.
+    /// `degrees-fahrenheit`, or their symbol, e.g. `°F`. A symbol shared by
+    /// several units, such as `°`, parses as the lowest numbered.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(number) = s.parse() {
+            return Ok(Self(number));
+        }
+        UNITS
+            .iter()
+            .find(|info| info.name.eq_ignore_ascii_case(s) || info.symbol == Some(s))
+            .map(|info| Self(info.number))
+            .ok_or_else(|| UnknownUnits(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELSIUS: Units = Units::DEGREES_CELSIUS;
+    const FAHRENHEIT: Units = Units::DEGREES_FAHRENHEIT;
+    const KELVIN: Units = Units(63);
+
+    fn assert_converts(value: f64, from: Units, to: Units, expected: f64) {
+        let converted = from.convert(value, to).unwrap();
+        assert!(
+            (converted - expected).abs() < 1e-9,
+            "{value} {from} is {converted} {to}, not {expected}"
+        );
+    }
+
+    #[test]
+    fn temperatures() {
+        assert_converts(21.5, CELSIUS, FAHRENHEIT, 70.7);
+        assert_converts(212.0, FAHRENHEIT, CELSIUS, 100.0);
+        assert_converts(-40.0, CELSIUS, FAHRENHEIT, -40.0);
+        assert_converts(0.0, CELSIUS, KELVIN, 273.15);
+        assert_converts(32.0, FAHRENHEIT, KELVIN, 273.15);
+        assert_converts(0.0, KELVIN, FAHRENHEIT, -459.67);
+    }
+
+    #[test]
+    fn other_quantities_dont_convert() {
+        assert_eq!(Units::DEGREES_CELSIUS.convert(20.0, Units(0)), None);
+        assert_eq!(Units::PERCENT.convert(50.0, Units::DEGREES_CELSIUS), None);
+        // A temperature difference isn't a temperature
+        assert_eq!(Units::DEGREES_CELSIUS.convert(20.0, Units(121)), None);
+        assert_eq!(Units::DEGREES_CELSIUS.convert(20.0, Units(9999)), None);
+    }
+
+    #[test]
+    fn unique_units_dont_convert() {
+        let power_factor = Units(15);
+        assert_eq!(power_factor.convert(0.9, Units::NO_UNITS), None);
+        assert_eq!(Units::NO_UNITS.convert(1.0, power_factor), None);
+        assert_eq!(power_factor.convert(0.9, power_factor), Some(0.9));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("64".parse(), Ok(Units::DEGREES_FAHRENHEIT));
+        assert_eq!("degrees-fahrenheit".parse(), Ok(Units::DEGREES_FAHRENHEIT));
+        assert_eq!("Degrees-Fahrenheit".parse(), Ok(Units::DEGREES_FAHRENHEIT));
+        assert_eq!("°F".parse(), Ok(Units::DEGREES_FAHRENHEIT));
+        assert_eq!("9999".parse(), Ok(Units(9999)));
+        assert_eq!(
+            "furlongs".parse::<Units>(),
+            Err(UnknownUnits("furlongs".to_string()))
+        );
+    }
+
+    #[test]
+    fn ambiguous_symbol() {
+        // degrees-phase and degrees-angular
+        assert_eq!(Units(14).symbol(), Units(90).symbol());
+        assert_eq!("°".parse(), Ok(Units(14)));
+        assert_eq!("degrees-angular".parse(), Ok(Units(90)));
+        assert_eq!(Units(14).convert(90.0, Units(90)), Some(90.0));
+    }
+}
@@ -0,0 +1,568 @@
+use std::fmt;
+
+use embedded_bacnet::application_protocol::primitives::data_value::BitString;
+
+use crate::{
+    apdu::{self, tag, Decoder, Encoder, TagClass, TagKind},
+    charset::EncodedString,
+    client::Client,
+    error::{DecodeError, Error},
+    ApplicationDataValue, Enumerated, NetworkIo, ObjectId, PropertyId,
+};
+
+/// Wildcard value for date and time fields ("any").
+pub const UNSPECIFIED: u8 = 255;
+
+/// A BACnet date. Fields set to [`UNSPECIFIED`] (year: 2155) match any value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    /// Day of week, 1 = Monday.
+    pub weekday: u8,
+}
+
+impl Date {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        match bytes {
+            [year, month, day, weekday] => Ok(Self {
+                year: 1900 + *year as u16,
+                month: *month,
+                day: *day,
+                weekday: *weekday,
+            }),
+            _ => Err(DecodeError("invalid date")),
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; 4] {
+        let year = self.year.saturating_sub(1900).min(UNSPECIFIED as u16) as u8;
+        [year, self.month, self.day, self.weekday]
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.year.saturating_sub(1900) >= UNSPECIFIED as u16 {
+            true => write!(f, "*")?,
+            false => write!(f, "{:04}", self.year)?,
+        }
+        for field in [self.month, self.day] {
+            match field {
+                UNSPECIFIED => write!(f, "-*")?,
+                field => write!(f, "-{field:02}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A BACnet time of day. Fields set to [`UNSPECIFIED`] match any value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub hundredths: u8,
+}
+
+impl Time {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        match bytes {
+            [hour, minute, second, hundredths] => Ok(Self {
+                hour: *hour,
+                minute: *minute,
+                second: *second,
+                hundredths: *hundredths,
+            }),
+            _ => Err(DecodeError("invalid time")),
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; 4] {
+        [self.hour, self.minute, self.second, self.hundredths]
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, field) in [self.hour, self.minute, self.second]
+            .into_iter()
+            .enumerate()
+        {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            match field {
+                UNSPECIFIED => write!(f, "*")?,
+                field => write!(f, "{field:02}")?,
+            }
+        }
+        match self.hundredths {
+            UNSPECIFIED | 0 => Ok(()),
+            hundredths => write!(f, ".{hundredths:02}"),
+        }
+    }
+}
+
+/// A BACnet date and time (BACnetDateTime).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DateTime {
+    pub date: Date,
+    pub time: Time,
+}
+
+impl DateTime {
+    pub fn new(date: Date, time: Time) -> Self {
+        Self { date, time }
+    }
+
+    pub(crate) fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let (_, date) = decoder.application(tag::DATE)?;
+        let (_, time) = decoder.application(tag::TIME)?;
+        Ok(Self {
+            date: Date::from_bytes(date)?,
+            time: Time::from_bytes(time)?,
+        })
+    }
+
+    pub(crate) fn encode(&self, encoder: &mut Encoder) {
+        encoder.date(self.date.to_bytes());
+        encoder.time(self.time.to_bytes());
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.date, self.time)
+    }
+}
+
+/// An owned BACnet property value.
+///
+/// A property holding a single application-tagged value decodes to that
+/// value; arrays and lists of several elements decode to [`Value::Array`],
+/// and [`Client::read_list`] reads them whatever their length. Data that
+/// isn't made of
+/// application-tagged values (context-tagged constructed types) is kept
+/// encoded in [`Value::Constructed`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "value", rename_all = "kebab-case")
+)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Real(f32),
+    Double(f64),
+    OctetString(Vec<u8>),
+    CharacterString(String),
+    BitString(Vec<bool>),
+    Enumerated(u32),
+    Date(Date),
+    Time(Time),
+    ObjectId(ObjectId),
+    Array(Vec<Value>),
+    Constructed(Vec<u8>),
+}
+
+impl Value {
+    /// The value as a float, for any numeric variant.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Unsigned(v) => Some(*v as f64),
+            Value::Signed(v) => Some(*v as f64),
+            Value::Real(v) => Some(*v as f64),
+            Value::Double(v) => Some(*v),
+            Value::Enumerated(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    /// The value as an unsigned integer, for unsigned and enumerated values.
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Unsigned(v) => u32::try_from(*v).ok(),
+            Value::Enumerated(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::CharacterString(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Decode a complete encoded property value.
+    pub(crate) fn decode_all(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut values = Self::decode_list(data)?;
+        match values.len() {
+            1 => Ok(values.remove(0)),
+            _ => Ok(Value::Array(values)),
+        }
+    }
+
+    /// Decode the elements of an encoded array or list, which a single
+    /// element doesn't tell apart from a scalar value.
+    pub(crate) fn decode_list(data: &[u8]) -> Result<Vec<Self>, DecodeError> {
+        let mut decoder = Decoder::new(data);
+        let mut values = Vec::new();
+        while !decoder.is_empty() {
+            values.push(Self::decode(&mut decoder)?);
+        }
+        Ok(values)
+    }
+
+    /// Decode the next element of an encoded value.
+    pub(crate) fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        if decoder.peek_tag()?.class == TagClass::Context {
+            let start = decoder.remaining();
+            decoder.skip()?;
+            let len = start.len() - decoder.remaining().len();
+            return Ok(Value::Constructed(start[..len].to_vec()));
+        }
+        let header = decoder.read_tag()?;
+        if header.number == tag::BOOLEAN {
+            return Ok(Value::Bool(header.kind == TagKind::Value(1)));
+        }
+        let bytes = decoder.bytes(header.len())?;
+        let value = match header.number {
+            tag::NULL => Value::Null,
+            tag::UNSIGNED => Value::Unsigned(apdu::decode_unsigned(bytes)),
+            tag::SIGNED => Value::Signed(apdu::decode_signed(bytes)),
+            tag::REAL => Value::Real(apdu::decode_real(bytes)?),
+            tag::DOUBLE => Value::Double(apdu::decode_double(bytes)?),
+            tag::OCTET_STRING => Value::OctetString(bytes.to_vec()),
+            tag::CHARACTER_STRING => {
+                Value::CharacterString(EncodedString::decode(bytes).text_lossy())
+            }
+            tag::BIT_STRING => Value::BitString(apdu::decode_bit_string(bytes)),
+            tag::ENUMERATED => Value::Enumerated(apdu::decode_unsigned(bytes) as u32),
+            tag::DATE => Value::Date(Date::from_bytes(bytes)?),
+            tag::TIME => Value::Time(Time::from_bytes(bytes)?),
+            tag::OBJECT_ID => Value::ObjectId(apdu::decode_object_id(bytes)?),
+            _ => return Err(DecodeError("reserved application tag")),
+        };
+        Ok(value)
+    }
+
+    /// Encode this value with application tags.
+    pub(crate) fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Value::Null => encoder.null(),
+            Value::Bool(v) => encoder.boolean(*v),
+            Value::Unsigned(v) => encoder.unsigned(*v),
+            Value::Signed(v) => encoder.signed(*v),
+            Value::Real(v) => encoder.real(*v),
+            Value::Double(v) => encoder.double(*v),
+            Value::OctetString(v) => encoder.octet_string(v),
+            Value::CharacterString(v) => encoder.character_string(v),
+            Value::BitString(v) => encoder.bit_string(v),
+            Value::Enumerated(v) => encoder.enumerated(*v),
+            Value::Date(v) => encoder.date(v.to_bytes()),
+            Value::Time(v) => encoder.time(v.to_bytes()),
+            Value::ObjectId(v) => encoder.object_id(*v),
+            Value::Array(values) => values.iter().for_each(|v| v.encode(encoder)),
+            Value::Constructed(v) => encoder.raw(v),
+        }
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder);
+        encoder.into_bytes()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(v) => write!(f, "{v}"),
+            Value::Unsigned(v) => write!(f, "{v}"),
+            Value::Signed(v) => write!(f, "{v}"),
+            Value::Real(v) => write!(f, "{v}"),
+            Value::Double(v) => write!(f, "{v}"),
+            Value::CharacterString(v) => write!(f, "{v}"),
+            Value::Enumerated(v) => write!(f, "{v}"),
+            Value::Date(v) => write!(f, "{v}"),
+            Value::Time(v) => write!(f, "{v}"),
+            Value::ObjectId(v) => write!(f, "{:?}:{}", v.object_type, v.id),
+            Value::BitString(bits) => {
+                let bits: String = bits.iter().map(|b| if *b { '1' } else { '0' }).collect();
+                write!(f, "{{{bits}}}")
+            }
+            Value::OctetString(bytes) | Value::Constructed(bytes) => {
+                bytes.iter().try_for_each(|b| write!(f, "{b:02x}"))
+            }
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Raw numeric value of an embedded-bacnet enumerated value.
+pub(crate) fn enumerated_to_u32(value: &Enumerated) -> Result<u32, DecodeError> {
+    #[allow(unreachable_patterns)]
+    match value {
+        Enumerated::Units(v) => Ok(v.clone() as u32),
+        Enumerated::Binary(v) => Ok(v.clone() as u32),
+        Enumerated::ObjectType(v) => Ok(*v as u32),
+        Enumerated::EventState(v) => Ok(v.clone() as u32),
+        Enumerated::NotifyType(v) => Ok(v.clone() as u32),
+        Enumerated::LoggingType(v) => Ok(v.clone() as u32),
+        Enumerated::Unknown(v) => Ok(*v),
+        _ => Err(DecodeError("enumerated value of an unknown kind")),
+    }
+}
+
+/// The bits of an embedded-bacnet bit string, for the kinds whose bits it
+/// keeps.
+fn bit_string_bits(value: &BitString<'_>) -> Option<Vec<bool>> {
+    #[allow(unreachable_patterns)]
+    match value {
+        BitString::StatusFlags(flags) => Some(vec![
+            flags.in_alarm,
+            flags.fault,
+            flags.overridden,
+            flags.out_of_service,
+        ]),
+        BitString::Custom(custom) => {
+            let mut bytes = vec![custom.unused_bits];
+            bytes.extend_from_slice(custom.bits);
+            Some(apdu::decode_bit_string(&bytes))
+        }
+        _ => None,
+    }
+}
+
+impl From<ApplicationDataValue<'_>> for Value {
+    /// Convert a value decoded by embedded-bacnet. Variants without an owned
+    /// equivalent (weekly schedules, date lists), and enumerated values and
+    /// bit strings of kinds whose raw value isn't kept, are converted through
+    /// their display form.
+    fn from(value: ApplicationDataValue<'_>) -> Self {
+        match value {
+            ApplicationDataValue::Null => Value::Null,
+            ApplicationDataValue::Boolean(v) => Value::Bool(v),
+            ApplicationDataValue::Real(v) => Value::Real(v),
+            ApplicationDataValue::Double(v) => Value::Double(v),
+            ApplicationDataValue::UnsignedInt(v) => Value::Unsigned(v as u64),
+            ApplicationDataValue::SignedInt(v) => Value::Signed(v as i64),
+            ApplicationDataValue::Enumerated(ref v) => match enumerated_to_u32(v) {
+                Ok(v) => Value::Enumerated(v),
+                Err(_) => Value::CharacterString(value.to_string()),
+            },
+            ApplicationDataValue::BitString(ref v) => match bit_string_bits(v) {
+                Some(bits) => Value::BitString(bits),
+                None => Value::CharacterString(value.to_string()),
+            },
+            ApplicationDataValue::OctetString(v) => Value::OctetString(v.to_vec()),
+            ApplicationDataValue::CharacterString(v) => Value::CharacterString(v.inner.to_string()),
+            ApplicationDataValue::ObjectId(v) => Value::ObjectId(v),
+            ApplicationDataValue::Date(v) => Value::Date(Date {
+                year: v.year,
+                month: v.month,
+                day: v.day,
+                weekday: v.wday,
+            }),
+            ApplicationDataValue::Time(v) => Value::Time(Time {
+                hour: v.hour,
+                minute: v.minute,
+                second: v.second,
+                hundredths: v.hundredths,
+            }),
+            ApplicationDataValue::WeeklySchedule(_) | ApplicationDataValue::DateList(_) => {
+                Value::CharacterString(value.to_string())
+            }
+        }
+    }
+}
+
+impl<T: NetworkIo + Clone> Client<T> {
+    /// Read a property as an owned [`Value`].
+    pub async fn read_value(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+    ) -> Result<Value, Error<T>> {
+        let data = self
+            .read_property_raw(object_id, property_id as u32, None)
+            .await?;
+        Ok(Value::decode_all(&data)?)
+    }
+
+    /// Read the elements of an array or list property, e.g. `object-list`,
+    /// including when it holds a single element.
+    pub async fn read_list(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+    ) -> Result<Vec<Value>, Error<T>> {
+        let data = self
+            .read_property_raw(object_id, property_id as u32, None)
+            .await?;
+        Ok(Value::decode_list(&data)?)
+    }
+
+    /// Read one element of an array property as an owned [`Value`].
+    pub async fn read_value_at(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+        array_index: u32,
+    ) -> Result<Value, Error<T>> {
+        let data = self
+            .read_property_raw(object_id, property_id as u32, Some(array_index))
+            .await?;
+        Ok(Value::decode_all(&data)?)
+    }
+
+    /// Write a [`Value`] to a property, optionally at a command priority.
+    pub async fn write_value(
+        &mut self,
+        object_id: ObjectId,
+        property_id: PropertyId,
+        value: &Value,
+        priority: Option<u8>,
+    ) -> Result<(), Error<T>> {
+        self.write_property_raw(
+            object_id,
+            property_id as u32,
+            None,
+            &value.to_bytes(),
+            priority,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_bacnet::application_protocol::primitives::data_value::{
+        CharacterString, CustomBitStream, Date as BacnetDate, Time as BacnetTime,
+    };
+
+    use super::*;
+    use crate::ObjectType;
+
+    #[test]
+    fn from_application_data_value() {
+        assert_eq!(Value::from(ApplicationDataValue::Null), Value::Null);
+        assert_eq!(
+            Value::from(ApplicationDataValue::Boolean(true)),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::Real(21.5)),
+            Value::Real(21.5)
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::Double(0.1)),
+            Value::Double(0.1)
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::UnsignedInt(u32::MAX)),
+            Value::Unsigned(u32::MAX as u64)
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::SignedInt(-40)),
+            Value::Signed(-40)
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::Enumerated(Enumerated::Unknown(3))),
+            Value::Enumerated(3)
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::BitString(BitString::Custom(
+                CustomBitStream {
+                    unused_bits: 4,
+                    bits: &[0b1010_0000],
+                }
+            ))),
+            Value::BitString(vec![true, false, true, false])
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::OctetString(&[0xde, 0xad])),
+            Value::OctetString(vec![0xde, 0xad])
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::CharacterString(CharacterString {
+                inner: "AHU-1",
+            })),
+            Value::CharacterString("AHU-1".to_string())
+        );
+        let object_id = ObjectId::new(ObjectType::ObjectAnalogValue, 7);
+        assert_eq!(
+            Value::from(ApplicationDataValue::ObjectId(object_id)),
+            Value::ObjectId(object_id)
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::Date(BacnetDate {
+                year: 2024,
+                month: 2,
+                day: 29,
+                wday: 4,
+            })),
+            Value::Date(Date {
+                year: 2024,
+                month: 2,
+                day: 29,
+                weekday: 4,
+            })
+        );
+        assert_eq!(
+            Value::from(ApplicationDataValue::Time(BacnetTime {
+                hour: 13,
+                minute: 30,
+                second: 5,
+                hundredths: 50,
+            })),
+            Value::Time(Time {
+                hour: 13,
+                minute: 30,
+                second: 5,
+                hundredths: 50,
+            })
+        );
+    }
+
+    #[test]
+    fn single_element_lists() {
+        let object_id = ObjectId::new(crate::ObjectType::ObjectFile, 1);
+        let data = Value::ObjectId(object_id).to_bytes();
+        assert_eq!(Value::decode_all(&data), Ok(Value::ObjectId(object_id)));
+        assert_eq!(
+            Value::decode_list(&data),
+            Ok(vec![Value::ObjectId(object_id)])
+        );
+        assert_eq!(Value::decode_list(&[]), Ok(Vec::new()));
+
+        let values = vec![Value::Unsigned(1), Value::Real(2.5)];
+        let data = Value::Array(values.clone()).to_bytes();
+        assert_eq!(Value::decode_list(&data), Ok(values.clone()));
+        assert_eq!(Value::decode_all(&data), Ok(Value::Array(values)));
+    }
+}
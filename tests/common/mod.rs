@@ -0,0 +1,104 @@
+//! Reference device harness for the integration tests.
+//!
+//! Each test gets its own container running `tests/reference-device`, mapped
+//! to a random host UDP port. Set `BACNET_TEST_DEVICE=host:port` to run
+//! against an already running device instead, and `BACNET_TEST_IMAGE` to use
+//! a prebuilt image.
+//!
+//! Tests writing to the device each use objects of their own and restore
+//! the values they change, so they can share a device.
+
+use std::{net::SocketAddr, time::Duration};
+
+use async_bacnet::Client;
+use tokio::{process::Command, sync::OnceCell, time::Instant};
+
+const DEFAULT_IMAGE: &str = "async-bacnet-reference-device";
+
+/// How long a new container gets to answer a Who-Is.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+static BUILD: OnceCell<()> = OnceCell::const_new();
+
+pub struct ReferenceDevice {
+    container: Option<String>,
+    pub addr: SocketAddr,
+}
+
+impl ReferenceDevice {
+    pub async fn start() -> Self {
+        if let Ok(addr) = std::env::var("BACNET_TEST_DEVICE") {
+            return Self {
+                container: None,
+                addr: addr.parse().expect("invalid BACNET_TEST_DEVICE"),
+            };
+        }
+
+        let image = match std::env::var("BACNET_TEST_IMAGE") {
+            Ok(image) => image,
+            Err(_) => {
+                BUILD
+                    .get_or_init(|| async {
+                        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/reference-device");
+                        docker(&["build", "-q", "-t", DEFAULT_IMAGE, dir]).await;
+                    })
+                    .await;
+                DEFAULT_IMAGE.to_string()
+            }
+        };
+        let container = docker(&["run", "-d", "--rm", "-p", "127.0.0.1::47808/udp", &image]).await;
+        let port = docker(&["port", &container, "47808/udp"]).await;
+        let addr = port
+            .lines()
+            .next()
+            .and_then(|line| line.parse().ok())
+            .expect("container port is not mapped");
+
+        let device = Self {
+            container: Some(container),
+            addr,
+        };
+        device.wait_ready().await;
+        device
+    }
+
+    /// Send Who-Is until the device answers, once BACpypes has bound its
+    /// socket.
+    async fn wait_ready(&self) {
+        let mut client = Client::new(self.addr).await.unwrap();
+        client.set_timeout(Duration::from_millis(500));
+        let end = Instant::now() + STARTUP_TIMEOUT;
+        while Instant::now() < end {
+            if let Ok(Some(_)) = client.who_is().await {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        panic!("reference device didn't answer Who-Is within {STARTUP_TIMEOUT:?}");
+    }
+}
+
+impl Drop for ReferenceDevice {
+    fn drop(&mut self) {
+        // Removed in the background: dropping can't wait for it.
+        if let Some(container) = &self.container {
+            let _ = Command::new("docker")
+                .args(["rm", "-f", container.as_str()])
+                .spawn();
+        }
+    }
+}
+
+async fn docker(args: &[&str]) -> String {
+    let output = Command::new("docker")
+        .args(args)
+        .output()
+        .await
+        .expect("failed to run docker");
+    assert!(
+        output.status.success(),
+        "docker {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
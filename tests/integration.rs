@@ -0,0 +1,247 @@
+//! Integration tests against the reference device in `tests/reference-device`.
+//!
+//! Run with `cargo test --features integration-test` (requires Docker).
+#![cfg(feature = "integration-test")]
+
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_bacnet::{Client, ObjectId, ObjectType, PacketDirection, PropertyId, Reliability, Value};
+use common::ReferenceDevice;
+
+const SIMPLE_ACK: u8 = 0x20;
+const CONFIRMED_COV_NOTIFICATION: u8 = 1;
+const READ_PROPERTY_MULTIPLE: u8 = 14;
+
+fn analog_value(instance: u32) -> ObjectId {
+    ObjectId::new(ObjectType::ObjectAnalogValue, instance)
+}
+
+/// The services of the confirmed requests the client sends, and the
+/// services it acknowledges with a Simple-ACK, as they go out.
+#[derive(Default)]
+struct Sent {
+    requests: Vec<u8>,
+    simple_acks: Vec<u8>,
+}
+
+fn observe_sent(client: &mut Client) -> Arc<Mutex<Sent>> {
+    let sent = Arc::new(Mutex::new(Sent::default()));
+    let observed = sent.clone();
+    client.set_packet_observer(move |direction, _, datagram| {
+        if direction != PacketDirection::Sent {
+            return;
+        }
+        // BVLL header and an NPDU without addresses: the APDU is at 6.
+        let mut sent = observed.lock().unwrap();
+        match datagram.get(6..) {
+            Some(&[pdu, _, _, service, ..]) if pdu >> 4 == 0 => sent.requests.push(service),
+            Some(&[SIMPLE_ACK, _, service]) => sent.simple_acks.push(service),
+            _ => {}
+        }
+    });
+    sent
+}
+
+#[tokio::test]
+async fn read_present_value() {
+    let device = ReferenceDevice::start().await;
+    let mut client = Client::new(device.addr).await.unwrap();
+
+    let value = client
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap();
+    assert_eq!(value, Value::Real(21.5));
+}
+
+#[tokio::test]
+async fn write_then_read_back() {
+    let device = ReferenceDevice::start().await;
+    let mut client = Client::new(device.addr).await.unwrap();
+    let object_id = analog_value(2);
+
+    let original = client
+        .read_value(object_id, PropertyId::PropPresentValue)
+        .await
+        .unwrap();
+    let written = Value::Real(original.as_f64().unwrap() as f32 + 1.0);
+    client
+        .write_value(object_id, PropertyId::PropPresentValue, &written, None)
+        .await
+        .unwrap();
+    let value = client
+        .read_value(object_id, PropertyId::PropPresentValue)
+        .await
+        .unwrap();
+    client
+        .write_value(object_id, PropertyId::PropPresentValue, &original, None)
+        .await
+        .unwrap();
+    assert_eq!(value, written);
+}
+
+#[tokio::test]
+async fn cov_notifications_are_acknowledged() {
+    let device = ReferenceDevice::start().await;
+    let mut client = Client::new(device.addr).await.unwrap();
+    let sent = observe_sent(&mut client);
+    let object_id = analog_value(3);
+
+    let original = client
+        .read_value(object_id, PropertyId::PropPresentValue)
+        .await
+        .unwrap();
+    let notified = Arc::new(Mutex::new(Vec::new()));
+    let received = notified.clone();
+    let process_id = client
+        .subscribe_cov(
+            object_id,
+            true,
+            Some(Duration::from_secs(60)),
+            move |notification| {
+                let present_value = notification
+                    .values
+                    .iter()
+                    .filter(|value| value.property_id == PropertyId::PropPresentValue as u32);
+                received
+                    .lock()
+                    .unwrap()
+                    .extend(present_value.map(|value| value.value.clone()));
+            },
+        )
+        .await
+        .unwrap();
+    // beyond the object's COV increment of 0.5
+    let changed = Value::Real(original.as_f64().unwrap() as f32 + 5.0);
+    client
+        .write_value(object_id, PropertyId::PropPresentValue, &changed, None)
+        .await
+        .unwrap();
+    client
+        .receive_notifications(Duration::from_secs(2))
+        .await
+        .unwrap();
+    client.unsubscribe_cov(process_id).await.unwrap();
+    client
+        .write_value(object_id, PropertyId::PropPresentValue, &original, None)
+        .await
+        .unwrap();
+
+    // the notification made on subscribing, then the one of the change
+    assert_eq!(*notified.lock().unwrap(), [original, changed]);
+    let sent = sent.lock().unwrap();
+    assert_eq!(
+        sent.simple_acks
+            .iter()
+            .filter(|service| **service == CONFIRMED_COV_NOTIFICATION)
+            .count(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn read_many_batches_into_read_property_multiple() {
+    let device = ReferenceDevice::start().await;
+    let mut client = Client::new(device.addr).await.unwrap();
+    let sent = observe_sent(&mut client);
+
+    let instances = 100..140;
+    let requests = instances
+        .clone()
+        .map(|instance| (analog_value(instance), PropertyId::PropPresentValue))
+        .collect();
+    let results = client.read_many(requests).await.unwrap();
+    for (instance, result) in instances.zip(results) {
+        assert_eq!(result.unwrap(), Value::Real(instance as f32));
+    }
+    let sent = sent.lock().unwrap();
+    assert_eq!(
+        sent.requests
+            .iter()
+            .filter(|service| **service == READ_PROPERTY_MULTIPLE)
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn unknown_object_is_a_service_error() {
+    let device = ReferenceDevice::start().await;
+    let mut client = Client::new(device.addr).await.unwrap();
+
+    let err = client
+        .read_value(analog_value(9999), PropertyId::PropPresentValue)
+        .await
+        .unwrap_err();
+    match err {
+        async_bacnet::Error::Service(err) => assert!(err.is_unknown_object()),
+        other => panic!("unexpected error: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn segmented_object_list() {
+    let device = ReferenceDevice::start().await;
+    let mut client = Client::new(device.addr).await.unwrap();
+
+    let value = client
+        .read_value(
+            ObjectId::new(ObjectType::ObjectDevice, 599),
+            PropertyId::PropObjectList,
+        )
+        .await
+        .unwrap();
+    match value {
+        Value::Array(objects) => assert!(objects.len() > 400),
+        other => panic!("unexpected object list: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn health_and_point_metadata() {
+    let device = ReferenceDevice::start().await;
+    let mut client = Client::new(device.addr).await.unwrap();
+
+    let health = client
+        .read_health(ObjectId::new(ObjectType::ObjectAnalogInput, 1))
+        .await
+        .unwrap();
+    assert_eq!(health.reliability, Some(Reliability::NoFaultDetected));
+    assert!(health.is_healthy());
+
+    let mut point = client.point(ObjectId::new(ObjectType::ObjectMultiStateValue, 1));
+    assert_eq!(point.name().await.unwrap(), "occupancy-mode");
+    assert_eq!(
+        point.state_text().await.unwrap(),
+        Some(vec![
+            "Unoccupied".to_string(),
+            "Occupied".to_string(),
+            "Standby".to_string()
+        ])
+    );
+}
+
+#[tokio::test]
+async fn multi_state_labels() {
+    let device = ReferenceDevice::start().await;
+    let mut client = Client::new(device.addr).await.unwrap();
+
+    let mut point = client.point(ObjectId::new(ObjectType::ObjectMultiStateValue, 1));
+    let original = point.read_state_label().await.unwrap();
+    let (label, expected) = match original.as_str() {
+        "Standby" => ("occupied", "Occupied"),
+        _ => ("standby", "Standby"),
+    };
+    point.write_state_label(label, None).await.unwrap();
+    let written = point.read_state_label().await.unwrap();
+    let unknown = point.write_state_label("Holiday", None).await;
+    point.write_state_label(&original, None).await.unwrap();
+
+    assert_eq!(written, expected);
+    assert!(matches!(unknown, Err(async_bacnet::Error::UnknownState(_))));
+}
@@ -0,0 +1,518 @@
+//! Tests driving `Client` through the in-memory `MockIo` and its
+//! `VirtualDevice`, without hardware.
+
+use std::time::Duration;
+
+use async_bacnet::{
+    testing::{MockIo, VirtualDevice},
+    Client, Date, Error, FileData, ObjectId, ObjectType, PropertyId, ReadPolicy, ScheduleEdit,
+    ServiceError, Value,
+};
+
+const READ_PROPERTY_MULTIPLE: u8 = 14;
+const WRITE_PROPERTY_MULTIPLE: u8 = 16;
+const REJECT_UNRECOGNIZED_SERVICE: u8 = 9;
+const ABORT_SEGMENTATION_NOT_SUPPORTED: u8 = 4;
+
+fn analog_value(instance: u32) -> ObjectId {
+    ObjectId::new(ObjectType::ObjectAnalogValue, instance)
+}
+
+fn device() -> VirtualDevice {
+    let mut device = VirtualDevice::new(1234);
+    device.add_object(
+        analog_value(1),
+        [
+            (PropertyId::PropPresentValue, Value::Real(21.5)),
+            (
+                PropertyId::PropObjectName,
+                Value::CharacterString("Zone temperature".to_string()),
+            ),
+        ],
+    );
+    device
+}
+
+/// A client on `device`, and a handle on the device to inspect it.
+fn connect(device: VirtualDevice, timeout: Duration) -> (Client<MockIo>, MockIo) {
+    let mut io = MockIo::new(device);
+    io.set_timeout(timeout);
+    (Client::from_io(io.clone()), io)
+}
+
+#[tokio::test]
+async fn read_then_write() {
+    let (mut client, io) = connect(device(), Duration::from_secs(1));
+
+    let value = client
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap();
+    assert_eq!(value, Value::Real(21.5));
+
+    client
+        .write_value(
+            analog_value(1),
+            PropertyId::PropPresentValue,
+            &Value::Real(19.0),
+            Some(8),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        io.device()
+            .property(analog_value(1), PropertyId::PropPresentValue),
+        Some(&Value::Real(19.0))
+    );
+}
+
+#[tokio::test]
+async fn array_elements() {
+    let (mut client, _io) = connect(device(), Duration::from_secs(1));
+    let device_id = ObjectId::new(ObjectType::ObjectDevice, 1234);
+
+    let len = client
+        .read_value_at(device_id, PropertyId::PropObjectList, 0)
+        .await
+        .unwrap();
+    assert_eq!(len, Value::Unsigned(2));
+    let first = client
+        .read_value_at(device_id, PropertyId::PropObjectList, 1)
+        .await
+        .unwrap();
+    assert_eq!(first, Value::ObjectId(device_id));
+    let second = client
+        .read_value_at(device_id, PropertyId::PropObjectList, 2)
+        .await
+        .unwrap();
+    assert_eq!(second, Value::ObjectId(analog_value(1)));
+
+    match client
+        .read_value_at(device_id, PropertyId::PropObjectList, 3)
+        .await
+    {
+        Err(Error::Service(err)) => assert_eq!(err.class, ServiceError::CLASS_PROPERTY),
+        result => panic!("unexpected result: {result:?}"),
+    }
+    assert_eq!(
+        client.read_object_list().await.unwrap(),
+        [device_id, analog_value(1)]
+    );
+}
+
+#[tokio::test]
+async fn unknown_object_and_property_are_service_errors() {
+    let (mut client, _io) = connect(device(), Duration::from_secs(1));
+
+    match client
+        .read_value(analog_value(9), PropertyId::PropPresentValue)
+        .await
+    {
+        Err(Error::Service(err)) => assert!(err.is_unknown_object()),
+        result => panic!("unexpected result: {result:?}"),
+    }
+    match client
+        .read_value(analog_value(1), PropertyId::PropUnits)
+        .await
+    {
+        Err(Error::Service(err)) => assert!(err.is_unknown_property()),
+        result => panic!("unexpected result: {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn scripted_service_error() {
+    let mut device = device();
+    let error = ServiceError {
+        class: ServiceError::CLASS_PROPERTY,
+        code: 40, // write-access-denied
+    };
+    device.fail_property(analog_value(1), PropertyId::PropPresentValue, error);
+    let (mut client, io) = connect(device, Duration::from_secs(1));
+
+    match client
+        .write_value(
+            analog_value(1),
+            PropertyId::PropPresentValue,
+            &Value::Real(0.0),
+            None,
+        )
+        .await
+    {
+        Err(Error::Service(err)) => assert_eq!(err, error),
+        result => panic!("unexpected result: {result:?}"),
+    }
+    assert_eq!(
+        io.device()
+            .property(analog_value(1), PropertyId::PropPresentValue),
+        Some(&Value::Real(21.5))
+    );
+}
+
+#[tokio::test]
+async fn read_many_batches_into_one_request() {
+    let (mut client, io) = connect(device(), Duration::from_secs(1));
+
+    let results = client
+        .read_many(vec![
+            (analog_value(1), PropertyId::PropPresentValue),
+            (analog_value(1), PropertyId::PropObjectName),
+            (analog_value(1), PropertyId::PropUnits),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(results[0].as_ref().unwrap(), &Value::Real(21.5));
+    assert_eq!(
+        results[1].as_ref().unwrap(),
+        &Value::CharacterString("Zone temperature".to_string())
+    );
+    assert!(matches!(&results[2], Err(Error::Service(err)) if err.is_unknown_property()));
+    // max-APDU, then one ReadPropertyMultiple
+    assert_eq!(io.device().requests(), 2);
+}
+
+#[tokio::test]
+async fn read_many_falls_back_when_rpm_is_rejected() {
+    let mut device = device();
+    device.reject_service(READ_PROPERTY_MULTIPLE, REJECT_UNRECOGNIZED_SERVICE);
+    let (mut client, io) = connect(device, Duration::from_secs(1));
+
+    let results = client
+        .read_many(vec![
+            (analog_value(1), PropertyId::PropPresentValue),
+            (analog_value(1), PropertyId::PropUnits),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(results[0].as_ref().unwrap(), &Value::Real(21.5));
+    assert!(matches!(&results[1], Err(Error::Service(err)) if err.is_unknown_property()));
+    // max-APDU, the rejected ReadPropertyMultiple, then one read per property
+    assert_eq!(io.device().requests(), 4);
+
+    // the rejection is remembered
+    client
+        .read_many(vec![(analog_value(1), PropertyId::PropPresentValue)])
+        .await
+        .unwrap();
+    assert_eq!(io.device().requests(), 5);
+}
+
+#[tokio::test]
+async fn read_properties_by_policy() {
+    let (mut client, _io) = connect(device(), Duration::from_secs(1));
+    let requests = vec![
+        (analog_value(1), PropertyId::PropUnits),
+        (analog_value(1), PropertyId::PropPresentValue),
+    ];
+
+    let results = client
+        .read_properties(requests.clone(), ReadPolicy::Collect)
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(
+        results.value(analog_value(1), PropertyId::PropPresentValue),
+        Some(&Value::Real(21.5))
+    );
+    assert_eq!(
+        results.value(analog_value(1), PropertyId::PropObjectName),
+        None
+    );
+    assert_eq!(results.errors().count(), 1);
+
+    match client.read_properties(requests, ReadPolicy::FailFast).await {
+        Err(Error::Property {
+            object_id,
+            property_id,
+            error,
+        }) => {
+            assert_eq!(object_id, analog_value(1));
+            assert_eq!(property_id as u32, PropertyId::PropUnits as u32);
+            assert!(error.is_unknown_property());
+        }
+        result => panic!("unexpected result: {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn loss_times_out() {
+    let mut device = device();
+    device.set_loss(1.0);
+    let (mut client, io) = connect(device, Duration::from_millis(50));
+
+    let err = client
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap_err();
+    assert!(err.is_timeout(), "unexpected error: {err:?}");
+    assert_eq!(io.device().requests(), 1);
+}
+
+#[tokio::test]
+async fn latency_beyond_the_timeout_times_out() {
+    let mut device = device();
+    device.set_latency(Duration::from_millis(200));
+    let (mut client, _io) = connect(device.clone(), Duration::from_millis(50));
+    let err = client
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap_err();
+    assert!(err.is_timeout(), "unexpected error: {err:?}");
+
+    device.set_latency(Duration::from_millis(20));
+    let (mut client, _io) = connect(device, Duration::from_secs(1));
+    let value = client
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap();
+    assert_eq!(value, Value::Real(21.5));
+}
+
+#[tokio::test]
+async fn client_timeouts_are_per_client() {
+    let mut device = device();
+    device.set_latency(Duration::from_millis(200));
+    let (mut client, io) = connect(device, Duration::from_millis(50));
+    let mut other = Client::from_io(io);
+    client.set_timeout(Duration::from_secs(1));
+
+    let err = client
+        .with_timeout(Duration::from_millis(50))
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap_err();
+    assert!(err.is_timeout(), "unexpected error: {err:?}");
+    let value = client
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap();
+    assert_eq!(value, Value::Real(21.5));
+
+    // the transport's timeout still applies to the other client
+    let err = other
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap_err();
+    assert!(err.is_timeout(), "unexpected error: {err:?}");
+}
+
+#[tokio::test]
+async fn response_over_max_apdu_is_aborted() {
+    let mut device = device();
+    device.set_max_apdu(50);
+    device.set_property(
+        analog_value(1),
+        PropertyId::PropObjectName,
+        Value::CharacterString("x".repeat(100)),
+    );
+    let (mut client, _io) = connect(device, Duration::from_secs(1));
+
+    match client
+        .read_value(analog_value(1), PropertyId::PropObjectName)
+        .await
+    {
+        Err(Error::Abort(reason)) => assert_eq!(reason, ABORT_SEGMENTATION_NOT_SUPPORTED),
+        result => panic!("unexpected result: {result:?}"),
+    }
+    // short responses still fit
+    let value = client
+        .read_value(analog_value(1), PropertyId::PropPresentValue)
+        .await
+        .unwrap();
+    assert_eq!(value, Value::Real(21.5));
+}
+
+#[tokio::test]
+async fn health_tolerates_only_an_unknown_reliability() {
+    let mut device = device();
+    device.add_object(
+        analog_value(2),
+        [
+            (
+                PropertyId::PropStatusFlags,
+                Value::BitString(vec![false; 4]),
+            ),
+            (PropertyId::PropOutOfService, Value::Bool(false)),
+        ],
+    );
+    let (mut client, _io) = connect(device.clone(), Duration::from_secs(1));
+    let health = client.read_health(analog_value(2)).await.unwrap();
+    assert_eq!(health.reliability, None);
+    assert!(!health.out_of_service);
+
+    let error = ServiceError {
+        class: ServiceError::CLASS_PROPERTY,
+        code: 27, // read-access-denied
+    };
+    device.fail_property(analog_value(2), PropertyId::PropReliability, error);
+    let (mut client, _io) = connect(device, Duration::from_secs(1));
+    match client.read_health(analog_value(2)).await {
+        Err(Error::Service(err)) => assert_eq!(err, error),
+        result => panic!("unexpected result: {result:?}"),
+    }
+}
+
+fn write_present_value(instance: u32, value: f32) -> ScheduleEdit {
+    ScheduleEdit::Write {
+        object_id: analog_value(instance),
+        property_id: PropertyId::PropPresentValue,
+        array_index: None,
+        value: Value::Real(value),
+    }
+}
+
+fn analog_values(count: u32) -> VirtualDevice {
+    let mut device = VirtualDevice::new(1234);
+    for instance in 1..=count {
+        device.add_object(
+            analog_value(instance),
+            [(PropertyId::PropPresentValue, Value::Real(0.0))],
+        );
+    }
+    device
+}
+
+#[tokio::test]
+async fn schedule_writes_are_sent_together() {
+    let (mut client, io) = connect(analog_values(3), Duration::from_secs(1));
+
+    let edits: Vec<_> = (1..=3)
+        .map(|instance| write_present_value(instance, instance as f32))
+        .collect();
+    let report = client.apply_schedule_edits(&edits).await.unwrap();
+    assert_eq!(report.requests, 1);
+    assert!(report.is_verified());
+    for instance in 1..=3 {
+        assert_eq!(
+            io.device()
+                .property(analog_value(instance), PropertyId::PropPresentValue),
+            Some(&Value::Real(instance as f32))
+        );
+    }
+}
+
+#[tokio::test]
+async fn schedule_writes_fall_back_when_wpm_is_rejected() {
+    let mut device = analog_values(3);
+    device.reject_service(WRITE_PROPERTY_MULTIPLE, REJECT_UNRECOGNIZED_SERVICE);
+    let (mut client, io) = connect(device, Duration::from_secs(1));
+
+    let edits: Vec<_> = (1..=3)
+        .map(|instance| write_present_value(instance, instance as f32))
+        .collect();
+    let report = client.apply_schedule_edits(&edits).await.unwrap();
+    // One WriteProperty per edit, the rejected request excluded
+    assert_eq!(report.requests, 3);
+    assert!(report.is_verified());
+    // max-APDU, the rejected WritePropertyMultiple, 3 writes, 3 read-backs
+    assert_eq!(io.device().requests(), 8);
+
+    // the rejection is remembered
+    let report = client.apply_schedule_edits(&edits[..2]).await.unwrap();
+    assert_eq!(report.requests, 2);
+    assert_eq!(io.device().requests(), 12);
+}
+
+#[tokio::test]
+async fn schedule_list_edits_are_split_at_max_apdu() {
+    let calendar = ObjectId::new(ObjectType::ObjectCalendar, 1);
+    let mut device = VirtualDevice::new(1234);
+    device.add_object(calendar, [(PropertyId::PropDateList, Value::Array(vec![]))]);
+    let (mut client, io) = connect(device, Duration::from_secs(1));
+    client.set_max_apdu(50);
+
+    let dates: Vec<_> = (1..=10)
+        .map(|day| {
+            Value::Date(Date {
+                year: 2025,
+                month: 12,
+                day,
+                weekday: 255,
+            })
+        })
+        .collect();
+    let add = |elements: &[Value]| ScheduleEdit::AddToList {
+        object_id: calendar,
+        property_id: PropertyId::PropDateList,
+        elements: elements.to_vec(),
+    };
+    let report = client
+        .apply_schedule_edits(&[add(&dates[..5]), add(&dates[5..])])
+        .await
+        .unwrap();
+    // The 10 dates of 5 bytes don't fit in the 46 bytes after the request
+    // header, with the 9 of the object, property and list tags
+    assert_eq!(report.requests, 2);
+    assert!(report.is_verified());
+    assert_eq!(
+        io.device().property(calendar, PropertyId::PropDateList),
+        Some(&Value::Array(dates))
+    );
+}
+
+#[tokio::test]
+async fn overwritten_schedule_edits_are_not_verified() {
+    let (mut client, io) = connect(analog_values(1), Duration::from_secs(1));
+
+    let edits = [write_present_value(1, 1.0), write_present_value(1, 2.0)];
+    let report = client.apply_schedule_edits(&edits).await.unwrap();
+    assert_eq!(report.requests, 1);
+    // The device holds 2.0, so the first edit would fail verification
+    assert!(report.is_verified());
+    // max-APDU, the WritePropertyMultiple, then one read-back
+    assert_eq!(io.device().requests(), 3);
+}
+
+#[tokio::test]
+async fn stream_files_are_transferred_in_chunks() {
+    let file = ObjectId::new(ObjectType::ObjectFile, 1);
+    let mut device = VirtualDevice::new(1234);
+    device
+        .set_max_apdu(128)
+        .set_file(file, FileData::Stream(Vec::new()));
+    let (mut client, io) = connect(device, Duration::from_secs(1));
+
+    let contents: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    client
+        .write_file(file, &FileData::Stream(contents.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        io.device().file(file),
+        Some(&FileData::Stream(contents.clone()))
+    );
+    // max-APDU, then 108 octets per write
+    assert_eq!(io.device().requests(), 1 + 10);
+
+    let read = client.read_file(file).await.unwrap();
+    assert_eq!(read, FileData::Stream(contents));
+    // The access method, then 108 octets per read
+    assert_eq!(io.device().requests(), 11 + 1 + 10);
+}
+
+#[tokio::test]
+async fn record_files_are_transferred_in_chunks() {
+    let file = ObjectId::new(ObjectType::ObjectFile, 2);
+    let mut device = VirtualDevice::new(1234);
+    device
+        .set_max_apdu(128)
+        .set_file(file, FileData::Records(Vec::new()));
+    let (mut client, io) = connect(device, Duration::from_secs(1));
+
+    let records: Vec<Vec<u8>> = (0..20).map(|i| vec![i as u8; 10]).collect();
+    client
+        .write_file(file, &FileData::Records(records.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        io.device().file(file),
+        Some(&FileData::Records(records.clone()))
+    );
+    // max-APDU, then the 7 records fitting in 108 octets per write
+    assert_eq!(io.device().requests(), 1 + 3);
+
+    let read = client.read_file(file).await.unwrap();
+    assert_eq!(read, FileData::Records(records));
+    // The access method, then 8 records per read
+    assert_eq!(io.device().requests(), 4 + 1 + 3);
+}
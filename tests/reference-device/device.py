@@ -0,0 +1,102 @@
+"""Reference BACnet device used by the integration tests.
+
+Serves a small, known set of objects with BACpypes3. The object list is long
+enough to force a segmented response when read as a whole.
+"""
+
+import asyncio
+
+from bacpypes3.argparse import SimpleArgumentParser
+from bacpypes3.app import Application
+from bacpypes3.local.analog import AnalogInputObject, AnalogValueObject
+from bacpypes3.local.binary import BinaryValueObject
+from bacpypes3.local.multistate import MultiStateValueObject
+
+FILLER_OBJECTS = 400
+
+
+async def main() -> None:
+    args = SimpleArgumentParser().parse_args()
+    app = Application.from_args(args)
+
+    app.add_object(
+        AnalogInputObject(
+            objectIdentifier=("analogInput", 1),
+            objectName="outside-air-temperature",
+            presentValue=12.5,
+            units="degreesCelsius",
+            statusFlags=[0, 0, 0, 0],
+            outOfService=False,
+            reliability="noFaultDetected",
+        )
+    )
+    app.add_object(
+        AnalogValueObject(
+            objectIdentifier=("analogValue", 1),
+            objectName="zone-setpoint",
+            presentValue=21.5,
+            units="degreesCelsius",
+            statusFlags=[0, 0, 0, 0],
+            outOfService=False,
+            covIncrement=0.5,
+        )
+    )
+    # Written by the tests, one object per test so they can run at once.
+    app.add_object(
+        AnalogValueObject(
+            objectIdentifier=("analogValue", 2),
+            objectName="write-scratch",
+            presentValue=0.0,
+            units="noUnits",
+            statusFlags=[0, 0, 0, 0],
+            outOfService=False,
+        )
+    )
+    app.add_object(
+        AnalogValueObject(
+            objectIdentifier=("analogValue", 3),
+            objectName="cov-scratch",
+            presentValue=0.0,
+            units="noUnits",
+            statusFlags=[0, 0, 0, 0],
+            outOfService=False,
+            covIncrement=0.5,
+        )
+    )
+    app.add_object(
+        BinaryValueObject(
+            objectIdentifier=("binaryValue", 1),
+            objectName="fan-enable",
+            presentValue="inactive",
+            statusFlags=[0, 0, 0, 0],
+            outOfService=False,
+        )
+    )
+    app.add_object(
+        MultiStateValueObject(
+            objectIdentifier=("multiStateValue", 1),
+            objectName="occupancy-mode",
+            presentValue=2,
+            numberOfStates=3,
+            stateText=["Unoccupied", "Occupied", "Standby"],
+            statusFlags=[0, 0, 0, 0],
+            outOfService=False,
+        )
+    )
+    for instance in range(100, 100 + FILLER_OBJECTS):
+        app.add_object(
+            AnalogValueObject(
+                objectIdentifier=("analogValue", instance),
+                objectName=f"filler-{instance}",
+                presentValue=float(instance),
+                units="noUnits",
+                statusFlags=[0, 0, 0, 0],
+                outOfService=False,
+            )
+        )
+
+    await asyncio.Future()
+
+
+if __name__ == "__main__":
+    asyncio.run(main())